@@ -7,7 +7,7 @@ class App {
 
     setEvents(jsonText: string) {
         const json = JSON.parse(jsonText);
-        this.events = json;
+        this.events = json.events;
         this.displayEvents(this.events);
 
         const eventTypes: string[] = [];
@@ -0,0 +1,521 @@
+//! Triage archives: ZIP collections (e.g. made by KAPE or Velociraptor) that contain
+//! a copy of the Diagnosis folder.
+//!
+//! The Diagnosis files are extracted into a temporary directory (as read-only files),
+//! which is removed once the archive is dropped.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+use tracing::{info, warn};
+use zip::ZipArchive;
+
+use crate::diagnosis::{ArtifactKind, DiagnosisFolder};
+
+/// Path of the Diagnosis folder on the system volume.
+const DIAGNOSIS_FOLDER_PATH: [&str; 3] = ["ProgramData", "Microsoft", "Diagnosis"];
+
+/// A file extracted from the archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveMember {
+    /// Name of the member in the archive, as stored.
+    pub member_path: String,
+
+    /// Where the member was extracted to (removed after the analysis).
+    pub extracted_path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// A Diagnosis folder found in the archive.
+///
+/// Archives may hold several, e.g. one per collected volume.
+#[derive(Debug)]
+pub struct ExtractedDiagnosisFolder {
+    /// Decoded path of the volume in the archive, before `ProgramData` (e.g. `C` for KAPE,
+    /// `uploads/auto/C:` for Velociraptor).
+    pub volume_prefix: String,
+    pub folder: DiagnosisFolder,
+    pub members: Vec<ArchiveMember>,
+}
+
+/// The Diagnosis folders of an archive, extracted into a temporary directory.
+#[derive(Debug)]
+pub struct ExtractedArchive {
+    pub archive_path: PathBuf,
+    pub diagnosis_folders: Vec<ExtractedDiagnosisFolder>,
+
+    // Kept last, so the folders are dropped before their files are removed.
+    temporary_directory: TempDir,
+}
+
+impl ExtractedArchive {
+    /// Extracts all Diagnosis folders of the ZIP archive at `archive_path`.
+    ///
+    /// Fails if the archive cannot be read or has no Diagnosis folder. Members that
+    /// cannot be extracted (e.g. encrypted ones) are logged and skipped.
+    pub fn extract(archive_path: &Path) -> Result<Self> {
+        let file = File::open(archive_path)
+            .into_diagnostic()
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to open archive {}.",
+                    archive_path.display()
+                )
+            })?;
+        let mut archive = ZipArchive::new(file).into_diagnostic().wrap_err_with(|| {
+            format!(
+                "Failed to read archive {}.",
+                archive_path.display()
+            )
+        })?;
+
+        // Members of each Diagnosis folder, by the prefix of their volume.
+        let mut members_by_prefix: BTreeMap<String, Vec<(usize, Vec<String>)>> = BTreeMap::new();
+        for member_index in 0..archive.len() {
+            let Some(member_name) = archive.name_for_index(member_index) else {
+                continue;
+            };
+            if member_name.ends_with('/') {
+                continue;
+            }
+
+            if let Some((volume_prefix, relative_path)) = split_diagnosis_path(member_name) {
+                members_by_prefix
+                    .entry(volume_prefix)
+                    .or_default()
+                    .push((member_index, relative_path));
+            }
+        }
+
+        if members_by_prefix.is_empty() {
+            return Err(miette!(
+                "No ProgramData\\Microsoft\\Diagnosis folder found in archive {}.",
+                archive_path.display()
+            ));
+        }
+
+        let temporary_directory = tempfile::Builder::new()
+            .prefix("winspy-")
+            .tempdir()
+            .into_diagnostic()
+            .wrap_err("Failed to create a temporary directory for the archive.")?;
+
+        let mut diagnosis_folders = Vec::with_capacity(members_by_prefix.len());
+
+        for (folder_index, (volume_prefix, members)) in members_by_prefix.into_iter().enumerate() {
+            let root = temporary_directory
+                .path()
+                .join(format!("volume-{folder_index}"))
+                .join("Diagnosis");
+
+            let mut extracted_members = Vec::with_capacity(members.len());
+            for (member_index, relative_path) in members {
+                match extract_member(&mut archive, member_index, &root, &relative_path) {
+                    Ok(member) => extracted_members.push(member),
+                    Err(error) => warn!(
+                        "Skipping member {} of archive {}: {:?}",
+                        relative_path.join("/"),
+                        archive_path.display(),
+                        error
+                    ),
+                }
+            }
+
+            info!(
+                "Extracted {} files of the Diagnosis folder of volume {} in {}.",
+                extracted_members.len(),
+                volume_prefix,
+                archive_path.display()
+            );
+
+            fs::create_dir_all(&root).into_diagnostic()?;
+            diagnosis_folders.push(ExtractedDiagnosisFolder {
+                volume_prefix,
+                folder: DiagnosisFolder::discover(&root)?,
+                members: extracted_members,
+            });
+        }
+
+        Ok(Self {
+            archive_path: archive_path.to_path_buf(),
+            diagnosis_folders,
+            temporary_directory,
+        })
+    }
+
+    /// Paths of all valid extracted artifacts of the given kind.
+    pub fn valid_artifact_paths(&self, kind: ArtifactKind) -> Vec<PathBuf> {
+        self.diagnosis_folders
+            .iter()
+            .flat_map(|diagnosis_folder| diagnosis_folder.folder.valid_artifact_paths(kind))
+            .collect()
+    }
+
+    /// Describes where an extracted database (and its `-wal` and `-shm` files) came from.
+    pub fn source_of(&self, extracted_path: &Path) -> Option<ArchiveSourceMetadata> {
+        self.diagnosis_folders.iter().find_map(|diagnosis_folder| {
+            let find_member = |suffix: &str| {
+                let mut path = extracted_path.as_os_str().to_owned();
+                path.push(suffix);
+
+                diagnosis_folder
+                    .members
+                    .iter()
+                    .find(|member| member.extracted_path.as_os_str() == path)
+                    .cloned()
+            };
+
+            Some(ArchiveSourceMetadata {
+                archive_path: self.archive_path.clone(),
+                volume_prefix: diagnosis_folder.volume_prefix.clone(),
+                database: find_member("")?,
+                wal: find_member("-wal"),
+                shm: find_member("-shm"),
+            })
+        })
+    }
+}
+
+impl Drop for ExtractedArchive {
+    fn drop(&mut self) {
+        // Read-only files cannot be deleted on Windows, so they are made writable again.
+        #[cfg(windows)]
+        for member in self
+            .diagnosis_folders
+            .iter()
+            .flat_map(|diagnosis_folder| &diagnosis_folder.members)
+        {
+            if let Ok(metadata) = fs::metadata(&member.extracted_path) {
+                let mut permissions = metadata.permissions();
+                #[allow(clippy::permissions_set_readonly_false)]
+                permissions.set_readonly(false);
+                let _ = fs::set_permissions(&member.extracted_path, permissions);
+            }
+        }
+
+        info!(
+            "Removing files extracted from {} in {}.",
+            self.archive_path.display(),
+            self.temporary_directory.path().display()
+        );
+    }
+}
+
+/// Where in an archive a database was read from.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveSourceMetadata {
+    pub archive_path: PathBuf,
+    pub volume_prefix: String,
+    pub database: ArchiveMember,
+    pub wal: Option<ArchiveMember>,
+    pub shm: Option<ArchiveMember>,
+}
+
+/// Splits the name of an archive member into the prefix of its volume and its path
+/// within the Diagnosis folder, if it is in one.
+///
+/// Components are URL-decoded first (Velociraptor stores `C:` as `C%3A`), and the
+/// Diagnosis folder is matched regardless of case.
+fn split_diagnosis_path(member_name: &str) -> Option<(String, Vec<String>)> {
+    let components = member_name
+        .split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+        .map(percent_decode)
+        .collect::<Vec<_>>();
+
+    let folder_start = components
+        .windows(DIAGNOSIS_FOLDER_PATH.len())
+        .position(|window| {
+            window
+                .iter()
+                .zip(DIAGNOSIS_FOLDER_PATH)
+                .all(|(component, expected)| component.eq_ignore_ascii_case(expected))
+        })?;
+
+    let relative_path = components[folder_start + DIAGNOSIS_FOLDER_PATH.len()..].to_vec();
+    if relative_path.is_empty() {
+        return None;
+    }
+
+    Some((
+        components[..folder_start].join("/"),
+        relative_path,
+    ))
+}
+
+/// Decodes `%XX` escapes. Invalid escapes are kept as they are.
+fn percent_decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut position = 0;
+    while position < bytes.len() {
+        let escaped = (bytes[position] == b'%')
+            .then(|| bytes.get(position + 1..position + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                position += 3;
+            }
+            None => {
+                decoded.push(bytes[position]);
+                position += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Extracts a member into `root`, as a read-only file, hashing it on the way.
+fn extract_member(
+    archive: &mut ZipArchive<File>,
+    member_index: usize,
+    root: &Path,
+    relative_path: &[String],
+) -> Result<ArchiveMember> {
+    // Decoded components may contain anything, so only plain names are accepted.
+    let is_plain_name = |component: &String| {
+        !component.is_empty()
+            && component != "."
+            && component != ".."
+            && !component.contains(['/', '\\', ':', '\0'])
+    };
+    if !relative_path.iter().all(is_plain_name) {
+        return Err(miette!("Unsafe member path."));
+    }
+
+    let mut member = archive.by_index(member_index).into_diagnostic()?;
+    let member_path = member.name().to_string();
+
+    let extracted_path = relative_path
+        .iter()
+        .fold(root.to_path_buf(), |path, component| {
+            path.join(component)
+        });
+    if let Some(parent) = extracted_path.parent() {
+        fs::create_dir_all(parent).into_diagnostic()?;
+    }
+
+    let mut extracted_file = File::create_new(&extracted_path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create {}.", extracted_path.display()))?;
+
+    let mut sha256 = Sha256::new();
+    let mut size = 0u64;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let bytes_read = match member.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => bytes_read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => {
+                return Err(error)
+                    .into_diagnostic()
+                    .wrap_err("Failed to decompress member.")
+            }
+        };
+
+        sha256.update(&buffer[..bytes_read]);
+        extracted_file
+            .write_all(&buffer[..bytes_read])
+            .into_diagnostic()?;
+        size += bytes_read as u64;
+    }
+    drop(extracted_file);
+
+    let mut permissions = fs::metadata(&extracted_path)
+        .into_diagnostic()?
+        .permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&extracted_path, permissions).into_diagnostic()?;
+
+    Ok(ArchiveMember {
+        member_path,
+        extracted_path,
+        size,
+        sha256: format!("{:x}", sha256.finalize()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    /// Writes a ZIP archive with the given members (in order) and returns its path.
+    fn write_archive(directory: &Path, members: &[(&str, &[u8])]) -> PathBuf {
+        let archive_path = directory.join("collection.zip");
+        let mut writer = ZipWriter::new(File::create(&archive_path).unwrap());
+        for (member_name, content) in members {
+            writer
+                .start_file(*member_name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap();
+
+        archive_path
+    }
+
+    fn sqlite_file(content: &[u8]) -> Vec<u8> {
+        let mut bytes = b"SQLite format 3\0".to_vec();
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("C%3A"), "C:");
+        assert_eq!(percent_decode("a%2fb%20c"), "a/b c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%zz%41"), "%zzA");
+    }
+
+    #[test]
+    fn splits_diagnosis_paths() {
+        let split = |member_name| split_diagnosis_path(member_name);
+        let relative_path = |components: &[&str]| {
+            components
+                .iter()
+                .map(|component| component.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // KAPE
+        assert_eq!(
+            split("C/ProgramData/Microsoft/Diagnosis/EventTranscript/EventTranscript.db"),
+            Some((
+                "C".to_string(),
+                relative_path(&["EventTranscript", "EventTranscript.db"])
+            ))
+        );
+        // Velociraptor
+        assert_eq!(
+            split("uploads/auto/C%3A/ProgramData/Microsoft/Diagnosis/osver.txt"),
+            Some((
+                "uploads/auto/C:".to_string(),
+                relative_path(&["osver.txt"])
+            ))
+        );
+        assert_eq!(
+            split("programdata\\MICROSOFT\\diagnosis\\parse.dat"),
+            Some((String::new(), relative_path(&["parse.dat"])))
+        );
+
+        assert_eq!(split("C/ProgramData/Microsoft/Diagnosis/"), None);
+        assert_eq!(
+            split("C/ProgramData/Microsoft/Windows/osver.txt"),
+            None
+        );
+    }
+
+    #[test]
+    fn extracts_diagnosis_folders() {
+        let directory = tempfile::tempdir().unwrap();
+        let database = sqlite_file(b"events");
+        let archive_path = write_archive(
+            directory.path(),
+            &[
+                ("C/ProgramData/Microsoft/Diagnosis/", b""),
+                (
+                    "C/ProgramData/Microsoft/Diagnosis/EventTranscript/EventTranscript.db",
+                    &database,
+                ),
+                (
+                    "C/ProgramData/Microsoft/Diagnosis/EventTranscript/EventTranscript.db-wal",
+                    b"wal",
+                ),
+                (
+                    "C/ProgramData/Microsoft/Diagnosis/..%2F..%2Fescaped.txt",
+                    b"escaped",
+                ),
+                (
+                    "D%3A/ProgramData/Microsoft/Diagnosis/osver.txt",
+                    b"10.0.19045",
+                ),
+                ("C/Windows/System32/config/SOFTWARE", b"unrelated"),
+            ],
+        );
+
+        let archive = ExtractedArchive::extract(&archive_path).unwrap();
+        let temporary_directory = archive.temporary_directory.path().to_path_buf();
+
+        let volume_prefixes = archive
+            .diagnosis_folders
+            .iter()
+            .map(|diagnosis_folder| diagnosis_folder.volume_prefix.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(volume_prefixes, ["C", "D:"]);
+
+        // The escaping member is skipped, the others are extracted under their folder.
+        let members = &archive.diagnosis_folders[0].members;
+        assert_eq!(members.len(), 2);
+        assert!(!temporary_directory.join("escaped.txt").exists());
+        assert!(members
+            .iter()
+            .all(|member| member.extracted_path.starts_with(&temporary_directory)));
+
+        let database_paths = archive.valid_artifact_paths(ArtifactKind::EventTranscript);
+        assert_eq!(database_paths.len(), 1);
+        assert_eq!(fs::read(&database_paths[0]).unwrap(), database);
+        assert!(fs::metadata(&database_paths[0])
+            .unwrap()
+            .permissions()
+            .readonly());
+        assert_eq!(
+            archive.valid_artifact_paths(ArtifactKind::OsVersion).len(),
+            1
+        );
+
+        let source = archive.source_of(&database_paths[0]).unwrap();
+        assert_eq!(source.volume_prefix, "C");
+        assert_eq!(
+            source.database.member_path,
+            "C/ProgramData/Microsoft/Diagnosis/EventTranscript/EventTranscript.db"
+        );
+        assert_eq!(source.database.size, database.len() as u64);
+        assert_eq!(
+            source.database.sha256,
+            format!("{:x}", Sha256::digest(&database))
+        );
+        assert_eq!(source.wal.unwrap().size, 3);
+        assert!(source.shm.is_none());
+
+        drop(archive);
+        assert!(!temporary_directory.exists());
+    }
+
+    #[test]
+    fn rejects_archives_without_diagnosis_folder() {
+        let directory = tempfile::tempdir().unwrap();
+        let archive_path = write_archive(
+            directory.path(),
+            &[("C/ProgramData/Microsoft/osver.txt", b"10.0.19045")],
+        );
+
+        let error = ExtractedArchive::extract(&archive_path).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("No ProgramData\\Microsoft\\Diagnosis folder found"));
+
+        fs::write(&archive_path, b"not an archive").unwrap();
+        let error = ExtractedArchive::extract(&archive_path).unwrap_err();
+        assert!(error.to_string().starts_with("Failed to read archive"));
+    }
+}
@@ -0,0 +1,456 @@
+//! Reconciliation of the two clocks of an event: the `timestamp` column of the database
+//! and the `time` field of the payload.
+//!
+//! Both normally agree within seconds. A lasting change in their difference suggests
+//! the system clock was changed between the events. The events are compared as they
+//! are read, per database and device, without keeping them.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{detectors::DetectedEvent, models::persisted_event::PersistedEvent};
+
+/// Default smallest change of the skew that is reported as a jump, in seconds.
+pub const DEFAULT_CLOCK_JUMP_THRESHOLD_SECONDS: i64 = 60;
+
+/// Clock a detected event's times are taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineClock {
+    /// The `timestamp` column of the database.
+    DatabaseTimestamp,
+
+    /// The `time` field of the payload.
+    PayloadTime,
+}
+
+/// Both clocks of the source event of a detected event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClockSkewAnnotation {
+    /// Clock the times of the detected event are taken from.
+    pub timeline_clock: TimelineClock,
+    pub payload_time: DateTime<Utc>,
+
+    /// `payload_time` minus the database timestamp.
+    pub skew_milliseconds: i64,
+}
+
+impl ClockSkewAnnotation {
+    /// Compares the clocks of `source_event`; `None` if its payload has no time.
+    pub fn new(source_event: &PersistedEvent, detected_event: &DetectedEvent) -> Option<Self> {
+        let payload_time = source_event.envelope().time?;
+
+        Some(Self {
+            timeline_clock: detected_event.timeline_clock(),
+            payload_time,
+            skew_milliseconds: (payload_time - *source_event.timestamp()).num_milliseconds(),
+        })
+    }
+}
+
+/// Skew of a single persisted event.
+#[derive(Debug, Clone)]
+struct ClockSkewSample {
+    timestamp: DateTime<Utc>,
+    event_name: String,
+    skew_milliseconds: i64,
+}
+
+/// Events whose skew is compared with each other: those of one device, read from one
+/// database in the order the database returns them. Recovered events are emitted after
+/// all rows of their database, so they form a series of their own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClockSkewSeriesKey {
+    source: PathBuf,
+    device_id: String,
+    recovered: bool,
+}
+
+/// The last two samples of a series, enough to recognize a jump when the next one arrives.
+#[derive(Debug, Default)]
+struct ClockSkewSeries {
+    previous_samples: Vec<ClockSkewSample>,
+}
+
+/// Relative width of the buckets of [`SkewHistogram`]: percentiles are accurate to 1%.
+const HISTOGRAM_BUCKET_GROWTH: f64 = 1.01;
+
+/// Histogram of the skew with logarithmically growing buckets, so that its size
+/// does not depend on the number of events (at most a few thousand buckets
+/// cover the whole range of `i64`).
+#[derive(Debug, Default)]
+struct SkewHistogram {
+    counts_by_bucket: BTreeMap<i32, u64>,
+    number_of_samples: u64,
+    sum: i128,
+    minimum: i64,
+    maximum: i64,
+}
+
+impl SkewHistogram {
+    /// Buckets are ordered the same way as the skews they contain.
+    fn bucket_of(skew_milliseconds: i64) -> i32 {
+        let magnitude =
+            (skew_milliseconds.unsigned_abs() as f64 + 1.0).log(HISTOGRAM_BUCKET_GROWTH) as i32;
+
+        if skew_milliseconds < 0 {
+            -magnitude - 1
+        } else {
+            magnitude
+        }
+    }
+
+    /// Middle of the range of skews in `bucket`.
+    fn bucket_value(bucket: i32) -> i64 {
+        let magnitude = if bucket < 0 { -bucket - 1 } else { bucket };
+        let lower = HISTOGRAM_BUCKET_GROWTH.powi(magnitude) - 1.0;
+        let upper = HISTOGRAM_BUCKET_GROWTH.powi(magnitude + 1) - 1.0;
+        let value = ((lower + upper) / 2.0).round() as i64;
+
+        if bucket < 0 {
+            -value
+        } else {
+            value
+        }
+    }
+
+    fn add(&mut self, skew_milliseconds: i64) {
+        if self.number_of_samples == 0 {
+            self.minimum = skew_milliseconds;
+            self.maximum = skew_milliseconds;
+        } else {
+            self.minimum = self.minimum.min(skew_milliseconds);
+            self.maximum = self.maximum.max(skew_milliseconds);
+        }
+
+        *self
+            .counts_by_bucket
+            .entry(Self::bucket_of(skew_milliseconds))
+            .or_default() += 1;
+        self.number_of_samples += 1;
+        self.sum += i128::from(skew_milliseconds);
+    }
+
+    /// Skew below which `percent` of the samples are; `number_of_samples` must not be 0.
+    fn percentile(&self, percent: u64) -> i64 {
+        let rank = (self.number_of_samples - 1) * percent / 100;
+        let mut number_of_samples_below = 0;
+
+        for (&bucket, &count) in &self.counts_by_bucket {
+            number_of_samples_below += count;
+            if number_of_samples_below > rank {
+                return Self::bucket_value(bucket).clamp(self.minimum, self.maximum);
+            }
+        }
+
+        self.maximum
+    }
+
+    fn distribution(&self) -> Option<ClockSkewDistribution> {
+        if self.number_of_samples == 0 {
+            return None;
+        }
+
+        Some(ClockSkewDistribution {
+            minimum: self.minimum,
+            maximum: self.maximum,
+            mean: self.sum as f64 / self.number_of_samples as f64,
+            median: self.percentile(50),
+            percentile_5: self.percentile(5),
+            percentile_95: self.percentile(95),
+        })
+    }
+}
+
+/// Compares the clocks of every persisted event as it is read.
+///
+/// Only the distribution of the skew and the last two events of every series are kept,
+/// so memory does not grow with the number of events.
+#[derive(Debug)]
+pub struct ClockSkewObserver {
+    jump_threshold: TimeDelta,
+    series: HashMap<ClockSkewSeriesKey, ClockSkewSeries>,
+    histogram: SkewHistogram,
+    jumps: Vec<ClockJump>,
+    number_of_events_without_payload_time: u64,
+}
+
+impl ClockSkewObserver {
+    /// Creates an observer that reports changes of the skew larger than `jump_threshold`.
+    pub fn new(jump_threshold: TimeDelta) -> Self {
+        Self {
+            jump_threshold,
+            series: HashMap::new(),
+            histogram: SkewHistogram::default(),
+            jumps: Vec::new(),
+            number_of_events_without_payload_time: 0,
+        }
+    }
+
+    /// Adds an event read from the database at `source`.
+    ///
+    /// A change only counts as a jump if the next event of the series keeps the new skew,
+    /// so that single events with an odd payload time are not reported.
+    pub fn observe(&mut self, event: &PersistedEvent, source: &Path) {
+        let Some(payload_time) = event.envelope().time else {
+            self.number_of_events_without_payload_time += 1;
+            return;
+        };
+
+        let sample = ClockSkewSample {
+            timestamp: *event.timestamp(),
+            event_name: event.event_name().to_string(),
+            skew_milliseconds: (payload_time - *event.timestamp()).num_milliseconds(),
+        };
+        self.histogram.add(sample.skew_milliseconds);
+
+        let key = ClockSkewSeriesKey {
+            source: source.to_path_buf(),
+            device_id: event.device_id().to_string(),
+            recovered: event.provenance().is_recovered(),
+        };
+        let series = self.series.entry(key).or_default();
+
+        let threshold_milliseconds = self.jump_threshold.num_milliseconds();
+        let is_jump = |from: &ClockSkewSample, to: &ClockSkewSample| {
+            (to.skew_milliseconds - from.skew_milliseconds).abs() > threshold_milliseconds
+        };
+
+        if let [first, second] = series.previous_samples.as_slice() {
+            if is_jump(first, second) && !is_jump(second, &sample) {
+                self.jumps.push(ClockJump {
+                    source: source.to_path_buf(),
+                    device_id: event.device_id().to_string(),
+                    timestamp: second.timestamp,
+                    event_name: second.event_name.clone(),
+                    previous_skew_milliseconds: first.skew_milliseconds,
+                    skew_milliseconds: second.skew_milliseconds,
+                    change_milliseconds: second.skew_milliseconds - first.skew_milliseconds,
+                });
+            }
+
+            series.previous_samples.remove(0);
+        }
+        series.previous_samples.push(sample);
+    }
+
+    pub fn into_report(mut self) -> ClockSkewReport {
+        self.jumps.sort_by_key(|jump| jump.timestamp);
+
+        ClockSkewReport {
+            number_of_events_compared: self.histogram.number_of_samples,
+            number_of_events_without_payload_time: self.number_of_events_without_payload_time,
+            distribution: self.histogram.distribution(),
+            jump_threshold_seconds: self.jump_threshold.num_seconds(),
+            number_of_series: self.series.len(),
+            jumps: self.jumps,
+        }
+    }
+}
+
+/// Distribution of the skew over all events, in milliseconds.
+///
+/// Minimum, maximum and mean are exact; the median and percentiles are accurate to 1%.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockSkewDistribution {
+    pub minimum: i64,
+    pub maximum: i64,
+    pub mean: f64,
+    pub median: i64,
+
+    /// 5th and 95th percentiles.
+    pub percentile_5: i64,
+    pub percentile_95: i64,
+}
+
+/// A lasting change of the skew between two consecutive events of a series.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockJump {
+    /// Database the events were read from.
+    pub source: PathBuf,
+    pub device_id: String,
+
+    /// Database timestamp of the first event after the jump.
+    pub timestamp: DateTime<Utc>,
+    pub event_name: String,
+    pub previous_skew_milliseconds: i64,
+    pub skew_milliseconds: i64,
+    pub change_milliseconds: i64,
+}
+
+/// How the two clocks of the events relate.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockSkewReport {
+    pub number_of_events_compared: u64,
+    pub number_of_events_without_payload_time: u64,
+    pub distribution: Option<ClockSkewDistribution>,
+    pub jump_threshold_seconds: i64,
+
+    /// Number of series (database, device and whether recovered) jumps were looked for in.
+    pub number_of_series: usize,
+    pub jumps: Vec<ClockJump>,
+}
+
+impl ClockSkewReport {
+    pub fn log_summary(&self) {
+        if let Some(distribution) = &self.distribution {
+            info!(
+                "Payload time minus database timestamp over {} events: median {} ms, \
+                from {} ms to {} ms.",
+                self.number_of_events_compared,
+                distribution.median,
+                distribution.minimum,
+                distribution.maximum
+            );
+        }
+
+        for jump in &self.jumps {
+            warn!(
+                "Clock skew changed by {} ms at {} ({}, device {} in {}): \
+                the system clock may have been changed.",
+                jump.change_milliseconds,
+                jump.timestamp,
+                jump.event_name,
+                jump.device_id,
+                jump.source.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    /// An event logged `minute` minutes into the day whose payload time is `skew_seconds` ahead.
+    fn event(device_id: &str, minute: i64, skew_seconds: i64) -> PersistedEvent {
+        let timestamp =
+            Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap() + TimeDelta::minutes(minute);
+        let payload_time = timestamp + TimeDelta::seconds(skew_seconds);
+
+        PersistedEvent::for_tests(
+            device_id,
+            "Microsoft.Windows.Test",
+            timestamp,
+            json!({ "time": payload_time.to_rfc3339() }),
+        )
+    }
+
+    fn report(events: &[(&str, &str, i64, i64)]) -> ClockSkewReport {
+        let mut observer = ClockSkewObserver::new(TimeDelta::seconds(60));
+        for &(source, device_id, minute, skew_seconds) in events {
+            observer.observe(
+                &event(device_id, minute, skew_seconds),
+                Path::new(source),
+            );
+        }
+        observer.into_report()
+    }
+
+    #[test]
+    fn reports_lasting_jumps_only() {
+        let report = report(&[
+            ("a.db", "d", 0, 1),
+            ("a.db", "d", 1, 1),
+            // A single odd event is not a jump.
+            ("a.db", "d", 2, 500),
+            ("a.db", "d", 3, 1),
+            // The clock is set back by an hour, and stays there.
+            ("a.db", "d", 4, 3601),
+            ("a.db", "d", 5, 3601),
+        ]);
+
+        let jumps = report
+            .jumps
+            .iter()
+            .map(|jump| {
+                (
+                    jump.change_milliseconds,
+                    jump.timestamp.format("%H:%M").to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(jumps, vec![(3_600_000, "00:04".to_string())]);
+        assert_eq!(report.number_of_series, 1);
+    }
+
+    #[test]
+    fn compares_events_within_their_series() {
+        // Two devices with different, but constant skews, read from two databases.
+        let report = report(&[
+            ("a.db", "d1", 0, 1),
+            ("a.db", "d2", 1, 3601),
+            ("a.db", "d1", 2, 1),
+            ("a.db", "d2", 3, 3601),
+            ("b.db", "d2", 0, 3601),
+            ("a.db", "d1", 4, 1),
+            ("b.db", "d2", 1, 3601),
+        ]);
+
+        assert!(report.jumps.is_empty());
+        assert_eq!(report.number_of_series, 3);
+        assert_eq!(report.number_of_events_compared, 7);
+    }
+
+    #[test]
+    fn summarizes_distribution_in_bounded_buckets() {
+        assert!(report(&[]).distribution.is_none());
+
+        let mut observer = ClockSkewObserver::new(TimeDelta::seconds(60));
+        for minute in 0..10_000 {
+            observer.observe(
+                &event("d", minute, minute - 5_000),
+                Path::new("a.db"),
+            );
+        }
+        assert!(observer.histogram.counts_by_bucket.len() < 2_000);
+
+        let distribution = observer.into_report().distribution.unwrap();
+        assert_eq!(distribution.minimum, -5_000_000);
+        assert_eq!(distribution.maximum, 4_999_000);
+        assert_eq!(distribution.mean, -500.0);
+
+        let is_close = |value: i64, expected: i64| {
+            (value - expected).abs() as f64 <= expected.abs() as f64 * 0.01 + 1.0
+        };
+        assert!(
+            is_close(distribution.median, -1_000),
+            "{distribution:?}"
+        );
+        assert!(
+            is_close(distribution.percentile_5, -4_501_000),
+            "{distribution:?}"
+        );
+        assert!(
+            is_close(distribution.percentile_95, 4_499_000),
+            "{distribution:?}"
+        );
+    }
+
+    #[test]
+    fn orders_buckets_like_skews() {
+        let skews = [i64::MIN, -1_000_000, -2, -1, 0, 1, 2, 1_000_000, i64::MAX];
+        let buckets = skews.map(SkewHistogram::bucket_of);
+
+        assert!(
+            buckets.windows(2).all(|pair| pair[0] < pair[1]),
+            "{buckets:?}"
+        );
+        for skew in skews {
+            let value = SkewHistogram::bucket_value(SkewHistogram::bucket_of(skew));
+            assert!(
+                (value as f64 - skew as f64).abs() <= skew.unsigned_abs() as f64 * 0.01 + 1.0,
+                "{skew} -> {value}"
+            );
+        }
+    }
+}
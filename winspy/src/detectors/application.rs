@@ -5,7 +5,7 @@ use chrono::{
 use serde::{Deserialize, Serialize};
 
 use super::{DetectedEvent, EventDetector, EventTranscriptReadOnlyView, ProcessedEvent};
-use crate::models::persisted_event::{PersistedEvent, PersistedEventPayload};
+use crate::models::persisted_event::PersistedEvent;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ApplicationClosedInner {
@@ -64,6 +64,28 @@ const APP_INTERACTIVITY_SUMMARY_EVENT_NAME: &str = "Win32kTraceLogging.AppIntera
 const NANOS_PER_MILLISECOND: u32 = 1_000_000;
 
 impl EventDetector for ApplicationEventDetector {
+    fn name(&self) -> &'static str {
+        "application"
+    }
+
+    fn description(&self) -> &'static str {
+        "Applications that were closed, with how long they were focused and used"
+    }
+
+    fn version(&self) -> &'static str {
+        "0.1.0"
+    }
+
+    fn source_event_names(&self) -> Vec<&'static str> {
+        vec![APP_INTERACTIVITY_SUMMARY_EVENT_NAME]
+    }
+
+    fn fork(&self) -> Option<Box<dyn EventDetector>> {
+        Some(Box::new(Self::new()))
+    }
+
+    // Payload fields are extracted with explicit `let ... else` chains.
+    #[allow(clippy::question_mark)]
     fn process_event(
         &mut self,
         event: &PersistedEvent,
@@ -73,21 +95,13 @@ impl EventDetector for ApplicationEventDetector {
             return None;
         }
 
-        let PersistedEventPayload::Parsed { payload } = event.payload() else {
-            return None;
-        };
-
-        let Some(payload) = payload.as_object() else {
-            return None;
-        };
+        let envelope = event.envelope();
 
-        let Some(event_time) = payload.get("time").and_then(|field| field.as_str()) else {
+        let Some(event_time) = envelope.time else {
             return None;
         };
 
-        let Some(data_table) = payload.get("data").and_then(|field| field.as_object()) else {
-            return None;
-        };
+        let data_table = &envelope.data;
 
         // Extract relevant fields from the `data` table.
 
@@ -110,7 +124,8 @@ impl EventDetector for ApplicationEventDetector {
                     return None;
                 };
 
-                let Some(alternative_executable_name) = raw_app_version.split('!').last() else {
+                let Some(alternative_executable_name) = raw_app_version.split('!').next_back()
+                else {
                     return None;
                 };
 
@@ -130,12 +145,6 @@ impl EventDetector for ApplicationEventDetector {
         };
 
         let (opened_at, closed_at) = {
-            let Ok(event_time) = DateTime::parse_from_rfc3339(event_time) else {
-                return None;
-            };
-
-            let event_time_utc = event_time.to_utc();
-
             let Some(since_first_interactivity_ms) = data_table
                 .get("SinceFirstInteractivityMS")
                 .and_then(|field| field.as_i64())
@@ -156,12 +165,12 @@ impl EventDetector for ApplicationEventDetector {
                 return None;
             };
 
-            let Some(opened_at) = event_time_utc.checked_sub_signed(since_first_interactivity_delta)
+            let Some(opened_at) = event_time.checked_sub_signed(since_first_interactivity_delta)
             else {
                 return None;
             };
 
-            (opened_at, event_time_utc)
+            (opened_at, event_time)
         };
 
         let focus_duration_in_seconds = {
@@ -276,7 +285,7 @@ impl EventDetector for ApplicationEventDetector {
         };
 
         Some(vec![ProcessedEvent::new_with_random_id(
-            event.timestamp().to_owned(),
+            event,
             ApplicationEvent::application_closed(ApplicationClosedInner {
                 executable_name,
                 executable_sha1_hash,
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 use super::{DetectedEvent, EventDetector, EventTranscriptReadOnlyView, ProcessedEvent};
-use crate::models::persisted_event::{PersistedEvent, PersistedEventPayload};
+use crate::models::persisted_event::PersistedEvent;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum BatteryEventType {
@@ -41,6 +41,27 @@ const BATTERY_CHANGE_EVENT_NAME: &str =
     "Microsoft.Windows.Kernel.Power.BatteryChargePercentageChange";
 
 impl EventDetector for BatteryEventDetector {
+    fn name(&self) -> &'static str {
+        "battery"
+    }
+
+    fn description(&self) -> &'static str {
+        "Changes of the remaining battery charge"
+    }
+
+    fn version(&self) -> &'static str {
+        "0.1.0"
+    }
+
+    fn source_event_names(&self) -> Vec<&'static str> {
+        vec![BATTERY_CHANGE_EVENT_NAME]
+    }
+
+    fn fork(&self) -> Option<Box<dyn EventDetector>> {
+        Some(Box::new(Self::new()))
+    }
+
+    #[allow(clippy::question_mark)]
     fn process_event(
         &mut self,
         event: &PersistedEvent,
@@ -50,19 +71,9 @@ impl EventDetector for BatteryEventDetector {
             return None;
         }
 
-        let PersistedEventPayload::Parsed { payload } = event.payload() else {
-            return None;
-        };
-
-        let Some(payload) = payload.as_object() else {
-            return None;
-        };
-
-        let Some(data_field) = payload.get("data").and_then(|field| field.as_object()) else {
-            return None;
-        };
-
-        let Some(remaining_percentage) = data_field
+        let Some(remaining_percentage) = event
+            .envelope()
+            .data
             .get("RemainingPercentage")
             .and_then(|field| field.as_i64())
         else {
@@ -74,7 +85,7 @@ impl EventDetector for BatteryEventDetector {
         };
 
         Some(vec![ProcessedEvent::new_with_random_id(
-            event.timestamp().to_owned(),
+            event,
             BatteryEvent::battery_percentage_change(battery_percentage),
         )])
     }
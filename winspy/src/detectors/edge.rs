@@ -1,10 +1,12 @@
+use std::collections::HashSet;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+use super::{DetectedEvent, EventDetector, EventTranscriptReadOnlyView, ProcessedEvent};
 use crate::models::persisted_event::PersistedEvent;
 
-use super::EventDetector;
-
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct TabEvent {
     opened_at: Option<DateTime<Utc>>,
@@ -19,6 +21,18 @@ pub struct EdgeInstance {
     session_guid: String,
 }
 
+impl EdgeInstance {
+    /// An instance that was seen being opened, but not (yet) closed.
+    pub fn opened(session_guid: String, opened_at: DateTime<Utc>) -> Self {
+        Self {
+            opened_at: Some(opened_at),
+            closed_at: None,
+            tabs: Vec::new(),
+            session_guid,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct EdgeDefaultSearchEngineChange {
     changed_at: DateTime<Utc>,
@@ -48,6 +62,158 @@ pub enum EdgeEvent {
     HomePage(EdgeHomePage),
 }
 
+impl From<EdgeEvent> for DetectedEvent {
+    fn from(value: EdgeEvent) -> Self {
+        Self::EdgeEvent(value)
+    }
+}
+
+/// Detects Edge sessions from the configuration Edge reports when it starts.
+///
+/// A session is reported once, when its first event is seen, so the detector
+/// has to see all events in order.
 pub struct EdgeEventDetector {
-    instances: Vec<EdgeInstance>,
+    known_sessions: HashSet<String>,
+}
+
+impl EdgeEventDetector {
+    pub fn new() -> Self {
+        Self {
+            known_sessions: HashSet::new(),
+        }
+    }
+}
+
+/// Full name of the event as stored, including the Aria tenant prefix (which is also
+/// how downloaded settings refer to it).
+const EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME: &str =
+    "Aria.f4a7d46e472049dfba756e11bdbbc08f.Microsoft.WebBrowser.SystemInfo.Config";
+
+impl EventDetector for EdgeEventDetector {
+    fn name(&self) -> &'static str {
+        "edge"
+    }
+
+    fn description(&self) -> &'static str {
+        "Edge sessions, from the configuration Edge reports when it starts"
+    }
+
+    fn version(&self) -> &'static str {
+        "0.1.0"
+    }
+
+    fn source_event_names(&self) -> Vec<&'static str> {
+        vec![EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME]
+    }
+
+    fn process_event(
+        &mut self,
+        event: &PersistedEvent,
+        _context: &EventTranscriptReadOnlyView,
+    ) -> Option<Vec<ProcessedEvent>> {
+        if event.event_name() != EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME {
+            return None;
+        }
+
+        // The session ID is a number in some versions of Edge and a string in others.
+        let session_guid = match event.envelope().data.get("session_id")? {
+            Value::String(session_id) => session_id.clone(),
+            Value::Number(session_id) => session_id.to_string(),
+            _ => return None,
+        };
+
+        if !self.known_sessions.insert(session_guid.clone()) {
+            return None;
+        }
+
+        Some(vec![ProcessedEvent::new_with_random_id(
+            event,
+            EdgeEvent::EdgeInstance(EdgeInstance::opened(
+                session_guid,
+                *event.timestamp(),
+            )),
+        )])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use serde_json::json;
+
+    use super::*;
+
+    fn session_guids(events: &[PersistedEvent]) -> Vec<String> {
+        let (tags, tag_locales, producers, categories) = (
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let context = EventTranscriptReadOnlyView {
+            tags: &tags,
+            tag_locales: &tag_locales,
+            producers: &producers,
+            categories: &categories,
+        };
+
+        let mut detector = EdgeEventDetector::new();
+        events
+            .iter()
+            .filter_map(|event| detector.process_event(event, &context))
+            .flatten()
+            .map(
+                |processed_event| match processed_event.detected_event {
+                    DetectedEvent::EdgeEvent(EdgeEvent::EdgeInstance(instance)) => {
+                        instance.session_guid
+                    }
+                    detected_event => panic!("unexpected detection: {detected_event:?}"),
+                },
+            )
+            .collect()
+    }
+
+    #[test]
+    fn reports_each_session_once() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+        let event = |seconds: i64, event_name: &str, session_id: Value| {
+            PersistedEvent::for_tests(
+                "d",
+                event_name,
+                start + TimeDelta::seconds(seconds),
+                json!({ "data": { "session_id": session_id } }),
+            )
+        };
+
+        let events = [
+            event(0, EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME, json!(42)),
+            event(1, EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME, json!(42)),
+            event(
+                2,
+                EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME,
+                json!("7b0e"),
+            ),
+            // Without the tenant prefix, or of another provider.
+            event(
+                3,
+                "Microsoft.WebBrowser.SystemInfo.Config",
+                json!(1),
+            ),
+            event(
+                4,
+                "Aria.f4a7d46e472049dfba756e11bdbbc08f.Microsoft.WebBrowser.SystemInfo.Config.Extra",
+                json!(2),
+            ),
+            event(5, EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME, json!(null)),
+            event(
+                6,
+                EDGE_SYSTEM_INFO_CONFIG_EVENT_NAME,
+                json!("7b0e"),
+            ),
+        ];
+
+        assert_eq!(session_guids(&events), ["42", "7b0e"]);
+    }
 }
@@ -1,117 +1,507 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    path::{Path, PathBuf},
+    pin::pin,
+};
 
-use chrono::prelude::{DateTime, Utc};
-use miette::{Context, Result};
+use chrono::{
+    prelude::{DateTime, Utc},
+    TimeDelta,
+};
+use futures::{stream::TryChunksError, TryStreamExt};
+use miette::{miette, Context, Result};
 use serde::{Deserialize, Serialize};
+use tracing::info;
 use uuid::Uuid;
 
 use self::{
     application::{ApplicationEvent, ApplicationEventDetector},
     battery::{BatteryEvent, BatteryEventDetector},
-    edge::EdgeEvent,
+    edge::{EdgeEvent, EdgeEventDetector},
+    pipeline::{DetectorPipeline, CHUNK_SIZE},
+    seen_events::SeenEvents,
     usb::{USBEvent, USBEventDetector},
 };
 use crate::{
+    clock_skew::{ClockSkewAnnotation, ClockSkewObserver, ClockSkewReport, TimelineClock},
+    evidence_summary::{EvidenceSummary, EvidenceSummaryCollector},
     models::{
         category::{Category, CategoryId},
-        persisted_event::PersistedEvent,
+        envelope::{MalformedEnvelope, MalformedEnvelopeCollector},
+        error::EventReaderError,
+        filetime::FileTime,
+        persisted_event::{LoggingBinary, PersistedEvent},
         producer::{Producer, ProducerId},
-        tag_description::{TagDescription, TagDescriptionId},
+        provenance::EventProvenance,
+        tag_description::{
+            TagDescription, TagDescriptionId, TagDescriptionKey, TagLocales, DEFAULT_TAG_LOCALE,
+        },
     },
-    reader::EventTranscriptReader,
+    os_version::{ObservedOsVersion, OsVersionObserver},
+    reader::EventSourceReader,
+    user::{user_of, UserObserver, UserReport},
 };
 
 mod application;
 mod battery;
 mod edge;
+mod pipeline;
+mod seen_events;
 mod usb;
 
 pub struct EventTranscriptProcessor {
-    events: Vec<PersistedEvent>,
-    tags: HashMap<TagDescriptionId, TagDescription>,
+    readers: Vec<EventSourceReader>,
+    tags: HashMap<TagDescriptionKey, TagDescription>,
+
+    /// Locale each tag is described in: the preferred one if possible.
+    tag_locales: HashMap<TagDescriptionId, TagDescriptionKey>,
+    preferred_locale: String,
     producers: HashMap<ProducerId, Producer>,
     categories: HashMap<CategoryId, Category>,
 }
 
-impl EventTranscriptProcessor {
-    pub async fn new_from_event_transcript_reader(
-        mut reader: EventTranscriptReader,
-    ) -> Result<Self> {
-        let events = reader
-            .load_all_events()
-            .await
-            .wrap_err("Failed to load all persisted events.")?;
+/// Orders the locales of a tag: the preferred one, then the default one,
+/// then all others alphabetically.
+fn locale_rank<'a>(key: &'a TagDescriptionKey, preferred_locale: &str) -> (u8, &'a str) {
+    let priority = if key.locale().eq_ignore_ascii_case(preferred_locale) {
+        0
+    } else if key.locale().eq_ignore_ascii_case(DEFAULT_TAG_LOCALE) {
+        1
+    } else {
+        2
+    };
+
+    (priority, key.locale())
+}
 
-        let tags = reader
-            .load_all_tags()
-            .await
-            .wrap_err("Failed to load all tags.")?;
+/// Chooses the locale each tag is described in, by [`locale_rank`].
+fn choose_tag_locales(
+    tags: &HashMap<TagDescriptionKey, TagDescription>,
+    preferred_locale: &str,
+) -> HashMap<TagDescriptionId, TagDescriptionKey> {
+    let mut tag_locales: HashMap<TagDescriptionId, TagDescriptionKey> = HashMap::new();
+    for tag in tags.values() {
+        let candidate = tag.key();
+        let is_better = tag_locales.get(&tag.id()).is_none_or(|current| {
+            locale_rank(&candidate, preferred_locale) < locale_rank(current, preferred_locale)
+        });
+
+        if is_better {
+            tag_locales.insert(tag.id(), candidate);
+        }
+    }
 
-        let producers = reader
-            .load_all_producers()
-            .await
-            .wrap_err("Failed to load all producers.")?;
+    tag_locales
+}
 
-        let categories = reader
-            .load_all_categories()
-            .await
-            .wrap_err("Failed to load all categories.")?;
+/// Which databases contain a persisted event, and what was detected in it.
+struct PersistedEventSources {
+    /// Indices of the readers whose database contains the event.
+    reader_indices: Vec<usize>,
 
-        let mut tags_map = HashMap::with_capacity(tags.len());
-        for tag in tags {
-            tags_map.insert(tag.id(), tag);
-        }
+    /// Indices of the events detected in it, within the aggregated events.
+    processed_event_indices: Range<usize>,
+}
+
+/// Events detected so far.
+#[derive(Default)]
+struct AggregatedEvents {
+    events: Vec<ProcessedEvent>,
+    user_observer: UserObserver,
+}
 
-        let mut producers_map = HashMap::with_capacity(producers.len());
-        for producer in producers {
-            producers_map.insert(producer.id(), producer);
+impl AggregatedEvents {
+    /// Adds the events detected in a chunk of persisted events (all read from
+    /// `source_path`), in the order of the chunk.
+    ///
+    /// Returns the indices of the events detected in each persisted event.
+    fn add_chunk(
+        &mut self,
+        chunk: &[PersistedEvent],
+        detected_events: Vec<Vec<ProcessedEvent>>,
+        context: &EventTranscriptReadOnlyView,
+        source_path: &Path,
+    ) -> Vec<Range<usize>> {
+        let mut processed_event_indices = Vec::with_capacity(chunk.len());
+
+        for (event, mut processed_events) in chunk.iter().zip(detected_events) {
+            for processed_event in &mut processed_events {
+                processed_event.resolve_source_event(event, context);
+                processed_event.sources = vec![source_path.to_path_buf()];
+            }
+            self.user_observer.observe(event, &processed_events);
+
+            let first_processed_event_index = self.events.len();
+            self.events.extend(processed_events);
+            processed_event_indices.push(first_processed_event_index..self.events.len());
         }
 
-        let mut categories_map = HashMap::with_capacity(categories.len());
-        for category in categories {
-            categories_map.insert(category.id(), category);
+        processed_event_indices
+    }
+}
+
+/// Runs the detectors over a chunk of persisted events read by one reader and empties it.
+///
+/// If `seen_events` is given, the keys of the events are stored there, so that
+/// later databases can refer to them.
+async fn process_chunk(
+    chunk: &mut Vec<PersistedEvent>,
+    pipeline: &mut DetectorPipeline,
+    aggregated_events: &mut AggregatedEvents,
+    context: &EventTranscriptReadOnlyView<'_>,
+    reader_index: usize,
+    source_path: &Path,
+    seen_events: Option<&SeenEvents>,
+) -> Result<()> {
+    let detected_events = tokio::task::block_in_place(|| pipeline.process_chunk(chunk, context));
+    let processed_event_indices =
+        aggregated_events.add_chunk(chunk, detected_events, context, source_path);
+
+    if let Some(seen_events) = seen_events {
+        let keys = chunk.iter().map(|event| event.key().digest());
+        seen_events
+            .insert_all(reader_index, keys.zip(processed_event_indices))
+            .await
+            .wrap_err("Failed to store the keys of processed events.")?;
+    }
+
+    chunk.clear();
+    Ok(())
+}
+
+impl EventTranscriptProcessor {
+    /// Creates a processor over one or more databases (transcripts and event stores).
+    ///
+    /// Tags, producers and categories of all transcripts are merged;
+    /// if an ID appears in several of them, the first transcript wins.
+    ///
+    /// Tags are described in `preferred_locale` where the transcripts allow it,
+    /// otherwise in [`DEFAULT_TAG_LOCALE`] or, failing that, any locale.
+    pub async fn new_from_readers(
+        readers: Vec<EventSourceReader>,
+        preferred_locale: &str,
+    ) -> Result<Self> {
+        let mut tags_map = HashMap::new();
+        let mut producers_map = HashMap::new();
+        let mut categories_map = HashMap::new();
+
+        for reader in &readers {
+            let EventSourceReader::Transcript(reader) = reader else {
+                continue;
+            };
+
+            let tags = reader
+                .load_all_tags()
+                .await
+                .wrap_err("Failed to load all tags.")?;
+
+            let producers = reader
+                .load_all_producers()
+                .await
+                .wrap_err("Failed to load all producers.")?;
+
+            let categories = reader
+                .load_all_categories()
+                .await
+                .wrap_err("Failed to load all categories.")?;
+
+            for tag in tags {
+                tags_map.entry(tag.key()).or_insert(tag);
+            }
+
+            for producer in producers {
+                producers_map.entry(producer.id()).or_insert(producer);
+            }
+
+            for category in categories {
+                categories_map.entry(category.id()).or_insert(category);
+            }
         }
 
         Ok(Self {
-            events,
+            readers,
+            tag_locales: choose_tag_locales(&tags_map, preferred_locale),
             tags: tags_map,
+            preferred_locale: preferred_locale.to_string(),
             producers: producers_map,
             categories: categories_map,
         })
     }
 
-    pub fn process_events(self, mut primary_detector: AllDetectors) -> Vec<ProcessedEvent> {
+    /// Locales of the tag descriptions, for the output metadata.
+    pub fn tag_locales(&self) -> TagLocales {
+        let available_locales = self
+            .tags
+            .values()
+            .map(|tag| tag.locale().to_string())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        let preferred_key_locale = self.preferred_locale.to_ascii_lowercase();
+        let number_of_tags_in_fallback_locale = self
+            .tag_locales
+            .values()
+            .filter(|key| key.locale() != preferred_key_locale)
+            .count();
+
+        TagLocales {
+            preferred_locale: self.preferred_locale.clone(),
+            available_locales,
+            number_of_tags_in_fallback_locale,
+        }
+    }
+
+    /// Runs all detectors over every persisted event as it is streamed from the readers.
+    ///
+    /// Databases are read one after another. An event that an earlier database
+    /// already contained (as identified by [`PersistedEvent::key`]) is not processed again;
+    /// instead, the database is added to the `sources` of the events detected in it.
+    /// The keys of the events are looked up in a temporary database on disk (see
+    /// [`SeenEvents`]); only events found in more than one database are kept in memory.
+    ///
+    /// Persisted events are buffered in chunks of [`CHUNK_SIZE`], over which the detectors
+    /// run on `jobs` threads; each chunk is dropped as soon as the detectors have seen it.
+    /// Only the detected events (and rows the readers rejected) are kept, ordered by
+    /// timestamp, together with the OS versions the events report.
+    ///
+    /// Changes of the clock skew larger than `clock_jump_threshold` are reported as jumps.
+    pub async fn process_events(
+        self,
+        detectors: DetectorRegistry,
+        jobs: usize,
+        clock_jump_threshold: TimeDelta,
+    ) -> Result<ProcessingResults> {
         let read_only_view = EventTranscriptReadOnlyView {
             tags: &self.tags,
+            tag_locales: &self.tag_locales,
             producers: &self.producers,
             categories: &self.categories,
         };
 
-        let mut aggregated_events = Vec::new();
+        let mut pipeline = DetectorPipeline::new(detectors.into_detectors(), jobs)?;
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        let mut aggregated_events = AggregatedEvents::default();
+        let mut os_version_observer = OsVersionObserver::default();
+        let mut malformed_envelope_collector = MalformedEnvelopeCollector::default();
+        let mut clock_skew_observer = ClockSkewObserver::new(clock_jump_threshold);
+        let mut evidence_summary_collector = EvidenceSummaryCollector::default();
+
+        // Only needed if there is a later database that could contain the same events.
+        let seen_events = if self.readers.len() > 1 {
+            Some(SeenEvents::new().await?)
+        } else {
+            None
+        };
+        let last_reader_index = self.readers.len().saturating_sub(1);
+
+        // Events that more than one database contains, by the digest of their key.
+        let mut shared_event_sources: HashMap<[u8; 32], PersistedEventSources> = HashMap::new();
+
+        for (reader_index, reader) in self.readers.iter().enumerate() {
+            let mut number_of_duplicate_events: u64 = 0;
+            // Events are looked up in batches, so there is one query per batch instead of per event.
+            let mut batches = pin!(reader.stream_events().await.try_chunks(CHUNK_SIZE));
+
+            while let Some(batch) = batches
+                .try_next()
+                .await
+                .map_err(|TryChunksError(_, error)| error)
+                .wrap_err_with(|| {
+                    format!(
+                        "Failed to load persisted event from {}.",
+                        reader.database_path().display()
+                    )
+                })?
+            {
+                let earlier_events = match seen_events.as_ref().filter(|_| reader_index > 0) {
+                    Some(seen_events) => {
+                        let key_digests = batch
+                            .iter()
+                            .map(|event| event.key().digest())
+                            .collect::<Vec<_>>();
+                        seen_events
+                            .find_all_from_earlier_reader(&key_digests, reader_index)
+                            .await
+                            .wrap_err("Failed to look up the keys of persisted events.")?
+                    }
+                    None => HashMap::new(),
+                };
+
+                for event in batch {
+                    if !earlier_events.is_empty() {
+                        let key_digest = event.key().digest();
+
+                        if let Some((first_reader_index, processed_event_indices)) =
+                            earlier_events.get(&key_digest)
+                        {
+                            let sources =
+                                shared_event_sources.entry(key_digest).or_insert_with(|| {
+                                    PersistedEventSources {
+                                        reader_indices: vec![*first_reader_index],
+                                        processed_event_indices: processed_event_indices.clone(),
+                                    }
+                                });
+                            if !sources.reader_indices.contains(&reader_index) {
+                                sources.reader_indices.push(reader_index);
+                            }
+
+                            number_of_duplicate_events += 1;
+                            continue;
+                        }
+                    }
+
+                    os_version_observer.observe(&event);
+                    clock_skew_observer.observe(&event, reader.database_path());
+                    evidence_summary_collector.observe(&event);
+                    malformed_envelope_collector.observe(
+                        event.event_name(),
+                        *event.timestamp(),
+                        event.envelope(),
+                    );
+
+                    chunk.push(event);
+
+                    if chunk.len() == CHUNK_SIZE {
+                        process_chunk(
+                            &mut chunk,
+                            &mut pipeline,
+                            &mut aggregated_events,
+                            &read_only_view,
+                            reader_index,
+                            reader.database_path(),
+                            seen_events
+                                .as_ref()
+                                .filter(|_| reader_index < last_reader_index),
+                        )
+                        .await?;
+                    }
+                }
+            }
+
+            // Chunks do not span databases, so every chunk has a single source.
+            process_chunk(
+                &mut chunk,
+                &mut pipeline,
+                &mut aggregated_events,
+                &read_only_view,
+                reader_index,
+                reader.database_path(),
+                seen_events
+                    .as_ref()
+                    .filter(|_| reader_index < last_reader_index),
+            )
+            .await?;
+
+            info!(
+                "Skipped {} events of {} that an earlier database already contained.",
+                number_of_duplicate_events,
+                reader.database_path().display()
+            );
+        }
+
+        drop(chunk);
+        pipeline.log_timings();
+        if let Some(seen_events) = seen_events {
+            seen_events.close().await;
+        }
+
+        let AggregatedEvents {
+            events: mut aggregated_events,
+            user_observer,
+        } = aggregated_events;
+
+        for sources in shared_event_sources.into_values() {
+            let source_paths = sources
+                .reader_indices
+                .iter()
+                .map(|&reader_index| self.readers[reader_index].database_path().to_path_buf())
+                .collect::<Vec<_>>();
+
+            for processed_event in &mut aggregated_events[sources.processed_event_indices] {
+                processed_event.sources = source_paths.clone();
+            }
+        }
+
+        // Stable, so events of the same timestamp keep the order they were detected in.
+        aggregated_events.sort_by_key(|processed_event| processed_event.timestamp);
 
-        for event in self.events {
-            let emitted_events = primary_detector.process_event(&event, &read_only_view);
+        let evidence_summary = evidence_summary_collector.into_summary(&read_only_view);
 
-            if let Some(events) = emitted_events {
-                aggregated_events.extend(events);
+        let mut rejected_rows = Vec::new();
+        let mut records_without_json_payload = Vec::new();
+        for reader in self.readers {
+            rejected_rows.extend(reader.take_rejected_rows());
+            if let Some(number_of_records) = reader.number_of_records_without_json_payload() {
+                records_without_json_payload.push((
+                    reader.database_path().to_path_buf(),
+                    number_of_records,
+                ));
             }
+            reader.close().await;
         }
 
-        aggregated_events
+        Ok(ProcessingResults {
+            events: aggregated_events,
+            rejected_rows,
+            records_without_json_payload,
+            observed_os_versions: os_version_observer.into_observed_versions(),
+            malformed_envelopes: malformed_envelope_collector.into_malformed_envelopes(),
+            users: user_observer.into_report(),
+            clock_skew: clock_skew_observer.into_report(),
+            evidence_summary,
+        })
     }
 }
 
+/// Everything [`EventTranscriptProcessor::process_events`] produces.
+pub struct ProcessingResults {
+    pub events: Vec<ProcessedEvent>,
+
+    /// Rows that could not be decoded (only collected in lenient mode).
+    pub rejected_rows: Vec<EventReaderError>,
+
+    /// Number of records each event store skipped because their payload is not JSON.
+    pub records_without_json_payload: Vec<(PathBuf, u64)>,
+
+    /// OS versions (`ext.os.ver`) reported by the events.
+    pub observed_os_versions: Vec<ObservedOsVersion>,
+
+    /// Events whose Common Schema envelope could not be fully decoded.
+    pub malformed_envelopes: Vec<MalformedEnvelope>,
+
+    /// Users the events were attributed to.
+    pub users: UserReport,
+
+    /// How payload times and database timestamps of the events relate.
+    pub clock_skew: ClockSkewReport,
+
+    /// Events per tag and per category.
+    pub evidence_summary: EvidenceSummary,
+}
+
 #[allow(dead_code)]
 pub struct EventTranscriptReadOnlyView<'a> {
-    tags: &'a HashMap<TagDescriptionId, TagDescription>,
+    tags: &'a HashMap<TagDescriptionKey, TagDescription>,
+    tag_locales: &'a HashMap<TagDescriptionId, TagDescriptionKey>,
     producers: &'a HashMap<ProducerId, Producer>,
     categories: &'a HashMap<CategoryId, Category>,
 }
 
 #[allow(dead_code)]
 impl<'a> EventTranscriptReadOnlyView<'a> {
+    /// Description of the tag in the preferred locale (or the one it falls back to).
     pub fn tag_by_id(&self, tag_id: TagDescriptionId) -> Option<&TagDescription> {
-        self.tags.get(&tag_id)
+        self.tags.get(self.tag_locales.get(&tag_id)?)
+    }
+
+    pub fn tag_by_id_and_locale(
+        &self,
+        tag_id: TagDescriptionId,
+        locale: &str,
+    ) -> Option<&TagDescription> {
+        self.tags.get(&TagDescriptionKey::new(tag_id, locale))
     }
 
     pub fn producer_by_id(&self, producer_id: ProducerId) -> Option<&Producer> {
@@ -123,8 +513,27 @@ impl<'a> EventTranscriptReadOnlyView<'a> {
     }
 }
 
+#[cfg(test)]
+impl<'a> EventTranscriptReadOnlyView<'a> {
+    /// A view of the given tags (with the locale chosen for each), producers and categories.
+    pub fn for_tests(
+        tags: &'a HashMap<TagDescriptionKey, TagDescription>,
+        tag_locales: &'a HashMap<TagDescriptionId, TagDescriptionKey>,
+        producers: &'a HashMap<ProducerId, Producer>,
+        categories: &'a HashMap<CategoryId, Category>,
+    ) -> Self {
+        Self {
+            tags,
+            tag_locales,
+            producers,
+            categories,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
+#[allow(clippy::enum_variant_names)]
 pub enum DetectedEvent {
     #[serde(rename = "battery_event")]
     BatteryEvent(BatteryEvent),
@@ -139,29 +548,169 @@ pub enum DetectedEvent {
     UsbEvent(USBEvent),
 }
 
+impl DetectedEvent {
+    /// Name of the detector that finds this kind of event.
+    pub fn detector_name(&self) -> &'static str {
+        match self {
+            Self::BatteryEvent(_) => "battery",
+            Self::ApplicationEvent(_) => "application",
+            Self::EdgeEvent(_) => "edge",
+            Self::UsbEvent(_) => "usb",
+        }
+    }
+
+    /// Clock the times of the event are taken from.
+    pub fn timeline_clock(&self) -> TimelineClock {
+        match self {
+            // Opening and closing times are computed from the payload time.
+            Self::ApplicationEvent(_) => TimelineClock::PayloadTime,
+            Self::BatteryEvent(_) | Self::EdgeEvent(_) | Self::UsbEvent(_) => {
+                TimelineClock::DatabaseTimestamp
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ProcessedEvent {
     pub id: Uuid,
+
+    /// Timestamp of the source event (with 100-nanosecond precision).
     pub timestamp: DateTime<Utc>,
+
+    /// Timestamp of the source event, as the raw `FILETIME` tick value.
+    pub filetime: FileTime,
+
+    /// Where the source event was read from.
+    pub provenance: EventProvenance,
+
+    /// Whether the source event was recovered (from the write-ahead log or unused space)
+    /// rather than read from the database as it is.
+    pub recovered: bool,
+
+    /// Database files that contain the source event.
+    pub sources: Vec<PathBuf>,
+
+    /// User the source event was logged for (a SID, or the raw `ext.user.localId`).
+    pub user: Option<String>,
+
+    /// Both clocks of the source event, if its payload has a time.
+    pub clock_skew: Option<ClockSkewAnnotation>,
+
+    /// Tags of the source event (e.g. "Browsing History"), as far as the transcript
+    /// describes them.
+    pub tags: Vec<EventTag>,
+
+    /// Names of the categories of the source event.
+    pub categories: Vec<String>,
+
+    /// Name of the producer of the source event (e.g. Windows).
+    pub producer: Option<String>,
+    pub provider_group_guid: Option<String>,
+
+    /// Binary that logged the source event.
+    pub logging_binary: Option<LoggingBinary>,
+
     pub detected_event: DetectedEvent,
 }
 
 impl ProcessedEvent {
-    pub fn new_with_random_id<E>(timestamp: DateTime<Utc>, event: E) -> Self
+    /// Creates a new processed event detected in `source_event`, taking over its timestamp.
+    pub fn new_with_random_id<E>(source_event: &PersistedEvent, event: E) -> Self
     where
         E: Into<DetectedEvent>,
     {
         let id = Uuid::new_v4();
+        let detected_event = event.into();
 
         Self {
             id,
-            timestamp,
-            detected_event: event.into(),
+            timestamp: source_event.timestamp().to_owned(),
+            filetime: source_event.filetime(),
+            provenance: source_event.provenance().clone(),
+            recovered: source_event.provenance().is_recovered(),
+            sources: Vec::new(),
+            user: user_of(source_event),
+            clock_skew: ClockSkewAnnotation::new(source_event, &detected_event),
+            tags: Vec::new(),
+            categories: Vec::new(),
+            producer: None,
+            provider_group_guid: None,
+            logging_binary: None,
+            detected_event,
         }
     }
+
+    /// Fills in the tags, categories, producer, provider group and logging binary
+    /// of the source event, looked up in the transcripts.
+    ///
+    /// IDs the transcripts do not describe are left out.
+    pub fn resolve_source_event(
+        &mut self,
+        source_event: &PersistedEvent,
+        context: &EventTranscriptReadOnlyView,
+    ) {
+        self.tags = source_event
+            .tag_description_ids()
+            .iter()
+            .filter_map(|&tag_id| context.tag_by_id(tag_id))
+            .map(|tag| EventTag {
+                id: tag.id(),
+                name: tag.name().to_string(),
+                description: tag.description().to_string(),
+            })
+            .collect();
+
+        self.categories = source_event
+            .category_ids()
+            .iter()
+            .filter_map(|&category_id| context.category_by_id(category_id))
+            .map(|category| category.name().to_string())
+            .collect();
+
+        self.producer = context
+            .producer_by_id(source_event.producer_id())
+            .map(|producer| producer.name().to_string());
+
+        // Events of the event store have neither a provider group nor a logging binary.
+        let provider_group_guid = source_event.provider_group().guid();
+        self.provider_group_guid =
+            (!provider_group_guid.is_empty()).then(|| provider_group_guid.to_string());
+
+        let logging_binary = source_event.logging_binary();
+        self.logging_binary = (!logging_binary.name.is_empty()).then(|| logging_binary.clone());
+    }
 }
 
-pub trait EventDetector {
+/// A tag of an event, with its description.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventTag {
+    pub id: TagDescriptionId,
+    pub name: String,
+    pub description: String,
+}
+
+pub trait EventDetector: Send {
+    /// Short name, by which the detector is selected on the command line.
+    fn name(&self) -> &'static str;
+
+    /// What the detector finds, in a few words.
+    fn description(&self) -> &'static str;
+
+    /// Version of the detection logic, changed whenever the detector reports
+    /// different events for the same input.
+    fn version(&self) -> &'static str;
+
+    /// Names (`full_event_name`) of the persisted events the detector looks at.
+    fn source_event_names(&self) -> Vec<&'static str>;
+
+    /// Another instance of the detector for a worker thread, if the detector keeps
+    /// no state between events. Such detectors run on parts of the events in parallel;
+    /// the others (the default) see every event, in order, one chunk at a time.
+    fn fork(&self) -> Option<Box<dyn EventDetector>> {
+        None
+    }
+
     fn process_event(
         &mut self,
         event: &PersistedEvent,
@@ -169,46 +718,199 @@ pub trait EventDetector {
     ) -> Option<Vec<ProcessedEvent>>;
 }
 
-pub struct AllDetectors {
-    battery: BatteryEventDetector,
-    application: ApplicationEventDetector,
-    usb: USBEventDetector,
+/// Name, version and purpose of a detector, for `list-detectors` and the output metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct DetectorMetadata {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub description: &'static str,
+    pub source_event_names: Vec<&'static str>,
+}
+
+/// The detectors to run, in the order their events are reported in.
+pub struct DetectorRegistry {
+    detectors: Vec<Box<dyn EventDetector>>,
 }
 
-impl AllDetectors {
+impl DetectorRegistry {
+    /// A registry of every detector the tool has.
     pub fn new() -> Self {
-        Self {
-            battery: BatteryEventDetector::new(),
-            application: ApplicationEventDetector::new(),
-            usb: USBEventDetector::new(),
+        let mut registry = Self {
+            detectors: Vec::new(),
+        };
+
+        registry.register(Box::new(BatteryEventDetector::new()));
+        registry.register(Box::new(ApplicationEventDetector::new()));
+        registry.register(Box::new(USBEventDetector::new()));
+        registry.register(Box::new(EdgeEventDetector::new()));
+
+        registry
+    }
+
+    pub fn register(&mut self, detector: Box<dyn EventDetector>) {
+        self.detectors.push(detector);
+    }
+
+    /// Keeps only the `enabled` detectors (all of them if none are given),
+    /// except for the `skipped` ones.
+    ///
+    /// Fails if a name does not belong to any detector.
+    pub fn select(self, enabled: &[String], skipped: &[String]) -> Result<Self> {
+        let known_names = self
+            .detectors
+            .iter()
+            .map(|detector| detector.name())
+            .collect::<Vec<_>>();
+
+        if let Some(unknown_name) = enabled
+            .iter()
+            .chain(skipped)
+            .find(|name| !known_names.contains(&name.as_str()))
+        {
+            return Err(miette!(
+                "Unknown detector {}; the detectors are: {}.",
+                unknown_name,
+                known_names.join(", ")
+            ));
         }
+
+        let detectors = self
+            .detectors
+            .into_iter()
+            .filter(|detector| {
+                enabled.is_empty() || enabled.iter().any(|name| name == detector.name())
+            })
+            .filter(|detector| !skipped.iter().any(|name| name == detector.name()))
+            .collect();
+
+        Ok(Self { detectors })
+    }
+
+    pub fn metadata(&self) -> Vec<DetectorMetadata> {
+        self.detectors
+            .iter()
+            .map(|detector| DetectorMetadata {
+                name: detector.name(),
+                version: detector.version(),
+                description: detector.description(),
+                source_event_names: detector.source_event_names(),
+            })
+            .collect()
+    }
+
+    /// Names of the individual detectors, each with the names of its source events.
+    pub fn source_event_names_by_detector(&self) -> Vec<(&'static str, Vec<&'static str>)> {
+        self.detectors
+            .iter()
+            .map(|detector| (detector.name(), detector.source_event_names()))
+            .collect()
+    }
+
+    pub fn into_detectors(self) -> Vec<Box<dyn EventDetector>> {
+        self.detectors
     }
 }
 
-impl EventDetector for AllDetectors {
-    fn process_event(
-        &mut self,
-        event: &PersistedEvent,
-        context: &EventTranscriptReadOnlyView,
-    ) -> Option<Vec<ProcessedEvent>> {
-        let mut aggregated_events = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(descriptions: &[(i64, &str)]) -> HashMap<TagDescriptionKey, TagDescription> {
+        descriptions
+            .iter()
+            .map(|&(id, locale)| {
+                let tag = TagDescription::new(
+                    TagDescriptionId::new(id),
+                    format!("{id} ({locale})"),
+                    String::new(),
+                    locale.to_string(),
+                );
+                (tag.key(), tag)
+            })
+            .collect()
+    }
 
-        if let Some(emitted_battery_events) = self.battery.process_event(event, context) {
-            aggregated_events.extend(emitted_battery_events);
-        };
+    fn processor_with_tags(
+        descriptions: &[(i64, &str)],
+        preferred_locale: &str,
+    ) -> EventTranscriptProcessor {
+        let tags = tags(descriptions);
+
+        EventTranscriptProcessor {
+            readers: Vec::new(),
+            tag_locales: choose_tag_locales(&tags, preferred_locale),
+            tags,
+            preferred_locale: preferred_locale.to_string(),
+            producers: HashMap::new(),
+            categories: HashMap::new(),
+        }
+    }
 
-        if let Some(emitted_application_events) = self.application.process_event(event, context) {
-            aggregated_events.extend(emitted_application_events);
+    /// Name of the description of each tag that was chosen, by tag ID.
+    fn chosen_names(processor: &EventTranscriptProcessor) -> Vec<String> {
+        let view = EventTranscriptReadOnlyView {
+            tags: &processor.tags,
+            tag_locales: &processor.tag_locales,
+            producers: &processor.producers,
+            categories: &processor.categories,
         };
 
-        if let Some(emitted_usb_events) = self.usb.process_event(event, context) {
-            aggregated_events.extend(emitted_usb_events);
+        (1..=4)
+            .filter_map(|id| view.tag_by_id(TagDescriptionId::new(id)))
+            .map(|tag| tag.name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn falls_back_to_the_default_locale_then_any_other() {
+        let descriptions = [
+            (1, "de-DE"),
+            (1, "en-US"),
+            (1, "fr-FR"),
+            (2, "en-US"),
+            (2, "fr-FR"),
+            (3, "sl-SI"),
+            (3, "fr-FR"),
+        ];
+
+        // Locales are compared case-insensitively.
+        let processor = processor_with_tags(&descriptions, "DE-de");
+        assert_eq!(
+            chosen_names(&processor),
+            ["1 (de-DE)", "2 (en-US)", "3 (fr-FR)"]
+        );
+
+        let tag_locales = processor.tag_locales();
+        assert_eq!(tag_locales.preferred_locale, "DE-de");
+        assert_eq!(
+            tag_locales.available_locales,
+            ["de-DE", "en-US", "fr-FR", "sl-SI"]
+        );
+        assert_eq!(tag_locales.number_of_tags_in_fallback_locale, 2);
+
+        let processor = processor_with_tags(&descriptions, DEFAULT_TAG_LOCALE);
+        assert_eq!(
+            chosen_names(&processor),
+            ["1 (en-US)", "2 (en-US)", "3 (fr-FR)"]
+        );
+        assert_eq!(
+            processor.tag_locales().number_of_tags_in_fallback_locale,
+            1
+        );
+    }
+
+    #[test]
+    fn ranks_locales() {
+        let rank = |locale, preferred_locale| {
+            let key = TagDescriptionKey::new(TagDescriptionId::new(1), locale);
+            let (priority, locale) = locale_rank(&key, preferred_locale);
+            (priority, locale.to_string())
         };
 
-        if !aggregated_events.is_empty() {
-            Some(aggregated_events)
-        } else {
-            None
-        }
+        assert_eq!(rank("sl-SI", "sl-si"), (0, "sl-si".to_string()));
+        assert_eq!(rank("en-US", "sl-SI"), (1, "en-us".to_string()));
+        assert_eq!(rank("EN-us", "en-US"), (0, "en-us".to_string()));
+        assert_eq!(rank("de-DE", "sl-SI"), (2, "de-de".to_string()));
+        assert!(rank("de-DE", "sl-SI") < rank("fr-FR", "sl-SI"));
     }
 }
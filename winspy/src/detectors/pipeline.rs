@@ -0,0 +1,370 @@
+//! Runs the detectors over chunks of persisted events on a pool of worker threads.
+//!
+//! The pool is created once, with `jobs` threads, and every chunk is split into tasks for it.
+//! Stateless detectors (those that can be forked) are copied once per thread, and each copy
+//! takes a contiguous part of the chunk. Every stateful detector is one more task that sees
+//! all events of the chunk, in order. Stateful detectors therefore share the threads of the
+//! pool instead of taking one each, so no more than `jobs` threads ever run detectors.
+//!
+//! The events detected in a persisted event are merged in the order of the detectors,
+//! so the results do not depend on the number of threads.
+
+use std::time::{Duration, Instant};
+
+use miette::{IntoDiagnostic, Result, WrapErr};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use tracing::info;
+
+use super::{EventDetector, EventTranscriptReadOnlyView, ProcessedEvent};
+use crate::models::persisted_event::PersistedEvent;
+
+/// Number of persisted events that are buffered before the detectors run over them.
+pub const CHUNK_SIZE: usize = 4096;
+
+/// A detector, with its position in the list of detectors.
+type IndexedDetector = (usize, Box<dyn EventDetector>);
+
+/// Events one detector found in one persisted event: event index, detector index, events.
+type Detection = (usize, usize, Vec<ProcessedEvent>);
+
+pub struct DetectorPipeline {
+    detector_names: Vec<&'static str>,
+
+    /// Threads the tasks run on, or `None` to run them on the calling thread.
+    pool: Option<ThreadPool>,
+
+    /// Copies of all stateless detectors, one per thread.
+    stateless_workers: Vec<Vec<IndexedDetector>>,
+    stateful_detectors: Vec<IndexedDetector>,
+
+    /// Time spent in each detector, summed over all workers.
+    durations: Vec<Duration>,
+    number_of_detected_events: Vec<u64>,
+}
+
+impl DetectorPipeline {
+    /// Distributes `detectors` over a pool of `jobs` threads (at least one).
+    pub fn new(detectors: Vec<Box<dyn EventDetector>>, jobs: usize) -> Result<Self> {
+        let jobs = jobs.max(1);
+        let number_of_detectors = detectors.len();
+
+        let mut detector_names = Vec::with_capacity(number_of_detectors);
+        let mut stateless_detectors = Vec::new();
+        let mut stateful_detectors = Vec::new();
+
+        for (detector_index, detector) in detectors.into_iter().enumerate() {
+            detector_names.push(detector.name());
+
+            if detector.fork().is_some() {
+                stateless_detectors.push((detector_index, detector));
+            } else {
+                stateful_detectors.push((detector_index, detector));
+            }
+        }
+
+        let mut stateless_workers = Vec::with_capacity(jobs);
+        for _ in 1..jobs {
+            stateless_workers.push(
+                stateless_detectors
+                    .iter()
+                    .filter_map(|(detector_index, detector)| {
+                        Some((*detector_index, detector.fork()?))
+                    })
+                    .collect(),
+            );
+        }
+        stateless_workers.push(stateless_detectors);
+
+        let pool = if jobs > 1 {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(jobs)
+                .thread_name(|thread_index| format!("detector-{thread_index}"))
+                .build()
+                .into_diagnostic()
+                .wrap_err("Failed to start the detector threads.")?;
+            Some(pool)
+        } else {
+            None
+        };
+
+        info!(
+            "Running {} detectors on {} threads ({} stateful).",
+            number_of_detectors,
+            jobs,
+            stateful_detectors.len()
+        );
+
+        Ok(Self {
+            detector_names,
+            pool,
+            stateless_workers,
+            stateful_detectors,
+            durations: vec![Duration::ZERO; number_of_detectors],
+            number_of_detected_events: vec![0; number_of_detectors],
+        })
+    }
+
+    /// Runs the detectors over a chunk of persisted events.
+    ///
+    /// Returns the detected events of every persisted event, in the order of `events`.
+    pub fn process_chunk(
+        &mut self,
+        events: &[PersistedEvent],
+        context: &EventTranscriptReadOnlyView,
+    ) -> Vec<Vec<ProcessedEvent>> {
+        let part_size = events.len().div_ceil(self.stateless_workers.len()).max(1);
+
+        // Each task runs some detectors over a contiguous part of the chunk.
+        let mut tasks = Vec::new();
+        for (worker_index, detectors) in self.stateless_workers.iter_mut().enumerate() {
+            let first_event_index = (worker_index * part_size).min(events.len());
+            let last_event_index = (first_event_index + part_size).min(events.len());
+            tasks.push((
+                detectors.as_mut_slice(),
+                first_event_index,
+                &events[first_event_index..last_event_index],
+            ));
+        }
+        for detector in &mut self.stateful_detectors {
+            tasks.push((std::slice::from_mut(detector), 0, events));
+        }
+
+        let run_task = |(detectors, first_event_index, events)| {
+            run_detectors(detectors, first_event_index, events, context)
+        };
+        let outputs = match &self.pool {
+            Some(pool) => pool.install(|| {
+                tasks
+                    .into_par_iter()
+                    .with_max_len(1)
+                    .map(run_task)
+                    .collect::<Vec<_>>()
+            }),
+            None => tasks.into_iter().map(run_task).collect::<Vec<_>>(),
+        };
+
+        let mut detections_by_event: Vec<Vec<(usize, Vec<ProcessedEvent>)>> =
+            events.iter().map(|_| Vec::new()).collect();
+        for (detections, durations) in outputs {
+            for (event_index, detector_index, processed_events) in detections {
+                self.number_of_detected_events[detector_index] += processed_events.len() as u64;
+                detections_by_event[event_index].push((detector_index, processed_events));
+            }
+
+            for (detector_index, duration) in durations {
+                self.durations[detector_index] += duration;
+            }
+        }
+
+        detections_by_event
+            .into_iter()
+            .map(|mut detections| {
+                detections.sort_by_key(|(detector_index, _)| *detector_index);
+                detections
+                    .into_iter()
+                    .flat_map(|(_, processed_events)| processed_events)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Logs how long each detector took (over all workers) and how much it found.
+    pub fn log_timings(&self) {
+        for (detector_index, name) in self.detector_names.iter().enumerate() {
+            info!(
+                "Detector {} found {} events in {:.3} s.",
+                name,
+                self.number_of_detected_events[detector_index],
+                self.durations[detector_index].as_secs_f64()
+            );
+        }
+    }
+}
+
+/// Runs each of `detectors` over `events`, which start at `first_event_index` in the chunk.
+fn run_detectors(
+    detectors: &mut [IndexedDetector],
+    first_event_index: usize,
+    events: &[PersistedEvent],
+    context: &EventTranscriptReadOnlyView,
+) -> (Vec<Detection>, Vec<(usize, Duration)>) {
+    let mut detections = Vec::new();
+    let mut durations = Vec::with_capacity(detectors.len());
+
+    for (detector_index, detector) in detectors {
+        let started_at = Instant::now();
+
+        for (event_offset, event) in events.iter().enumerate() {
+            if let Some(processed_events) = detector.process_event(event, context) {
+                detections.push((
+                    first_event_index + event_offset,
+                    *detector_index,
+                    processed_events,
+                ));
+            }
+        }
+
+        durations.push((*detector_index, started_at.elapsed()));
+    }
+
+    (detections, durations)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use serde_json::json;
+
+    use super::*;
+    use crate::detectors::battery::BatteryEvent;
+
+    /// Reports `n % 100` of every event whose `n` is even, on any thread.
+    struct EvenEventDetector;
+
+    /// Reports `100 + (number of events seen so far) % 100` for every event.
+    struct CountingDetector {
+        number_of_events: u64,
+    }
+
+    fn battery_event(event: &PersistedEvent, value: u64) -> Option<Vec<ProcessedEvent>> {
+        Some(vec![ProcessedEvent::new_with_random_id(
+            event,
+            BatteryEvent::battery_percentage_change(value as u8),
+        )])
+    }
+
+    impl EventDetector for EvenEventDetector {
+        fn name(&self) -> &'static str {
+            "even"
+        }
+
+        fn description(&self) -> &'static str {
+            "even events"
+        }
+
+        fn version(&self) -> &'static str {
+            "0.1.0"
+        }
+
+        fn source_event_names(&self) -> Vec<&'static str> {
+            Vec::new()
+        }
+
+        fn fork(&self) -> Option<Box<dyn EventDetector>> {
+            Some(Box::new(Self))
+        }
+
+        fn process_event(
+            &mut self,
+            event: &PersistedEvent,
+            _context: &EventTranscriptReadOnlyView,
+        ) -> Option<Vec<ProcessedEvent>> {
+            let n = event.envelope().data.get("n")?.as_u64()?;
+            (n % 2 == 0).then_some(())?;
+            battery_event(event, n % 100)
+        }
+    }
+
+    impl EventDetector for CountingDetector {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn description(&self) -> &'static str {
+            "all events, counted"
+        }
+
+        fn version(&self) -> &'static str {
+            "0.1.0"
+        }
+
+        fn source_event_names(&self) -> Vec<&'static str> {
+            Vec::new()
+        }
+
+        fn process_event(
+            &mut self,
+            event: &PersistedEvent,
+            _context: &EventTranscriptReadOnlyView,
+        ) -> Option<Vec<ProcessedEvent>> {
+            self.number_of_events += 1;
+            battery_event(event, 100 + self.number_of_events % 100)
+        }
+    }
+
+    fn events(number_of_events: u64) -> Vec<PersistedEvent> {
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+
+        (0..number_of_events)
+            .map(|n| {
+                PersistedEvent::for_tests(
+                    "d",
+                    "Microsoft.Windows.Test",
+                    start + TimeDelta::seconds(n as i64),
+                    json!({ "data": { "n": n } }),
+                )
+            })
+            .collect()
+    }
+
+    /// Runs the pipeline over `events` and returns the values it reported, in order.
+    fn run(jobs: usize, events: &[PersistedEvent]) -> Vec<Vec<u64>> {
+        let detectors: Vec<Box<dyn EventDetector>> = vec![
+            Box::new(CountingDetector {
+                number_of_events: 0,
+            }),
+            Box::new(EvenEventDetector),
+        ];
+        let mut pipeline = DetectorPipeline::new(detectors, jobs).unwrap();
+
+        let (tags, tag_locales, producers, categories) = (
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let context = EventTranscriptReadOnlyView {
+            tags: &tags,
+            tag_locales: &tag_locales,
+            producers: &producers,
+            categories: &categories,
+        };
+
+        events
+            .chunks(CHUNK_SIZE)
+            .flat_map(|chunk| pipeline.process_chunk(chunk, &context))
+            .map(|processed_events| {
+                processed_events
+                    .iter()
+                    .map(|processed_event| {
+                        let value = serde_json::to_value(&processed_event.detected_event).unwrap();
+                        value["content"]["battery_percentage_change"]["battery_percentage"]
+                            .as_u64()
+                            .unwrap()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_detections_in_event_and_detector_order() {
+        let events = events(2 * CHUNK_SIZE as u64 + 123);
+
+        let expected = (0..events.len() as u64)
+            .map(|n| {
+                let mut values = vec![100 + (n + 1) % 100];
+                if n % 2 == 0 {
+                    values.push(n % 100);
+                }
+                values
+            })
+            .collect::<Vec<_>>();
+
+        for jobs in [1, 2, 3, 8] {
+            assert_eq!(run(jobs, &events), expected, "{jobs} jobs");
+        }
+    }
+}
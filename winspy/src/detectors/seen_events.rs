@@ -0,0 +1,190 @@
+//! Keys of the persisted events read so far, kept in a temporary database on disk.
+//!
+//! When several databases are read, a later one may contain events that an earlier one
+//! already did. Keeping the key of every event in memory until the last database has
+//! been read would grow with the size of the evidence, so the keys are stored in an
+//! indexed table instead, and only events actually found again are kept in memory.
+
+use std::{collections::HashMap, ops::Range};
+
+use miette::{miette, IntoDiagnostic, Result};
+use sqlx::QueryBuilder;
+
+use crate::temporary_database::TemporaryDatabase;
+
+pub struct SeenEvents {
+    database: TemporaryDatabase,
+}
+
+impl SeenEvents {
+    pub async fn new() -> Result<Self> {
+        let database = TemporaryDatabase::new(
+            "seen_events.db",
+            "CREATE TABLE seen_events (
+                key_digest BLOB PRIMARY KEY,
+                reader_index INTEGER NOT NULL,
+                first_processed_event_index INTEGER NOT NULL,
+                last_processed_event_index INTEGER NOT NULL
+            ) WITHOUT ROWID",
+        )
+        .await?;
+
+        Ok(Self { database })
+    }
+
+    /// Looks up which of the given events a reader before `reader_index` already read,
+    /// with a single query.
+    ///
+    /// Returns the index of that reader and the indices of the events detected in it,
+    /// by the digest of the event key.
+    pub async fn find_all_from_earlier_reader(
+        &self,
+        key_digests: &[[u8; 32]],
+        reader_index: usize,
+    ) -> Result<HashMap<[u8; 32], (usize, Range<usize>)>> {
+        if key_digests.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        // A chunk of keys stays well below SQLite's limit of 32766 parameters.
+        let mut query = QueryBuilder::new(
+            "SELECT key_digest, reader_index, first_processed_event_index, \
+                last_processed_event_index \
+            FROM seen_events \
+            WHERE reader_index < ",
+        );
+        query.push_bind(reader_index as i64);
+        query.push(" AND key_digest IN (");
+        let mut separated = query.separated(", ");
+        for key_digest in key_digests {
+            separated.push_bind(key_digest.as_slice());
+        }
+        separated.push_unseparated(")");
+
+        let rows: Vec<(Vec<u8>, i64, i64, i64)> = query
+            .build_query_as()
+            .fetch_all(self.database.pool())
+            .await
+            .into_diagnostic()?;
+
+        rows.into_iter()
+            .map(
+                |(key_digest, reader_index, first_index, last_index)| {
+                    let key_digest = <[u8; 32]>::try_from(key_digest).map_err(|_| {
+                        miette!("The temporary database holds a malformed event key.")
+                    })?;
+
+                    Ok((
+                        key_digest,
+                        (
+                            reader_index as usize,
+                            first_index as usize..last_index as usize,
+                        ),
+                    ))
+                },
+            )
+            .collect()
+    }
+
+    /// Remembers the events of a chunk read by `reader_index`, with the indices
+    /// of the events detected in each. An event that is already known keeps
+    /// the reader it was first read by.
+    pub async fn insert_all(
+        &self,
+        reader_index: usize,
+        events: impl IntoIterator<Item = ([u8; 32], Range<usize>)>,
+    ) -> Result<()> {
+        let mut transaction = self.database.pool().begin().await.into_diagnostic()?;
+
+        for (key_digest, processed_event_indices) in events {
+            sqlx::query(
+                "INSERT OR IGNORE INTO seen_events
+                (key_digest, reader_index, first_processed_event_index, last_processed_event_index)
+                VALUES (?, ?, ?, ?)",
+            )
+            .bind(key_digest.as_slice())
+            .bind(reader_index as i64)
+            .bind(processed_event_indices.start as i64)
+            .bind(processed_event_indices.end as i64)
+            .execute(&mut *transaction)
+            .await
+            .into_diagnostic()?;
+        }
+
+        transaction.commit().await.into_diagnostic()
+    }
+
+    pub async fn close(self) {
+        self.database.close().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_events_of_earlier_readers_only() {
+        let seen_events = SeenEvents::new().await.unwrap();
+        seen_events
+            .insert_all(0, [([1; 32], 0..2), ([2; 32], 2..2)])
+            .await
+            .unwrap();
+        seen_events
+            .insert_all(1, [([1; 32], 5..6), ([3; 32], 6..7)])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            seen_events
+                .find_all_from_earlier_reader(&[[1; 32], [2; 32], [3; 32], [4; 32]], 2)
+                .await
+                .unwrap(),
+            HashMap::from([
+                ([1; 32], (0, 0..2)),
+                ([2; 32], (0, 2..2)),
+                ([3; 32], (1, 6..7)),
+            ])
+        );
+        assert_eq!(
+            seen_events
+                .find_all_from_earlier_reader(&[[1; 32], [3; 32]], 1)
+                .await
+                .unwrap(),
+            HashMap::from([([1; 32], (0, 0..2))])
+        );
+        assert!(seen_events
+            .find_all_from_earlier_reader(&[], 2)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let many_key_digests = (0..4096u16)
+            .map(|index| {
+                let mut key_digest = [0; 32];
+                key_digest[..2].copy_from_slice(&index.to_be_bytes());
+                key_digest
+            })
+            .collect::<Vec<_>>();
+        seen_events
+            .insert_all(
+                1,
+                many_key_digests
+                    .iter()
+                    .step_by(2)
+                    .map(|&key_digest| (key_digest, 0..1)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            seen_events
+                .find_all_from_earlier_reader(&many_key_digests, 2)
+                .await
+                .unwrap()
+                .len(),
+            2048
+        );
+
+        seen_events.close().await;
+    }
+}
@@ -29,14 +29,10 @@
 //     usb_events
 // }
 
-use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tracing::warn;
 
-use crate::{
-    extract_value_from_json_object, models::persisted_event::PersistedEventPayload,
-    require_json_object, require_json_object_value,
-};
+use crate::extract_value_from_json_object;
 
 use super::{DetectedEvent, EventDetector, ProcessedEvent};
 
@@ -77,22 +73,40 @@ impl USBEventDetector {
     }
 }
 
+const INVENTORY_DEVICE_PNP_ADD_EVENT_NAME: &str =
+    "Microsoft.Windows.Inventory.Core.InventoryDevicePnpAdd";
+
 impl EventDetector for USBEventDetector {
+    fn name(&self) -> &'static str {
+        "usb"
+    }
+
+    fn description(&self) -> &'static str {
+        "USB devices that were plugged in"
+    }
+
+    fn version(&self) -> &'static str {
+        "0.1.0"
+    }
+
+    fn source_event_names(&self) -> Vec<&'static str> {
+        vec![INVENTORY_DEVICE_PNP_ADD_EVENT_NAME]
+    }
+
+    fn fork(&self) -> Option<Box<dyn EventDetector>> {
+        Some(Box::new(Self::new()))
+    }
+
     fn process_event(
         &mut self,
         event: &crate::models::persisted_event::PersistedEvent,
-        context: &super::EventTranscriptReadOnlyView,
+        _context: &super::EventTranscriptReadOnlyView,
     ) -> Option<Vec<super::ProcessedEvent>> {
-        if !event.event_name_contains("Microsoft.Windows.Inventory.Core.InventoryDevicePnpAdd") {
+        if !event.event_name_contains(INVENTORY_DEVICE_PNP_ADD_EVENT_NAME) {
             return None;
         }
 
-        let PersistedEventPayload::Parsed { payload } = event.payload() else {
-            return None;
-        };
-
-        let payload_object = require_json_object!(payload);
-        let data = extract_value_from_json_object!(payload_object, "data" => object);
+        let data = &event.envelope().data;
         let class = extract_value_from_json_object!(data, "Class" => str);
         if !class.contains("usb") {
             warn!("Device is of type {}", class);
@@ -110,7 +124,7 @@ impl EventDetector for USBEventDetector {
         );
 
         Some(vec![ProcessedEvent::new_with_random_id(
-            event.timestamp().to_utc(),
+            event,
             USBEvent::Added(usb_event),
         )])
     }
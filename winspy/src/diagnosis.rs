@@ -0,0 +1,453 @@
+//! Discovery of the artifacts in a `C:\ProgramData\Microsoft\Diagnosis` folder.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// First 16 bytes of every SQLite database file.
+const SQLITE_HEADER_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// Files of `DownloadedSettings` that every Diagnosis folder is expected to have.
+const KNOWN_DOWNLOADED_SETTINGS: [&str; 5] = [
+    "utc.privacy.json",
+    "utc.allow.json",
+    "utc.app.json",
+    "utc.tracing.json",
+    "telemetry.ASM-WindowsDefault.json",
+];
+
+/// Kinds of files found in a Diagnosis folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    /// `EventTranscript\EventTranscript.db`: events shown by the Diagnostic Data Viewer.
+    EventTranscript,
+
+    /// `EventStore.db` (also in `ScenariosSqlStore` and `TenantStorage`):
+    /// events waiting to be uploaded.
+    EventStore,
+
+    /// A JSON file of `DownloadedSettings`: telemetry configuration.
+    DownloadedSettings,
+
+    /// `osver.txt`: the version of Windows.
+    OsVersion,
+
+    /// `parse.dat`.
+    ParseData,
+}
+
+impl ArtifactKind {
+    /// Whether winspy reads this kind of artifact (the others are only reported).
+    pub fn is_processed(self) -> bool {
+        matches!(
+            self,
+            Self::EventTranscript | Self::EventStore | Self::DownloadedSettings | Self::OsVersion
+        )
+    }
+}
+
+/// Result of looking for and validating an artifact.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ArtifactStatus {
+    Valid,
+    Invalid { reason: String },
+    Missing,
+}
+
+/// A single artifact of the Diagnosis folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveredArtifact {
+    pub kind: ArtifactKind,
+    pub path: PathBuf,
+
+    #[serde(flatten)]
+    pub status: ArtifactStatus,
+
+    /// Whether the artifact is fed into the analysis.
+    pub processed: bool,
+}
+
+/// All known artifacts of a Diagnosis folder, found or not.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosisFolder {
+    pub root: PathBuf,
+    pub artifacts: Vec<DiscoveredArtifact>,
+}
+
+impl DiagnosisFolder {
+    /// Looks for every known artifact in the Diagnosis folder at `root` and validates it.
+    pub fn discover(root: &Path) -> Result<Self> {
+        if !root.is_dir() {
+            return Err(miette!(
+                "Diagnosis folder {} does not exist or is not a directory.",
+                root.display()
+            ));
+        }
+
+        let mut folder = Self {
+            root: root.to_path_buf(),
+            artifacts: Vec::new(),
+        };
+
+        folder.add_expected(
+            ArtifactKind::EventTranscript,
+            root.join("EventTranscript").join("EventTranscript.db"),
+        );
+
+        // The main event store is always expected, other ones only exist on some machines.
+        folder.add_expected(
+            ArtifactKind::EventStore,
+            root.join("EventStore.db"),
+        );
+        for event_store_path in find_files(root, &|name| name == "EventStore.db")? {
+            if event_store_path.parent() != Some(root) {
+                folder.add_expected(ArtifactKind::EventStore, event_store_path);
+            }
+        }
+
+        let settings_directory = root.join("DownloadedSettings");
+        for file_name in KNOWN_DOWNLOADED_SETTINGS {
+            folder.add_expected(
+                ArtifactKind::DownloadedSettings,
+                settings_directory.join(file_name),
+            );
+        }
+        if settings_directory.is_dir() {
+            for settings_path in find_files(&settings_directory, &|name| {
+                name.ends_with(".json")
+            })? {
+                let is_known = settings_path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| KNOWN_DOWNLOADED_SETTINGS.contains(&name));
+
+                if !is_known {
+                    folder.add_expected(ArtifactKind::DownloadedSettings, settings_path);
+                }
+            }
+        }
+
+        folder.add_expected(ArtifactKind::OsVersion, root.join("osver.txt"));
+        folder.add_expected(ArtifactKind::ParseData, root.join("parse.dat"));
+
+        Ok(folder)
+    }
+
+    fn add_expected(&mut self, kind: ArtifactKind, path: PathBuf) {
+        let status = validate_artifact(kind, &path);
+        let processed = kind.is_processed() && status == ArtifactStatus::Valid;
+
+        self.artifacts.push(DiscoveredArtifact {
+            kind,
+            path,
+            status,
+            processed,
+        });
+    }
+
+    /// Paths of all valid artifacts of the given kind.
+    pub fn valid_artifact_paths(&self, kind: ArtifactKind) -> Vec<PathBuf> {
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.kind == kind && artifact.status == ArtifactStatus::Valid)
+            .map(|artifact| artifact.path.clone())
+            .collect()
+    }
+
+    /// Paths of all artifacts that exist, valid or not.
+    pub fn existing_artifact_paths(&self) -> Vec<PathBuf> {
+        self.artifacts
+            .iter()
+            .filter(|artifact| artifact.status != ArtifactStatus::Missing)
+            .map(|artifact| artifact.path.clone())
+            .collect()
+    }
+
+    /// Logs what was found, what was invalid and what was missing.
+    pub fn log_summary(&self) {
+        for artifact in &self.artifacts {
+            match &artifact.status {
+                ArtifactStatus::Valid => info!(
+                    "Found {:?} artifact {}{}.",
+                    artifact.kind,
+                    artifact.path.display(),
+                    if artifact.processed {
+                        ""
+                    } else {
+                        " (not processed)"
+                    }
+                ),
+                ArtifactStatus::Invalid { reason } => warn!(
+                    "Invalid {:?} artifact {}: {}",
+                    artifact.kind,
+                    artifact.path.display(),
+                    reason
+                ),
+                ArtifactStatus::Missing => warn!(
+                    "Missing {:?} artifact {}.",
+                    artifact.kind,
+                    artifact.path.display()
+                ),
+            }
+        }
+    }
+}
+
+/// Checks that the file at `path` exists and looks like an artifact of the given kind.
+fn validate_artifact(kind: ArtifactKind, path: &Path) -> ArtifactStatus {
+    if !path.exists() {
+        return ArtifactStatus::Missing;
+    }
+
+    let validation = || -> Result<()> {
+        if !path.is_file() {
+            return Err(miette!("not a file"));
+        }
+
+        match kind {
+            ArtifactKind::EventTranscript | ArtifactKind::EventStore => {
+                let mut header = [0u8; 16];
+                File::open(path)
+                    .and_then(|mut file| file.read_exact(&mut header))
+                    .into_diagnostic()
+                    .wrap_err("failed to read the SQLite header")?;
+
+                if &header != SQLITE_HEADER_MAGIC {
+                    return Err(miette!("not an SQLite database"));
+                }
+            }
+            ArtifactKind::DownloadedSettings => {
+                let content = fs::read(path).into_diagnostic()?;
+                serde_json::from_slice::<serde_json::Value>(&content)
+                    .into_diagnostic()
+                    .wrap_err("not valid JSON")?;
+            }
+            ArtifactKind::OsVersion => {
+                let content = fs::read_to_string(path).into_diagnostic()?;
+                if content.trim().is_empty() {
+                    return Err(miette!("file is empty"));
+                }
+            }
+            // The format of `parse.dat` is not known, so any file is accepted.
+            ArtifactKind::ParseData => {}
+        }
+
+        Ok(())
+    };
+
+    match validation() {
+        Ok(()) => ArtifactStatus::Valid,
+        Err(error) => ArtifactStatus::Invalid {
+            reason: error
+                .chain()
+                .map(|cause| cause.to_string())
+                .collect::<Vec<_>>()
+                .join(": "),
+        },
+    }
+}
+
+/// Recursively finds all files under `directory` whose name is accepted by `is_wanted`,
+/// sorted by path.
+///
+/// Symbolic links to directories are not followed, so a link cycle in an evidence tree
+/// cannot make the walk loop (links to files are returned like files).
+pub(crate) fn find_files(
+    directory: &Path,
+    is_wanted: &dyn Fn(&str) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut found_files = Vec::new();
+    let mut pending_directories = vec![directory.to_path_buf()];
+
+    while let Some(current_directory) = pending_directories.pop() {
+        let entries = fs::read_dir(&current_directory)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to list {}.", current_directory.display()))?;
+
+        for entry in entries {
+            let entry = entry.into_diagnostic()?;
+            let path = entry.path();
+
+            if entry.file_type().into_diagnostic()?.is_dir() {
+                pending_directories.push(path);
+            } else if path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_wanted)
+            {
+                found_files.push(path);
+            }
+        }
+    }
+
+    found_files.sort();
+    Ok(found_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_of<'a>(folder: &'a DiagnosisFolder, relative_path: &str) -> &'a ArtifactStatus {
+        let path = folder.root.join(relative_path);
+        &folder
+            .artifacts
+            .iter()
+            .find(|artifact| artifact.path == path)
+            .unwrap_or_else(|| panic!("{relative_path} was not discovered"))
+            .status
+    }
+
+    #[test]
+    fn discovers_artifacts() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../test_data/VM/Diagnosis");
+        let folder = DiagnosisFolder::discover(&root).unwrap();
+
+        let event_store_paths = folder
+            .artifacts
+            .iter()
+            .filter(|artifact| artifact.kind == ArtifactKind::EventStore)
+            .map(|artifact| artifact.path.strip_prefix(&root).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            event_store_paths,
+            [
+                PathBuf::from("EventStore.db"),
+                Path::new("ScenariosSqlStore").join("EventStore.db"),
+                Path::new("TenantStorage")
+                    .join("P-ARIA")
+                    .join("EventStore.db"),
+            ]
+        );
+
+        // Databases are only valid if the repository was checked out with Git LFS.
+        assert!(folder
+            .artifacts
+            .iter()
+            .all(|artifact| artifact.status != ArtifactStatus::Missing));
+
+        for file_name in KNOWN_DOWNLOADED_SETTINGS {
+            assert_eq!(
+                status_of(
+                    &folder,
+                    &format!("DownloadedSettings/{file_name}")
+                ),
+                &ArtifactStatus::Valid
+            );
+        }
+        assert_eq!(
+            status_of(&folder, "osver.txt"),
+            &ArtifactStatus::Valid
+        );
+        assert_eq!(
+            status_of(&folder, "parse.dat"),
+            &ArtifactStatus::Valid
+        );
+        assert_eq!(
+            folder.valid_artifact_paths(ArtifactKind::OsVersion),
+            [root.join("osver.txt")]
+        );
+
+        let parse_data = folder
+            .artifacts
+            .iter()
+            .find(|artifact| artifact.kind == ArtifactKind::ParseData)
+            .unwrap();
+        assert!(!parse_data.processed);
+    }
+
+    #[test]
+    fn reports_missing_and_invalid_artifacts() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::create_dir_all(root.join("EventTranscript")).unwrap();
+        fs::create_dir_all(root.join("DownloadedSettings")).unwrap();
+        fs::create_dir_all(root.join("EventStore.db")).unwrap();
+        fs::write(
+            root.join("EventTranscript").join("EventTranscript.db"),
+            b"version https://git-lfs.github.com/spec/v1",
+        )
+        .unwrap();
+        fs::write(
+            root.join("DownloadedSettings").join("utc.app.json"),
+            b"{",
+        )
+        .unwrap();
+        fs::write(
+            root.join("DownloadedSettings").join("extra.json"),
+            b"{}",
+        )
+        .unwrap();
+        fs::write(root.join("osver.txt"), b" \r\n").unwrap();
+
+        let folder = DiagnosisFolder::discover(root).unwrap();
+        let reason_of = |relative_path| match status_of(&folder, relative_path) {
+            ArtifactStatus::Invalid { reason } => reason.clone(),
+            status => panic!("{relative_path} is {status:?}"),
+        };
+
+        assert_eq!(
+            reason_of("EventTranscript/EventTranscript.db"),
+            "not an SQLite database"
+        );
+        assert_eq!(reason_of("EventStore.db"), "not a file");
+        assert!(reason_of("DownloadedSettings/utc.app.json").starts_with("not valid JSON: "));
+        assert_eq!(reason_of("osver.txt"), "file is empty");
+
+        assert_eq!(
+            status_of(&folder, "DownloadedSettings/utc.allow.json"),
+            &ArtifactStatus::Missing
+        );
+        assert_eq!(
+            status_of(&folder, "parse.dat"),
+            &ArtifactStatus::Missing
+        );
+        assert_eq!(
+            status_of(&folder, "DownloadedSettings/extra.json"),
+            &ArtifactStatus::Valid
+        );
+
+        assert!(folder
+            .valid_artifact_paths(ArtifactKind::EventTranscript)
+            .is_empty());
+        assert_eq!(folder.existing_artifact_paths().len(), 5);
+
+        assert!(DiagnosisFolder::discover(&root.join("osver.txt")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_directory_links() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path();
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::write(
+            root.join("a").join("b").join("EventStore.db"),
+            b"",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(root, root.join("a").join("b").join("cycle")).unwrap();
+        std::os::unix::fs::symlink(
+            root.join("a").join("b").join("EventStore.db"),
+            root.join("a").join("EventStore.db"),
+        )
+        .unwrap();
+
+        let found_files = find_files(root, &|name| name == "EventStore.db").unwrap();
+        assert_eq!(
+            found_files,
+            [
+                root.join("a").join("EventStore.db"),
+                root.join("a").join("b").join("EventStore.db"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,657 @@
+//! Telemetry configuration from the `DownloadedSettings` folder of the Diagnosis folder.
+//!
+//! These files decide which events Windows collects at all, which is needed
+//! to explain why a certain kind of event is absent from the transcript.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use miette::{Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// Prefix of the `utc.app.json` settings that list producers shown in the transcript.
+const TRANSCRIPT_ALLOWED_PRODUCER_PREFIX: &str = "UTC:::EVENTTRANSCRIPT.ALLOWPRODUCERID.";
+
+/// Content of every `DownloadedSettings` JSON file.
+#[derive(Debug, Deserialize)]
+struct RawSettingsFile {
+    #[serde(rename = "queryUrl")]
+    query_url: Option<String>,
+
+    #[serde(default)]
+    settings: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Kinds of files in `DownloadedSettings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsFileKind {
+    /// `telemetry.*.json`: sampling and routing of individual providers and events.
+    Telemetry,
+
+    /// `utc.app.json`: configuration of the telemetry service itself.
+    UtcApp,
+
+    /// `utc.allow.json`: patches of the event allow list.
+    UtcAllow,
+
+    /// `utc.privacy.json`: patches of the privacy configuration.
+    UtcPrivacy,
+
+    /// `utc.tracing.json`: tracing configuration.
+    UtcTracing,
+
+    Other,
+}
+
+impl SettingsFileKind {
+    fn from_file_name(file_name: &str) -> Self {
+        match file_name {
+            "utc.app.json" => Self::UtcApp,
+            "utc.allow.json" => Self::UtcAllow,
+            "utc.privacy.json" => Self::UtcPrivacy,
+            "utc.tracing.json" => Self::UtcTracing,
+            _ if file_name.starts_with("telemetry.") => Self::Telemetry,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// A parsed settings file, without its individual settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsFile {
+    pub path: PathBuf,
+    pub kind: SettingsFileKind,
+    pub query_url: Option<String>,
+    pub number_of_settings: usize,
+}
+
+/// Settings of a provider (or one of its events) from a `telemetry.*.json` file.
+///
+/// Keys of those files have the form `provider:event:privacy tag:setting`,
+/// where an empty event applies to every event of the provider.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EventRule {
+    pub provider: String,
+    pub event: Option<String>,
+    pub privacy_tag: Option<String>,
+
+    /// Percentage of events that are kept (0 drops all of them).
+    pub sample_rate: Option<f64>,
+    pub category_name: Option<String>,
+    pub latency: Option<String>,
+    pub persistence: Option<String>,
+}
+
+impl EventRule {
+    /// Name the rule applies to, in the form of `full_event_name` (or just the provider).
+    pub fn target_name(&self) -> String {
+        match &self.event {
+            Some(event) => format!("{}.{}", self.provider, event),
+            None => self.provider.clone(),
+        }
+    }
+
+    /// Whether the rule applies to an event with the given `full_event_name`.
+    fn applies_to(&self, full_event_name: &str) -> bool {
+        match &self.event {
+            Some(_) => self.target_name() == full_event_name,
+            None => full_event_name
+                .strip_prefix(self.provider.as_str())
+                .is_some_and(|rest| rest.starts_with('.')),
+        }
+    }
+}
+
+/// Whether events of a kind could have been collected, according to the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum CollectionStatus {
+    /// A rule keeps every event.
+    Allowed,
+
+    /// A rule keeps only a percentage of events.
+    Sampled { sample_rate: f64 },
+
+    /// A rule drops every event.
+    Blocked,
+
+    /// No rule applies: events are collected according to the diagnostic data level.
+    NotConfigured,
+}
+
+impl CollectionStatus {
+    /// Combines the sample rates of all rules (e.g. one per privacy tag) for the same target:
+    /// an event is collected if any of them keeps it.
+    fn from_sample_rates(sample_rates: impl Iterator<Item = f64>) -> Self {
+        match sample_rates.reduce(f64::max) {
+            None => Self::NotConfigured,
+            Some(rate) if rate <= 0.0 => Self::Blocked,
+            Some(rate) if rate >= 100.0 => Self::Allowed,
+            Some(sample_rate) => Self::Sampled { sample_rate },
+        }
+    }
+}
+
+/// All typed settings from a `DownloadedSettings` folder.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DownloadedSettings {
+    pub files: Vec<SettingsFile>,
+
+    /// Producers whose events the transcript shows (from `utc.app.json`).
+    pub transcript_allowed_producers: Vec<String>,
+
+    /// Entries of the `PATCH` list of `utc.allow.json`.
+    pub allow_list_patch: Vec<serde_json::Value>,
+
+    /// Entries of the `PATCH` list of `utc.privacy.json`.
+    pub privacy_patch: Vec<serde_json::Value>,
+
+    pub event_rules: Vec<EventRule>,
+}
+
+impl DownloadedSettings {
+    /// Parses all given settings files. Files that cannot be parsed are logged and skipped.
+    pub fn load_from_files(paths: &[PathBuf]) -> Self {
+        let mut settings = Self::default();
+
+        for path in paths {
+            if let Err(error) = settings.load_file(path) {
+                warn!(
+                    "Skipping settings file {}: {:?}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+
+        info!(
+            "Loaded {} settings files with {} event rules.",
+            settings.files.len(),
+            settings.event_rules.len()
+        );
+
+        settings
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<()> {
+        let content = fs::read(path)
+            .into_diagnostic()
+            .wrap_err("Failed to read the settings file.")?;
+        let raw_file: RawSettingsFile = serde_json::from_slice(&content)
+            .into_diagnostic()
+            .wrap_err("Failed to parse the settings file.")?;
+
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let kind = SettingsFileKind::from_file_name(file_name);
+
+        self.files.push(SettingsFile {
+            path: path.to_path_buf(),
+            kind,
+            query_url: raw_file.query_url,
+            number_of_settings: raw_file.settings.len(),
+        });
+
+        let patch_entries = || {
+            raw_file
+                .settings
+                .get("PATCH")
+                .and_then(|patch| patch.as_array())
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        match kind {
+            SettingsFileKind::Telemetry => {
+                self.event_rules
+                    .extend(parse_event_rules(&raw_file.settings));
+            }
+            SettingsFileKind::UtcApp => {
+                for (key, value) in &raw_file.settings {
+                    if key.starts_with(TRANSCRIPT_ALLOWED_PRODUCER_PREFIX) {
+                        if let Some(producer) = value.as_str() {
+                            self.transcript_allowed_producers.push(producer.to_string());
+                        }
+                    }
+                }
+            }
+            SettingsFileKind::UtcAllow => self.allow_list_patch.extend(patch_entries()),
+            SettingsFileKind::UtcPrivacy => self.privacy_patch.extend(patch_entries()),
+            SettingsFileKind::UtcTracing | SettingsFileKind::Other => {}
+        }
+
+        Ok(())
+    }
+
+    /// Whether events with the given `full_event_name` could have been collected.
+    ///
+    /// Rules for the event itself take precedence over rules for its whole provider.
+    pub fn collection_status_of(&self, full_event_name: &str) -> CollectionStatus {
+        let sample_rates_of = |event_specific: bool| {
+            self.event_rules
+                .iter()
+                .filter(move |rule| rule.event.is_some() == event_specific)
+                .filter(move |rule| rule.applies_to(full_event_name))
+                .filter_map(|rule| rule.sample_rate)
+        };
+
+        match CollectionStatus::from_sample_rates(sample_rates_of(true)) {
+            CollectionStatus::NotConfigured => {
+                CollectionStatus::from_sample_rates(sample_rates_of(false))
+            }
+            status => status,
+        }
+    }
+
+    /// Every provider or event with a sampling rule, grouped by its collection status.
+    pub fn targets_by_status(&self) -> CollectionTargets {
+        let mut sample_rates_by_target: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        for rule in &self.event_rules {
+            if let Some(sample_rate) = rule.sample_rate {
+                sample_rates_by_target
+                    .entry(rule.target_name())
+                    .or_default()
+                    .push(sample_rate);
+            }
+        }
+
+        let mut targets = CollectionTargets::default();
+        for (target_name, sample_rates) in sample_rates_by_target {
+            match CollectionStatus::from_sample_rates(sample_rates.into_iter()) {
+                CollectionStatus::Allowed => targets.allowed.push(target_name),
+                CollectionStatus::Blocked => targets.blocked.push(target_name),
+                CollectionStatus::Sampled { sample_rate } => targets.sampled.push(SampledTarget {
+                    name: target_name,
+                    sample_rate,
+                }),
+                CollectionStatus::NotConfigured => {}
+            }
+        }
+
+        targets
+    }
+}
+
+/// Groups the settings of a `telemetry.*.json` file into one rule per provider, event and tag.
+fn parse_event_rules(settings: &serde_json::Map<String, serde_json::Value>) -> Vec<EventRule> {
+    let mut rules: HashMap<(String, String, String), EventRule> = HashMap::new();
+
+    for (key, value) in settings {
+        let Some((target, setting_name)) = key.rsplit_once(':') else {
+            continue;
+        };
+        let mut target_parts = target.splitn(3, ':');
+        let (Some(provider), Some(event), Some(privacy_tag)) = (
+            target_parts.next(),
+            target_parts.next(),
+            target_parts.next(),
+        ) else {
+            continue;
+        };
+
+        let rule = rules
+            .entry((
+                provider.to_string(),
+                event.to_string(),
+                privacy_tag.to_string(),
+            ))
+            .or_insert_with(|| EventRule {
+                provider: provider.to_string(),
+                event: (!event.is_empty()).then(|| event.to_string()),
+                privacy_tag: (!privacy_tag.is_empty()).then(|| privacy_tag.to_string()),
+                ..Default::default()
+            });
+
+        // Values are strings or numbers, and the case of setting names varies.
+        let text_value = match value {
+            serde_json::Value::String(text) => Some(text.clone()),
+            serde_json::Value::Null => None,
+            other => Some(other.to_string()),
+        };
+
+        match setting_name.to_ascii_lowercase().as_str() {
+            "samplerate" => {
+                rule.sample_rate = text_value.and_then(|text| text.trim().parse().ok());
+            }
+            "catname" => rule.category_name = text_value,
+            "latency" => rule.latency = text_value,
+            "persistence" => rule.persistence = text_value,
+            _ => {}
+        }
+    }
+
+    let mut rules = rules.into_values().collect::<Vec<_>>();
+    rules.sort_by_key(|rule| (rule.target_name(), rule.privacy_tag.clone()));
+    rules
+}
+
+/// Providers and events with a sampling rule, by their collection status.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CollectionTargets {
+    pub allowed: Vec<String>,
+    pub sampled: Vec<SampledTarget>,
+    pub blocked: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SampledTarget {
+    pub name: String,
+    pub sample_rate: f64,
+}
+
+/// Collection status of an event a detector looks for.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceEventCoverage {
+    pub event_name: &'static str,
+
+    #[serde(flatten)]
+    pub status: CollectionStatus,
+}
+
+/// Whether the events a detector relies on could have been collected.
+#[derive(Debug, Clone, Serialize)]
+pub struct DetectorCoverage {
+    pub detector: &'static str,
+    pub source_events: Vec<SourceEventCoverage>,
+}
+
+/// The "collection coverage" section of the output.
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectionCoverage {
+    pub settings: DownloadedSettings,
+    pub targets: CollectionTargets,
+    pub detectors: Vec<DetectorCoverage>,
+}
+
+impl CollectionCoverage {
+    /// Annotates every detector (given as its name and the names of its source events)
+    /// with the collection status of its source events.
+    pub fn new(
+        settings: DownloadedSettings,
+        detectors: Vec<(&'static str, Vec<&'static str>)>,
+    ) -> Self {
+        let detectors = detectors
+            .into_iter()
+            .map(
+                |(detector, source_event_names)| DetectorCoverage {
+                    detector,
+                    source_events: source_event_names
+                        .into_iter()
+                        .map(|event_name| SourceEventCoverage {
+                            event_name,
+                            status: settings.collection_status_of(event_name),
+                        })
+                        .collect(),
+                },
+            )
+            .collect();
+
+        Self {
+            targets: settings.targets_by_status(),
+            settings,
+            detectors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Writes the given settings files into the folder.
+    fn write_files(directory: &Path, files: &[(&str, serde_json::Value)]) -> Vec<PathBuf> {
+        files
+            .iter()
+            .map(|(file_name, content)| {
+                let path = directory.join(file_name);
+                fs::write(&path, content.to_string()).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn load(files: &[(&str, serde_json::Value)]) -> DownloadedSettings {
+        let directory = tempfile::tempdir().unwrap();
+        DownloadedSettings::load_from_files(&write_files(directory.path(), files))
+    }
+
+    fn telemetry(settings: serde_json::Value) -> (&'static str, serde_json::Value) {
+        (
+            "telemetry.ASM-WindowsDefault.json",
+            json!({ "queryUrl": "/settings/v2.0/telemetry/UTC", "settings": settings }),
+        )
+    }
+
+    #[test]
+    fn parses_settings_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let broken_path = directory.path().join("utc.tracing.json");
+        fs::write(&broken_path, "{").unwrap();
+
+        let mut paths = vec![broken_path];
+        paths.extend(write_files(
+            directory.path(),
+            &[
+                telemetry(json!({
+                    "Microsoft.Windows.Inventory:::SampleRate": "100",
+                    "Microsoft.Windows.Inventory:::catName": "Inventory",
+                    "Microsoft.Windows.Kernel:Boot:0x1:SAMPLERATE": 0,
+                    "Microsoft.Windows.Kernel:Boot:0x1:Latency": "RealTime",
+                    "not a rule": "1"
+                })),
+                (
+                    "utc.app.json",
+                    json!({ "settings": {
+                        "UTC:::EVENTTRANSCRIPT.ALLOWPRODUCERID.1": "Windows",
+                        "UTC:::EVENTTRANSCRIPT.ALLOWPRODUCERID.2": 2,
+                        "UTC:::OTHER": "Office"
+                    } }),
+                ),
+                (
+                    "utc.allow.json",
+                    json!({ "settings": { "PATCH": [{ "op": "add" }] } }),
+                ),
+                ("utc.privacy.json", json!({ "settings": {} })),
+            ],
+        ));
+        let settings = DownloadedSettings::load_from_files(&paths);
+
+        assert_eq!(
+            settings
+                .files
+                .iter()
+                .map(|file| (file.kind, file.number_of_settings))
+                .collect::<Vec<_>>(),
+            [
+                (SettingsFileKind::Telemetry, 5),
+                (SettingsFileKind::UtcApp, 3),
+                (SettingsFileKind::UtcAllow, 1),
+                (SettingsFileKind::UtcPrivacy, 0),
+            ]
+        );
+        assert_eq!(
+            settings.files[0].query_url.as_deref(),
+            Some("/settings/v2.0/telemetry/UTC")
+        );
+        assert_eq!(settings.transcript_allowed_producers, ["Windows"]);
+        assert_eq!(
+            settings.allow_list_patch,
+            [json!({ "op": "add" })]
+        );
+        assert!(settings.privacy_patch.is_empty());
+
+        assert_eq!(
+            settings.event_rules,
+            [
+                EventRule {
+                    provider: "Microsoft.Windows.Inventory".to_string(),
+                    sample_rate: Some(100.0),
+                    category_name: Some("Inventory".to_string()),
+                    ..Default::default()
+                },
+                EventRule {
+                    provider: "Microsoft.Windows.Kernel".to_string(),
+                    event: Some("Boot".to_string()),
+                    privacy_tag: Some("0x1".to_string()),
+                    sample_rate: Some(0.0),
+                    latency: Some("RealTime".to_string()),
+                    ..Default::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn combines_sample_rates() {
+        let status_of =
+            |sample_rates: &[f64]| CollectionStatus::from_sample_rates(sample_rates.iter().copied());
+
+        assert_eq!(status_of(&[]), CollectionStatus::NotConfigured);
+        assert_eq!(status_of(&[0.0]), CollectionStatus::Blocked);
+        assert_eq!(
+            status_of(&[0.0, 100.0]),
+            CollectionStatus::Allowed
+        );
+        assert_eq!(
+            status_of(&[10.0, 0.0, 25.0]),
+            CollectionStatus::Sampled { sample_rate: 25.0 }
+        );
+        assert_eq!(status_of(&[150.0]), CollectionStatus::Allowed);
+    }
+
+    #[test]
+    fn prefers_event_rules_over_provider_rules() {
+        let settings = load(&[telemetry(json!({
+            "Microsoft.Windows.Allowed:::SampleRate": "100",
+            "Microsoft.Windows.Allowed:Dropped::SampleRate": "0",
+            "Microsoft.Windows.Blocked:::SampleRate": "0",
+            "Microsoft.Windows.Blocked:Sampled:0x1:SampleRate": "5",
+            "Microsoft.Windows.Blocked:Sampled:0x2:SampleRate": "20",
+            "Microsoft.Windows.Blocked:Unsampled::Latency": "Normal"
+        }))]);
+
+        let status_of = |full_event_name| settings.collection_status_of(full_event_name);
+        assert_eq!(
+            status_of("Microsoft.Windows.Allowed.Kept"),
+            CollectionStatus::Allowed
+        );
+        assert_eq!(
+            status_of("Microsoft.Windows.Allowed.Dropped"),
+            CollectionStatus::Blocked
+        );
+        assert_eq!(
+            status_of("Microsoft.Windows.Blocked.Sampled"),
+            CollectionStatus::Sampled { sample_rate: 20.0 }
+        );
+        assert_eq!(
+            status_of("Microsoft.Windows.Blocked.Unsampled"),
+            CollectionStatus::Blocked
+        );
+        assert_eq!(
+            status_of("Microsoft.Windows.AllowedNot.Event"),
+            CollectionStatus::NotConfigured
+        );
+
+        let targets = settings.targets_by_status();
+        assert_eq!(targets.allowed, ["Microsoft.Windows.Allowed"]);
+        assert_eq!(
+            targets.blocked,
+            [
+                "Microsoft.Windows.Allowed.Dropped",
+                "Microsoft.Windows.Blocked"
+            ]
+        );
+        assert_eq!(
+            targets
+                .sampled
+                .iter()
+                .map(|target| (target.name.as_str(), target.sample_rate))
+                .collect::<Vec<_>>(),
+            [("Microsoft.Windows.Blocked.Sampled", 20.0)]
+        );
+    }
+
+    #[test]
+    fn annotates_detectors_with_their_coverage() {
+        let settings = load(&[telemetry(json!({
+            "Microsoft.Windows.Battery:::SampleRate": "0",
+            "Microsoft.Windows.Usb:Inserted::SampleRate": "50"
+        }))]);
+
+        let coverage = CollectionCoverage::new(
+            settings,
+            vec![
+                ("battery", vec!["Microsoft.Windows.Battery.Level"]),
+                (
+                    "usb",
+                    vec![
+                        "Microsoft.Windows.Usb.Inserted",
+                        "Microsoft.Windows.Usb.Removed",
+                    ],
+                ),
+            ],
+        );
+
+        let detectors = coverage
+            .detectors
+            .iter()
+            .map(|detector| {
+                (
+                    detector.detector,
+                    detector
+                        .source_events
+                        .iter()
+                        .map(|source_event| (source_event.event_name, source_event.status))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            detectors,
+            [
+                (
+                    "battery",
+                    vec![(
+                        "Microsoft.Windows.Battery.Level",
+                        CollectionStatus::Blocked
+                    )]
+                ),
+                (
+                    "usb",
+                    vec![
+                        (
+                            "Microsoft.Windows.Usb.Inserted",
+                            CollectionStatus::Sampled { sample_rate: 50.0 }
+                        ),
+                        (
+                            "Microsoft.Windows.Usb.Removed",
+                            CollectionStatus::NotConfigured
+                        ),
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(
+            coverage.targets.blocked,
+            ["Microsoft.Windows.Battery"]
+        );
+
+        assert_eq!(
+            serde_json::to_value(&coverage.detectors[1].source_events[0]).unwrap(),
+            json!({
+                "event_name": "Microsoft.Windows.Usb.Inserted",
+                "status": "sampled",
+                "sample_rate": 50.0
+            })
+        );
+    }
+}
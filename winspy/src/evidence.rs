@@ -0,0 +1,277 @@
+//! Chain-of-custody helpers: hashing evidence files before and after processing.
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use md5::Md5;
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+/// Suffixes of files SQLite may keep next to a database.
+const SQLITE_SIDECAR_SUFFIXES: [&str; 3] = ["-wal", "-shm", "-journal"];
+
+/// Size of the buffer evidence files are hashed through.
+const HASHING_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Hashes of a single evidence file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct EvidenceFileHashes {
+    pub path: PathBuf,
+    pub size_in_bytes: u64,
+    pub sha256: String,
+    pub md5: String,
+}
+
+impl EvidenceFileHashes {
+    /// Reads the entire file at `path` and computes its SHA-256 and MD5 hashes.
+    pub fn compute(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open evidence file {}.", path.display()))?;
+
+        let mut sha256 = Sha256::new();
+        let mut md5 = Md5::new();
+        let mut size_in_bytes: u64 = 0;
+
+        let mut buffer = vec![0u8; HASHING_BUFFER_SIZE];
+        loop {
+            let bytes_read = file
+                .read(&mut buffer)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read evidence file {}.", path.display()))?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            sha256.update(&buffer[..bytes_read]);
+            md5.update(&buffer[..bytes_read]);
+            size_in_bytes += bytes_read as u64;
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            size_in_bytes,
+            sha256: format!("{:x}", sha256.finalize()),
+            md5: format!("{:x}", md5.finalize()),
+        })
+    }
+}
+
+/// Hashes of SQLite databases and all of their sidecar files at a point in time.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct EvidenceSnapshot {
+    pub files: Vec<EvidenceFileHashes>,
+
+    /// Databases the snapshot was taken of (sidecar files are found again from these).
+    #[serde(skip)]
+    database_paths: Vec<PathBuf>,
+}
+
+impl EvidenceSnapshot {
+    /// Hashes every database in `database_paths` and their
+    /// `-wal`, `-shm` and `-journal` files (if they exist).
+    pub fn take(database_paths: &[PathBuf]) -> Result<Self> {
+        let mut files = Vec::new();
+
+        for database_path in database_paths {
+            files.push(EvidenceFileHashes::compute(database_path)?);
+
+            for sidecar_path in Self::sidecar_paths(database_path) {
+                if sidecar_path.is_file() {
+                    files.push(EvidenceFileHashes::compute(&sidecar_path)?);
+                }
+            }
+        }
+
+        Ok(Self {
+            files,
+            database_paths: database_paths.to_vec(),
+        })
+    }
+
+    fn sidecar_paths(database_path: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+        SQLITE_SIDECAR_SUFFIXES.iter().map(|suffix| {
+            let mut sidecar_path = database_path.as_os_str().to_owned();
+            sidecar_path.push(suffix);
+            PathBuf::from(sidecar_path)
+        })
+    }
+
+    /// Hashes the same files again and returns the new snapshot.
+    ///
+    /// Fails if any file has changed, disappeared or if a new sidecar file has appeared.
+    pub fn verify_unchanged(&self) -> Result<Self> {
+        if self.database_paths.is_empty() {
+            return Err(miette!("Evidence snapshot contains no files."));
+        }
+
+        let current_snapshot = Self::take(&self.database_paths)
+            .wrap_err("Failed to re-hash evidence after processing.")?;
+
+        let mut discrepancies = Vec::new();
+
+        for before in &self.files {
+            match current_snapshot
+                .files
+                .iter()
+                .find(|after| after.path == before.path)
+            {
+                None => discrepancies.push(format!("{} disappeared", before.path.display())),
+                Some(after) if after != before => discrepancies.push(format!(
+                    "{} changed (SHA-256 {} -> {}, {} -> {} bytes)",
+                    before.path.display(),
+                    before.sha256,
+                    after.sha256,
+                    before.size_in_bytes,
+                    after.size_in_bytes
+                )),
+                Some(_) => {}
+            }
+        }
+
+        for after in &current_snapshot.files {
+            if !self.files.iter().any(|before| before.path == after.path) {
+                discrepancies.push(format!("{} appeared", after.path.display()));
+            }
+        }
+
+        if !discrepancies.is_empty() {
+            return Err(miette!(
+                "Evidence was modified while it was being processed: {}.",
+                discrepancies.join("; ")
+            ));
+        }
+
+        Ok(current_snapshot)
+    }
+}
+
+/// Checks that the evidence is unchanged when processing ends, whether it succeeds or not.
+///
+/// [`EvidenceGuard::finish`] verifies the evidence after a successful run. If the guard is
+/// dropped without it (processing failed part of the way), the evidence is verified anyway
+/// and the outcome is logged, as the error being returned cannot carry it.
+pub struct EvidenceGuard {
+    before_processing: Option<EvidenceSnapshot>,
+}
+
+impl EvidenceGuard {
+    pub fn new(before_processing: EvidenceSnapshot) -> Self {
+        Self {
+            before_processing: Some(before_processing),
+        }
+    }
+
+    /// Verifies the evidence and returns the snapshots from before and after processing.
+    pub fn finish(mut self) -> Result<(EvidenceSnapshot, EvidenceSnapshot)> {
+        // PANIC SAFETY: the snapshot is only taken out here and in `drop`.
+        let before_processing = self.before_processing.take().unwrap();
+        let after_processing = before_processing.verify_unchanged()?;
+
+        Ok((before_processing, after_processing))
+    }
+}
+
+impl Drop for EvidenceGuard {
+    fn drop(&mut self) {
+        let Some(before_processing) = self.before_processing.take() else {
+            return;
+        };
+
+        match before_processing.verify_unchanged() {
+            Ok(_) => info!("Evidence is unchanged after processing stopped early."),
+            Err(error) => error!(
+                "Evidence integrity check failed after processing stopped early: {:?}",
+                error
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A database with a write-ahead log, in a new temporary directory.
+    fn database_with_wal() -> (tempfile::TempDir, PathBuf) {
+        let directory = tempfile::tempdir().unwrap();
+        let database_path = directory.path().join("EventTranscript.db");
+        fs::write(&database_path, b"SQLite format 3\0database").unwrap();
+        fs::write(
+            directory.path().join("EventTranscript.db-wal"),
+            b"frames",
+        )
+        .unwrap();
+
+        (directory, database_path)
+    }
+
+    #[test]
+    fn hashes_databases_and_sidecars() {
+        let (_directory, database_path) = database_with_wal();
+
+        let snapshot = EvidenceSnapshot::take(std::slice::from_ref(&database_path)).unwrap();
+        assert_eq!(snapshot.files.len(), 2);
+        assert_eq!(snapshot.files[0].path, database_path);
+        assert_eq!(snapshot.files[1].size_in_bytes, 6);
+        assert_eq!(
+            snapshot.files[1].sha256,
+            format!("{:x}", Sha256::digest(b"frames"))
+        );
+        assert_eq!(
+            snapshot.files[1].md5,
+            format!("{:x}", Md5::digest(b"frames"))
+        );
+    }
+
+    #[test]
+    fn accepts_unchanged_evidence() {
+        let (_directory, database_path) = database_with_wal();
+
+        let snapshot = EvidenceSnapshot::take(&[database_path]).unwrap();
+        assert_eq!(snapshot.verify_unchanged().unwrap(), snapshot);
+
+        let (before_processing, after_processing) =
+            EvidenceGuard::new(snapshot.clone()).finish().unwrap();
+        assert_eq!(before_processing, snapshot);
+        assert_eq!(after_processing, snapshot);
+    }
+
+    #[test]
+    fn rejects_modified_sidecars() {
+        let (_directory, database_path) = database_with_wal();
+        let wal_path = database_path.with_extension("db-wal");
+        let journal_path = database_path.with_extension("db-journal");
+
+        let snapshot = EvidenceSnapshot::take(&[database_path]).unwrap();
+
+        fs::write(&wal_path, b"frames and more frames").unwrap();
+        let error = snapshot.verify_unchanged().unwrap_err().to_string();
+        assert!(
+            error.contains("EventTranscript.db-wal changed"),
+            "{error}"
+        );
+        assert!(error.contains("6 -> 22 bytes"), "{error}");
+        assert!(EvidenceGuard::new(snapshot.clone()).finish().is_err());
+
+        fs::remove_file(&wal_path).unwrap();
+        fs::write(&journal_path, b"journal").unwrap();
+        let error = snapshot.verify_unchanged().unwrap_err().to_string();
+        assert!(
+            error.contains("EventTranscript.db-wal disappeared"),
+            "{error}"
+        );
+        assert!(
+            error.contains("EventTranscript.db-journal appeared"),
+            "{error}"
+        );
+    }
+}
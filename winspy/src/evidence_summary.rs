@@ -0,0 +1,349 @@
+//! Which kinds of data the events hold: events counted per tag (e.g. "Browsing History")
+//! and per category, as described by the transcripts.
+
+use std::{collections::HashMap, hash::Hash};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    detectors::EventTranscriptReadOnlyView,
+    models::{
+        category::CategoryId, persisted_event::PersistedEvent, tag_description::TagDescriptionId,
+    },
+};
+
+/// Number of most frequent event names listed for every tag and category.
+const NUMBER_OF_TOP_EVENT_NAMES: usize = 5;
+
+/// How often an event name occurs within a tag or category.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventNameCount {
+    pub event_name: String,
+    pub number_of_events: u64,
+}
+
+/// Events of a single tag or category.
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupSummary {
+    pub id: i64,
+
+    /// Name from the transcript (`None` if no transcript describes the ID).
+    pub name: Option<String>,
+
+    /// Description of a tag (categories have none).
+    pub description: Option<String>,
+    pub number_of_events: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub top_event_names: Vec<EventNameCount>,
+}
+
+/// Events per tag and per category, the largest groups first.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EvidenceSummary {
+    pub tags: Vec<GroupSummary>,
+    pub categories: Vec<GroupSummary>,
+
+    /// Events without any tag (e.g. all events of an event store).
+    pub number_of_untagged_events: u64,
+}
+
+impl EvidenceSummary {
+    /// Prints the summary as two tables.
+    pub fn print_tables(&self) {
+        for (title, groups) in [("Tag", &self.tags), ("Category", &self.categories)] {
+            println!(
+                "{:<48} {:>10}  {:<20}  {:<20}  Top event",
+                title, "Events", "First seen", "Last seen"
+            );
+
+            for group in groups {
+                let name = group
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("(unknown {})", group.id));
+
+                println!(
+                    "{:<48} {:>10}  {:<20}  {:<20}  {}",
+                    name,
+                    group.number_of_events,
+                    group.first_seen.format("%Y-%m-%d %H:%M:%S"),
+                    group.last_seen.format("%Y-%m-%d %H:%M:%S"),
+                    group
+                        .top_event_names
+                        .first()
+                        .map(|top| top.event_name.as_str())
+                        .unwrap_or_default()
+                );
+            }
+
+            println!();
+        }
+
+        println!(
+            "Events without tags: {}",
+            self.number_of_untagged_events
+        );
+    }
+}
+
+/// Events of a single tag or category, while they are being counted.
+#[derive(Debug)]
+struct GroupCounts {
+    number_of_events: u64,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    event_names: HashMap<String, u64>,
+}
+
+impl GroupCounts {
+    fn new(timestamp: DateTime<Utc>) -> Self {
+        Self {
+            number_of_events: 0,
+            first_seen: timestamp,
+            last_seen: timestamp,
+            event_names: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, event: &PersistedEvent) {
+        let timestamp = *event.timestamp();
+
+        self.number_of_events += 1;
+        self.first_seen = self.first_seen.min(timestamp);
+        self.last_seen = self.last_seen.max(timestamp);
+        *self
+            .event_names
+            .entry(event.event_name().to_string())
+            .or_default() += 1;
+    }
+
+    fn into_summary(
+        self,
+        id: i64,
+        name: Option<String>,
+        description: Option<String>,
+    ) -> GroupSummary {
+        let mut top_event_names = self
+            .event_names
+            .into_iter()
+            .map(|(event_name, number_of_events)| EventNameCount {
+                event_name,
+                number_of_events,
+            })
+            .collect::<Vec<_>>();
+        top_event_names.sort_by(|first, second| {
+            second
+                .number_of_events
+                .cmp(&first.number_of_events)
+                .then_with(|| first.event_name.cmp(&second.event_name))
+        });
+        top_event_names.truncate(NUMBER_OF_TOP_EVENT_NAMES);
+
+        GroupSummary {
+            id,
+            name,
+            description,
+            number_of_events: self.number_of_events,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+            top_event_names,
+        }
+    }
+}
+
+/// Counts persisted events per tag and per category.
+#[derive(Debug, Default)]
+pub struct EvidenceSummaryCollector {
+    tags: HashMap<TagDescriptionId, GroupCounts>,
+    categories: HashMap<CategoryId, GroupCounts>,
+    number_of_untagged_events: u64,
+}
+
+impl EvidenceSummaryCollector {
+    pub fn observe(&mut self, event: &PersistedEvent) {
+        if event.tag_description_ids().is_empty() {
+            self.number_of_untagged_events += 1;
+        }
+
+        add_to_groups(&mut self.tags, event.tag_description_ids(), event);
+        add_to_groups(&mut self.categories, event.category_ids(), event);
+    }
+
+    /// Names the tags and categories and orders them by their number of events.
+    pub fn into_summary(self, context: &EventTranscriptReadOnlyView) -> EvidenceSummary {
+        let mut tags = self
+            .tags
+            .into_iter()
+            .map(|(tag_id, counts)| {
+                let tag = context.tag_by_id(tag_id);
+                counts.into_summary(
+                    tag_id.value(),
+                    tag.map(|tag| tag.name().to_string()),
+                    tag.map(|tag| tag.description().to_string()),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut categories = self
+            .categories
+            .into_iter()
+            .map(|(category_id, counts)| {
+                let category = context.category_by_id(category_id);
+                counts.into_summary(
+                    category_id.value(),
+                    category.map(|category| category.name().to_string()),
+                    None,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for groups in [&mut tags, &mut categories] {
+            groups.sort_by(|first, second| {
+                second
+                    .number_of_events
+                    .cmp(&first.number_of_events)
+                    .then_with(|| first.id.cmp(&second.id))
+            });
+        }
+
+        EvidenceSummary {
+            tags,
+            categories,
+            number_of_untagged_events: self.number_of_untagged_events,
+        }
+    }
+}
+
+fn add_to_groups<Id>(groups: &mut HashMap<Id, GroupCounts>, ids: &[Id], event: &PersistedEvent)
+where
+    Id: Copy + Eq + Hash,
+{
+    for &id in ids {
+        groups
+            .entry(id)
+            .or_insert_with(|| GroupCounts::new(*event.timestamp()))
+            .add(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use serde_json::json;
+
+    use super::*;
+    use crate::models::{
+        category::Category,
+        producer::ProducerId,
+        tag_description::{TagDescription, TagDescriptionKey},
+    };
+
+    fn event(seconds: i64, event_name: &str, tags: &[i64], categories: &[i64]) -> PersistedEvent {
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+
+        PersistedEvent::for_tests(
+            "S-1-5-21-device",
+            event_name,
+            start + TimeDelta::seconds(seconds),
+            json!({}),
+        )
+        .with_groups(
+            tags.iter().copied().map(TagDescriptionId::new).collect(),
+            categories.iter().copied().map(CategoryId::new).collect(),
+        )
+    }
+
+    #[test]
+    fn counts_events_per_tag_and_category() {
+        let mut collector = EvidenceSummaryCollector::default();
+        for event in [
+            event(30, "Browse", &[1], &[10]),
+            event(10, "Browse", &[1, 2], &[10]),
+            event(20, "Search", &[2], &[]),
+            event(40, "Install", &[], &[11]),
+            event(50, "Install", &[], &[]),
+        ] {
+            collector.observe(&event);
+        }
+
+        let tag = TagDescription::new(
+            TagDescriptionId::new(1),
+            "Browsing History".to_string(),
+            "Websites that were visited.".to_string(),
+            "en-US".to_string(),
+        );
+        let tag_locales = HashMap::from([(TagDescriptionId::new(1), tag.key())]);
+        let tags = HashMap::<TagDescriptionKey, TagDescription>::from([(tag.key(), tag)]);
+        let categories = HashMap::from([(
+            CategoryId::new(10),
+            Category::new(
+                CategoryId::new(10),
+                "Browsing".to_string(),
+                ProducerId::new(1),
+            ),
+        )]);
+        let producers = HashMap::new();
+        let context =
+            EventTranscriptReadOnlyView::for_tests(&tags, &tag_locales, &producers, &categories);
+
+        let summary = collector.into_summary(&context);
+        assert_eq!(summary.number_of_untagged_events, 2);
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+        let groups_of = |groups: &[GroupSummary]| {
+            groups
+                .iter()
+                .map(|group| {
+                    (
+                        group.id,
+                        group.name.clone(),
+                        group.number_of_events,
+                        (group.first_seen - start).num_seconds(),
+                        (group.last_seen - start).num_seconds(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            groups_of(&summary.tags),
+            [
+                (1, Some("Browsing History".to_string()), 2, 10, 30),
+                (2, None, 2, 10, 20),
+            ]
+        );
+        assert_eq!(
+            summary.tags[0].description.as_deref(),
+            Some("Websites that were visited.")
+        );
+        assert_eq!(
+            groups_of(&summary.categories),
+            [
+                (10, Some("Browsing".to_string()), 2, 10, 30),
+                (11, None, 1, 40, 40),
+            ]
+        );
+        assert_eq!(summary.categories[0].description, None);
+    }
+
+    #[test]
+    fn orders_top_event_names_by_count_then_name() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+        let mut counts = GroupCounts::new(start);
+        for event_name in ["F", "E", "D", "C", "B", "A", "A", "E", "G", "G", "G"] {
+            counts.add(&event(0, event_name, &[1], &[]));
+        }
+
+        let summary = counts.into_summary(1, None, None);
+        assert_eq!(summary.number_of_events, 11);
+        assert_eq!(
+            summary
+                .top_event_names
+                .iter()
+                .map(|top| (top.event_name.as_str(), top.number_of_events))
+                .collect::<Vec<_>>(),
+            [("G", 3), ("A", 2), ("E", 2), ("B", 1), ("C", 1)]
+        );
+    }
+}
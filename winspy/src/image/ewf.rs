@@ -0,0 +1,786 @@
+//! Expert Witness Format (EWF-E01, as written by EnCase and FTK Imager) images.
+//!
+//! Follows <https://github.com/libyal/libewf/blob/main/documentation/Expert%20Witness%20Compression%20Format%20(EWF).asciidoc>.
+//! Only what is needed to read the media is parsed: the volume, table, hash and digest sections.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use md5::{Digest, Md5};
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::Serialize;
+use sha1::Sha1;
+use tracing::info;
+
+use super::DiskImage;
+
+/// Signature at the start of every segment file.
+pub const EWF_SIGNATURE: &[u8; 8] = b"EVF\x09\x0d\x0a\xff\x00";
+
+const FILE_HEADER_SIZE: u64 = 13;
+const SECTION_DESCRIPTOR_SIZE: usize = 76;
+
+/// Size of the header of a table section, before its entries.
+const TABLE_HEADER_SIZE: usize = 24;
+
+/// Set in a table entry if the chunk is compressed (the other bits are its offset).
+const TABLE_ENTRY_COMPRESSED: u32 = 0x8000_0000;
+
+/// Upper bound on the number of segment files, as allowed by the naming scheme (E01 to ZZZ).
+const MAX_SEGMENTS: u16 = 14971;
+
+const ADLER32_MODULUS: u32 = 65521;
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    // Sums stay below 2^32 for blocks of this size before they have to be reduced.
+    for block in bytes.chunks(5552) {
+        for &byte in block {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= ADLER32_MODULUS;
+        b %= ADLER32_MODULUS;
+    }
+
+    (b << 16) | a
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Extension of the segment file with the given (1-based) number:
+/// `E01` to `E99`, then `EAA` to `EZZ`, `FAA` and so on.
+fn segment_extension(segment_number: u16, lowercase: bool) -> String {
+    let extension = if segment_number < 100 {
+        format!("E{segment_number:02}")
+    } else {
+        let index = u32::from(segment_number - 100);
+        [
+            char::from_u32('E' as u32 + index / (26 * 26)),
+            char::from_u32('A' as u32 + (index / 26) % 26),
+            char::from_u32('A' as u32 + index % 26),
+        ]
+        .into_iter()
+        .map(|character| character.unwrap_or('?'))
+        .collect()
+    };
+
+    if lowercase {
+        extension.to_lowercase()
+    } else {
+        extension
+    }
+}
+
+/// Finds all segment files of the image, starting from the first one (`.E01`).
+pub fn find_segment_paths(first_segment_path: &Path) -> Result<Vec<PathBuf>> {
+    let extension = first_segment_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    if !extension.eq_ignore_ascii_case("E01") {
+        return Err(miette!(
+            "Expected the first segment of the image (.E01), got {}.",
+            first_segment_path.display()
+        ));
+    }
+
+    let lowercase = extension == "e01";
+    let mut segment_paths = vec![first_segment_path.to_path_buf()];
+
+    for segment_number in 2..=MAX_SEGMENTS {
+        let segment_path =
+            first_segment_path.with_extension(segment_extension(segment_number, lowercase));
+        if !segment_path.is_file() {
+            break;
+        }
+        segment_paths.push(segment_path);
+    }
+
+    Ok(segment_paths)
+}
+
+/// Where a chunk of the media is stored.
+#[derive(Debug, Clone, Copy)]
+struct ChunkLocation {
+    segment_index: usize,
+    offset: u64,
+
+    /// Stored size, including the checksum of uncompressed chunks.
+    stored_size: u64,
+    is_compressed: bool,
+}
+
+/// A section of a segment file.
+struct SectionDescriptor {
+    section_type: String,
+    offset: u64,
+    next_offset: u64,
+    size: u64,
+}
+
+impl SectionDescriptor {
+    fn read(file: &mut File, offset: u64) -> Result<Self> {
+        let mut bytes = [0u8; SECTION_DESCRIPTOR_SIZE];
+        file.seek(SeekFrom::Start(offset)).into_diagnostic()?;
+        file.read_exact(&mut bytes)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read section descriptor at offset {offset}."))?;
+
+        if adler32(&bytes[..72]) != read_u32(&bytes, 72) {
+            return Err(miette!(
+                "Checksum mismatch of section descriptor at offset {}.",
+                offset
+            ));
+        }
+
+        let section_type = String::from_utf8_lossy(&bytes[..16])
+            .trim_end_matches('\0')
+            .to_string();
+
+        Ok(Self {
+            section_type,
+            offset,
+            next_offset: read_u64(&bytes, 16),
+            size: read_u64(&bytes, 24),
+        })
+    }
+
+    /// Reads the data of the section (everything after the descriptor).
+    fn read_data(&self, file: &mut File) -> Result<Vec<u8>> {
+        let data_size = self
+            .size
+            .checked_sub(SECTION_DESCRIPTOR_SIZE as u64)
+            .ok_or_else(|| miette!("Section {} is too small.", self.section_type))?;
+
+        let mut data = vec![0u8; data_size as usize];
+        file.seek(SeekFrom::Start(
+            self.offset + SECTION_DESCRIPTOR_SIZE as u64,
+        ))
+        .into_diagnostic()?;
+        file.read_exact(&mut data)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read section {}.", self.section_type))?;
+
+        Ok(data)
+    }
+}
+
+/// Hashes of the media, as stored in the image and as computed while verifying it.
+#[derive(Debug, Clone, Serialize)]
+pub struct EwfMetadata {
+    pub segments: Vec<PathBuf>,
+    pub media_size: u64,
+    pub bytes_per_sector: u32,
+    pub stored_md5: Option<String>,
+    pub stored_sha1: Option<String>,
+    pub computed_md5: String,
+    pub computed_sha1: String,
+}
+
+/// An EWF image, read through its chunk tables.
+pub struct EwfImage {
+    segments: Vec<Mutex<File>>,
+    segment_paths: Vec<PathBuf>,
+    chunks: Vec<ChunkLocation>,
+    chunk_size: u64,
+    media_size: u64,
+    bytes_per_sector: u32,
+    stored_md5: Option<[u8; 16]>,
+    stored_sha1: Option<[u8; 20]>,
+
+    /// The most recently read chunk, as reads are usually sequential within a chunk.
+    cached_chunk: Mutex<Option<(usize, Vec<u8>)>>,
+}
+
+impl EwfImage {
+    /// Opens the image whose first segment file (`.E01`) is at `path`.
+    pub fn open(path: &Path) -> Result<Self> {
+        let segment_paths = find_segment_paths(path)?;
+
+        let mut image = Self {
+            segments: Vec::with_capacity(segment_paths.len()),
+            segment_paths: Vec::new(),
+            chunks: Vec::new(),
+            chunk_size: 0,
+            media_size: 0,
+            bytes_per_sector: 0,
+            stored_md5: None,
+            stored_sha1: None,
+            cached_chunk: Mutex::new(None),
+        };
+
+        for (segment_index, segment_path) in segment_paths.iter().enumerate() {
+            let file = File::open(segment_path)
+                .into_diagnostic()
+                .wrap_err_with(|| {
+                    format!(
+                        "Failed to open segment {}.",
+                        segment_path.display()
+                    )
+                })?;
+
+            let is_last = image.read_segment(segment_index, file).wrap_err_with(|| {
+                format!(
+                    "Failed to read segment {}.",
+                    segment_path.display()
+                )
+            })?;
+
+            if is_last {
+                break;
+            }
+        }
+
+        image.segment_paths = segment_paths[..image.segments.len()].to_vec();
+
+        if image.chunk_size == 0 {
+            return Err(miette!("Image has no volume section."));
+        }
+
+        let expected_chunks = image.media_size.div_ceil(image.chunk_size);
+        if (image.chunks.len() as u64) < expected_chunks {
+            return Err(miette!(
+                "Image is incomplete: {} of {} chunks found (missing segment files?).",
+                image.chunks.len(),
+                expected_chunks
+            ));
+        }
+
+        Ok(image)
+    }
+
+    /// Reads the sections of a segment file. Returns whether it is the last segment.
+    fn read_segment(&mut self, segment_index: usize, mut file: File) -> Result<bool> {
+        let mut header = [0u8; FILE_HEADER_SIZE as usize];
+        file.read_exact(&mut header).into_diagnostic()?;
+
+        if &header[..8] != EWF_SIGNATURE {
+            return Err(miette!(
+                "Not an EWF segment file (signature mismatch)."
+            ));
+        }
+
+        let segment_number = u16::from_le_bytes([header[9], header[10]]);
+        if usize::from(segment_number) != segment_index + 1 {
+            return Err(miette!(
+                "Segment file has number {}, expected {}.",
+                segment_number,
+                segment_index + 1
+            ));
+        }
+
+        let file_size = file.metadata().into_diagnostic()?.len();
+        let mut offset = FILE_HEADER_SIZE;
+
+        // End of the most recent `sectors` section, where the last chunk of a table ends.
+        let mut sectors_end = None;
+        let mut is_last = false;
+
+        loop {
+            let section = SectionDescriptor::read(&mut file, offset)?;
+
+            match section.section_type.as_str() {
+                "volume" | "disk" => {
+                    let data = section.read_data(&mut file)?;
+                    if data.len() < 24 {
+                        return Err(miette!("Volume section is too small."));
+                    }
+
+                    let sectors_per_chunk = read_u32(&data, 8);
+                    self.bytes_per_sector = read_u32(&data, 12);
+                    self.chunk_size =
+                        u64::from(sectors_per_chunk) * u64::from(self.bytes_per_sector);
+                    self.media_size = read_u64(&data, 16) * u64::from(self.bytes_per_sector);
+                }
+                "sectors" => sectors_end = Some(section.offset + section.size),
+                // `table2` is a copy of `table` and is ignored.
+                "table" => {
+                    let data = section.read_data(&mut file)?;
+                    self.read_table(
+                        segment_index,
+                        &data,
+                        sectors_end.unwrap_or(section.offset),
+                    )?;
+                }
+                "hash" => {
+                    let data = section.read_data(&mut file)?;
+                    if data.len() >= 16 {
+                        self.stored_md5 = Some(data[..16].try_into().unwrap());
+                    }
+                }
+                "digest" => {
+                    let data = section.read_data(&mut file)?;
+                    if data.len() >= 36 {
+                        self.stored_md5 = Some(data[..16].try_into().unwrap());
+                        self.stored_sha1 = Some(data[16..36].try_into().unwrap());
+                    }
+                }
+                "done" => is_last = true,
+                _ => {}
+            }
+
+            // `next` and `done` point to themselves.
+            if section.next_offset <= offset || section.next_offset >= file_size {
+                break;
+            }
+            offset = section.next_offset;
+        }
+
+        self.segments.push(Mutex::new(file));
+        Ok(is_last)
+    }
+
+    /// Adds the chunks listed in a table section.
+    fn read_table(&mut self, segment_index: usize, data: &[u8], chunks_end: u64) -> Result<()> {
+        if data.len() < TABLE_HEADER_SIZE {
+            return Err(miette!("Table section is too small."));
+        }
+        if adler32(&data[..20]) != read_u32(data, 20) {
+            return Err(miette!(
+                "Checksum mismatch of table section header."
+            ));
+        }
+
+        let number_of_entries = read_u32(data, 0) as usize;
+        let base_offset = read_u64(data, 8);
+
+        let entries = data
+            .get(TABLE_HEADER_SIZE..TABLE_HEADER_SIZE + number_of_entries * 4)
+            .ok_or_else(|| miette!("Table section is truncated."))?;
+
+        let entries = entries
+            .chunks_exact(4)
+            .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+            .collect::<Vec<_>>();
+
+        for (entry_index, &entry) in entries.iter().enumerate() {
+            let offset = base_offset + u64::from(entry & !TABLE_ENTRY_COMPRESSED);
+            let end = entries
+                .get(entry_index + 1)
+                .map(|next| base_offset + u64::from(next & !TABLE_ENTRY_COMPRESSED))
+                .unwrap_or(chunks_end);
+
+            self.chunks.push(ChunkLocation {
+                segment_index,
+                offset,
+                stored_size: end
+                    .checked_sub(offset)
+                    .ok_or_else(|| miette!("Chunk {} has a negative size.", self.chunks.len()))?,
+                is_compressed: entry & TABLE_ENTRY_COMPRESSED != 0,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reads and (if needed) decompresses a chunk, verifying its checksum.
+    fn read_chunk(&self, chunk_index: usize) -> Result<Vec<u8>> {
+        let location = self.chunks[chunk_index];
+
+        let mut stored = vec![0u8; location.stored_size as usize];
+        {
+            let mut file = self.segments[location.segment_index].lock().unwrap();
+            file.seek(SeekFrom::Start(location.offset))
+                .into_diagnostic()?;
+            file.read_exact(&mut stored)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read chunk {chunk_index}."))?;
+        }
+
+        let mut chunk = if location.is_compressed {
+            // The zlib stream ends with its own Adler-32 checksum, which is verified here.
+            miniz_oxide::inflate::decompress_to_vec_zlib(&stored).map_err(|error| {
+                miette!(
+                    "Failed to decompress chunk {}: {:?}.",
+                    chunk_index,
+                    error
+                )
+            })?
+        } else {
+            let data_size = stored
+                .len()
+                .checked_sub(4)
+                .ok_or_else(|| miette!("Chunk {} is too small.", chunk_index))?;
+
+            if adler32(&stored[..data_size]) != read_u32(&stored, data_size) {
+                return Err(miette!(
+                    "Checksum mismatch of chunk {}.",
+                    chunk_index
+                ));
+            }
+
+            stored.truncate(data_size);
+            stored
+        };
+
+        // The last chunk may be longer than what is left of the media.
+        let chunk_start = chunk_index as u64 * self.chunk_size;
+        chunk.truncate(self.media_size.saturating_sub(chunk_start) as usize);
+
+        Ok(chunk)
+    }
+
+    /// Hashes the whole media and compares the hashes with the ones stored in the image.
+    ///
+    /// Fails if a stored hash does not match.
+    pub fn verify(&self) -> Result<EwfMetadata> {
+        let started_at = Instant::now();
+
+        let mut md5 = Md5::new();
+        let mut sha1 = Sha1::new();
+
+        for chunk_index in 0..self.chunks.len() {
+            if chunk_index as u64 * self.chunk_size >= self.media_size {
+                break;
+            }
+
+            let chunk = self.read_chunk(chunk_index)?;
+            md5.update(&chunk);
+            sha1.update(&chunk);
+        }
+
+        let computed_md5: [u8; 16] = md5.finalize().into();
+        let computed_sha1: [u8; 20] = sha1.finalize().into();
+
+        let to_hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+        };
+
+        if self.stored_md5.is_some_and(|stored| stored != computed_md5) {
+            return Err(miette!(
+                "MD5 of the media ({}) does not match the one stored in the image ({}).",
+                to_hex(&computed_md5),
+                to_hex(&self.stored_md5.unwrap())
+            ));
+        }
+        if self
+            .stored_sha1
+            .is_some_and(|stored| stored != computed_sha1)
+        {
+            return Err(miette!(
+                "SHA-1 of the media ({}) does not match the one stored in the image ({}).",
+                to_hex(&computed_sha1),
+                to_hex(&self.stored_sha1.unwrap())
+            ));
+        }
+
+        info!(
+            "Verified {} bytes of media in {} segments in {:.2?} (stored MD5: {}, stored SHA-1: {}).",
+            self.media_size,
+            self.segments.len(),
+            started_at.elapsed(),
+            if self.stored_md5.is_some() { "match" } else { "none" },
+            if self.stored_sha1.is_some() { "match" } else { "none" },
+        );
+
+        Ok(EwfMetadata {
+            segments: self.segment_paths.clone(),
+            media_size: self.media_size,
+            bytes_per_sector: self.bytes_per_sector,
+            stored_md5: self.stored_md5.as_ref().map(|hash| to_hex(hash)),
+            stored_sha1: self.stored_sha1.as_ref().map(|hash| to_hex(hash)),
+            computed_md5: to_hex(&computed_md5),
+            computed_sha1: to_hex(&computed_sha1),
+        })
+    }
+}
+
+impl DiskImage for EwfImage {
+    fn size(&self) -> u64 {
+        self.media_size
+    }
+
+    fn read_exact_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        if offset + buffer.len() as u64 > self.media_size {
+            return Err(miette!(
+                "Read of {} bytes at offset {} is beyond the end of the media.",
+                buffer.len(),
+                offset
+            ));
+        }
+
+        let mut position = 0;
+        let mut cached_chunk = self.cached_chunk.lock().unwrap();
+
+        while position < buffer.len() {
+            let media_offset = offset + position as u64;
+            let chunk_index = (media_offset / self.chunk_size) as usize;
+            let offset_in_chunk = (media_offset % self.chunk_size) as usize;
+
+            if cached_chunk.as_ref().map(|(index, _)| *index) != Some(chunk_index) {
+                *cached_chunk = Some((chunk_index, self.read_chunk(chunk_index)?));
+            }
+            let (_, chunk) = cached_chunk.as_ref().unwrap();
+
+            let available = chunk.get(offset_in_chunk..).unwrap_or_default();
+            if available.is_empty() {
+                return Err(miette!(
+                    "Chunk {} is shorter than expected.",
+                    chunk_index
+                ));
+            }
+
+            let length = available.len().min(buffer.len() - position);
+            buffer[position..position + length].copy_from_slice(&available[..length]);
+            position += length;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the file at `path` starts with the EWF signature.
+pub fn is_ewf_file(path: &Path) -> bool {
+    let mut signature = [0u8; EWF_SIGNATURE.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|()| &signature == EWF_SIGNATURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTORS_PER_CHUNK: u32 = 4;
+    const BYTES_PER_SECTOR: u32 = 512;
+    const CHUNK_SIZE: usize = (SECTORS_PER_CHUNK * BYTES_PER_SECTOR) as usize;
+
+    fn section(section_type: &str, data: &[u8], offset: usize, is_last: bool) -> Vec<u8> {
+        let size = SECTION_DESCRIPTOR_SIZE + data.len();
+        let next_offset = if is_last { offset } else { offset + size };
+
+        let mut bytes = vec![0u8; SECTION_DESCRIPTOR_SIZE];
+        bytes[..section_type.len()].copy_from_slice(section_type.as_bytes());
+        bytes[16..24].copy_from_slice(&(next_offset as u64).to_le_bytes());
+        bytes[24..32].copy_from_slice(&(size as u64).to_le_bytes());
+        let checksum = adler32(&bytes[..72]);
+        bytes[72..76].copy_from_slice(&checksum.to_le_bytes());
+
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn table(number_of_entries: usize, entries: &[u32]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 20];
+        bytes[..4].copy_from_slice(&(number_of_entries as u32).to_le_bytes());
+        let checksum = adler32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+
+        for entry in entries {
+            bytes.extend_from_slice(&entry.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Writes `media` as an image of `number_of_segments` segment files, compressing every
+    /// other chunk, and returns the path of the first one.
+    fn write_image(
+        directory: &Path,
+        media: &[u8],
+        number_of_segments: usize,
+        stored_md5: [u8; 16],
+    ) -> PathBuf {
+        let chunks = media.chunks(CHUNK_SIZE).collect::<Vec<_>>();
+        let chunks_per_segment = chunks.len().div_ceil(number_of_segments);
+
+        for (segment_index, segment_chunks) in chunks.chunks(chunks_per_segment).enumerate() {
+            let mut segment = EWF_SIGNATURE.to_vec();
+            segment.push(0x01);
+            segment.extend_from_slice(&(segment_index as u16 + 1).to_le_bytes());
+            segment.extend_from_slice(&[0, 0]);
+
+            if segment_index == 0 {
+                let mut volume = vec![0u8; 1052];
+                volume[4..8].copy_from_slice(&(chunks.len() as u32).to_le_bytes());
+                volume[8..12].copy_from_slice(&SECTORS_PER_CHUNK.to_le_bytes());
+                volume[12..16].copy_from_slice(&BYTES_PER_SECTOR.to_le_bytes());
+                let number_of_sectors = media.len() as u64 / u64::from(BYTES_PER_SECTOR);
+                volume[16..24].copy_from_slice(&number_of_sectors.to_le_bytes());
+                segment.extend(section("volume", &volume, segment.len(), false));
+            }
+
+            let sectors_offset = segment.len();
+            let mut sectors = Vec::new();
+            let mut entries = Vec::new();
+            for (chunk_index, chunk) in segment_chunks.iter().enumerate() {
+                let offset = (sectors_offset + SECTION_DESCRIPTOR_SIZE + sectors.len()) as u32;
+                if chunk_index % 2 == 0 {
+                    entries.push(offset | TABLE_ENTRY_COMPRESSED);
+                    sectors.extend(miniz_oxide::deflate::compress_to_vec_zlib(
+                        chunk, 6,
+                    ));
+                } else {
+                    entries.push(offset);
+                    sectors.extend_from_slice(chunk);
+                    sectors.extend_from_slice(&adler32(chunk).to_le_bytes());
+                }
+            }
+            segment.extend(section(
+                "sectors",
+                &sectors,
+                sectors_offset,
+                false,
+            ));
+
+            let table = table(entries.len(), &entries);
+            segment.extend(section("table", &table, segment.len(), false));
+            segment.extend(section("table2", &table, segment.len(), false));
+
+            if (segment_index + 1) * chunks_per_segment >= chunks.len() {
+                let sha1: [u8; 20] = Sha1::digest(media).into();
+                let mut digest = stored_md5.to_vec();
+                digest.extend_from_slice(&sha1);
+                digest.extend_from_slice(&[0; 44]);
+                segment.extend(section("digest", &digest, segment.len(), false));
+                segment.extend(section("done", &[], segment.len(), true));
+            } else {
+                segment.extend(section("next", &[], segment.len(), true));
+            }
+
+            let segment_path = directory
+                .join("image")
+                .with_extension(segment_extension(segment_index as u16 + 1, false));
+            std::fs::write(segment_path, segment).unwrap();
+        }
+
+        directory.join("image.E01")
+    }
+
+    /// Media that does not end on a chunk boundary, so the last chunk is short.
+    fn media() -> Vec<u8> {
+        (0..7 * CHUNK_SIZE + 3 * BYTES_PER_SECTOR as usize)
+            .map(|index| (index * 31 % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn computes_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+
+        // Long enough for the sums to be reduced several times.
+        let bytes = vec![0xff; 100_000];
+        let (a, b) = bytes.iter().fold((1u64, 0u64), |(a, b), &byte| {
+            let a = (a + u64::from(byte)) % u64::from(ADLER32_MODULUS);
+            (a, (b + a) % u64::from(ADLER32_MODULUS))
+        });
+        assert_eq!(adler32(&bytes), ((b << 16) | a) as u32);
+    }
+
+    #[test]
+    fn names_segment_files() {
+        assert_eq!(segment_extension(1, false), "E01");
+        assert_eq!(segment_extension(99, false), "E99");
+        assert_eq!(segment_extension(100, false), "EAA");
+        assert_eq!(segment_extension(101, false), "EAB");
+        assert_eq!(segment_extension(126, false), "EBA");
+        assert_eq!(segment_extension(776, false), "FAA");
+        assert_eq!(segment_extension(100, true), "eaa");
+    }
+
+    #[test]
+    fn reads_media_across_segments() {
+        let directory = tempfile::tempdir().unwrap();
+        let media = media();
+        let stored_md5 = Md5::digest(&media).into();
+        let path = write_image(directory.path(), &media, 3, stored_md5);
+
+        let image = EwfImage::open(&path).unwrap();
+        assert_eq!(image.segments.len(), 3);
+        assert_eq!(image.size(), media.len() as u64);
+
+        let mut buffer = vec![0u8; media.len()];
+        image.read_exact_at(0, &mut buffer).unwrap();
+        assert_eq!(buffer, media);
+
+        // Within a chunk and across chunks (and segments).
+        for (offset, length) in [
+            (10, 100),
+            (CHUNK_SIZE - 5, 10),
+            (2 * CHUNK_SIZE + 7, 3 * CHUNK_SIZE),
+        ] {
+            let mut buffer = vec![0u8; length];
+            image.read_exact_at(offset as u64, &mut buffer).unwrap();
+            assert_eq!(buffer, media[offset..offset + length]);
+        }
+
+        let mut buffer = vec![0u8; 2];
+        assert!(image
+            .read_exact_at(media.len() as u64 - 1, &mut buffer)
+            .is_err());
+
+        let metadata = image.verify().unwrap();
+        assert_eq!(
+            metadata.stored_md5,
+            Some(metadata.computed_md5.clone())
+        );
+        assert_eq!(
+            metadata.stored_sha1,
+            Some(metadata.computed_sha1.clone())
+        );
+        assert_eq!(metadata.segments.len(), 3);
+    }
+
+    #[test]
+    fn rejects_mismatched_hashes_and_damaged_chunks() {
+        let directory = tempfile::tempdir().unwrap();
+        let media = media();
+        let path = write_image(directory.path(), &media, 1, [0; 16]);
+
+        let error = EwfImage::open(&path).unwrap().verify().unwrap_err();
+        assert!(error.to_string().starts_with("MD5 of the media"));
+
+        // Flips a byte in the second (uncompressed) chunk.
+        let stored_md5 = Md5::digest(&media).into();
+        let path = write_image(directory.path(), &media, 1, stored_md5);
+        let mut segment = std::fs::read(&path).unwrap();
+        let position = segment
+            .windows(16)
+            .position(|window| window == &media[CHUNK_SIZE..CHUNK_SIZE + 16])
+            .unwrap();
+        segment[position] ^= 0xff;
+        std::fs::write(&path, segment).unwrap();
+
+        let image = EwfImage::open(&path).unwrap();
+        let mut buffer = vec![0u8; 16];
+        let error = image
+            .read_exact_at(CHUNK_SIZE as u64, &mut buffer)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Checksum mismatch of chunk 1.");
+        image.read_exact_at(0, &mut buffer).unwrap();
+    }
+
+    #[test]
+    fn rejects_incomplete_images() {
+        let directory = tempfile::tempdir().unwrap();
+        let media = media();
+        let path = write_image(directory.path(), &media, 3, [0; 16]);
+
+        let error = EwfImage::open(&path.with_extension("E02")).err().unwrap();
+        assert!(error.to_string().starts_with("Expected the first segment"));
+
+        std::fs::remove_file(path.with_extension("E03")).unwrap();
+        let error = EwfImage::open(&path).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Image is incomplete: 6 of 8 chunks found (missing segment files?)."
+        );
+    }
+}
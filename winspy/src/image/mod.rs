@@ -0,0 +1,302 @@
+//! Reading artifacts directly out of disk images, without mounting or exporting them first.
+//!
+//! Only what is needed to reach the Diagnosis folder is implemented: raw and EWF (E01)
+//! images, partition tables (MBR and GPT) and a read-only subset of NTFS. Files are read
+//! into memory and never written anywhere.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use self::{
+    ewf::{is_ewf_file, EwfImage, EwfMetadata},
+    ntfs::NtfsVolume,
+    partition::{find_partitions, Partition},
+};
+
+pub mod ewf;
+pub mod ntfs;
+pub mod partition;
+
+/// Path of the transcript on the system volume.
+const EVENT_TRANSCRIPT_PATH: [&str; 5] = [
+    "ProgramData",
+    "Microsoft",
+    "Diagnosis",
+    "EventTranscript",
+    "EventTranscript.db",
+];
+
+/// Random access to the bytes of a disk image.
+pub trait DiskImage {
+    /// Size of the image in bytes.
+    fn size(&self) -> u64;
+
+    /// Fills `buffer` with the bytes of the image starting at `offset`.
+    fn read_exact_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()>;
+}
+
+/// A raw (`dd`) image: the bytes of the disk, as they are.
+pub struct RawImage {
+    file: File,
+    size: u64,
+}
+
+impl RawImage {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open disk image {}.", path.display()))?;
+        let size = file.metadata().into_diagnostic()?.len();
+
+        Ok(Self { file, size })
+    }
+}
+
+impl DiskImage for RawImage {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_exact_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset)).into_diagnostic()?;
+        file.read_exact(buffer).into_diagnostic().wrap_err_with(|| {
+            format!(
+                "Failed to read {} bytes at offset {} of the image.",
+                buffer.len(),
+                offset
+            )
+        })
+    }
+}
+
+/// Container format of a disk image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskImageFormat {
+    Raw,
+    Ewf,
+}
+
+/// A disk image that was read, for the output metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskImageMetadata {
+    pub path: PathBuf,
+    pub format: DiskImageFormat,
+
+    /// Segments and hashes of an EWF image, verified before it was read.
+    pub ewf: Option<EwfMetadata>,
+}
+
+/// Opens a raw or EWF image, depending on its signature.
+///
+/// EWF images are verified first: reading fails if the media does not match
+/// the hashes stored in the image.
+pub fn open_disk_image(path: &Path) -> Result<(Box<dyn DiskImage>, DiskImageMetadata)> {
+    if !is_ewf_file(path) {
+        let image = RawImage::open(path)?;
+        let metadata = DiskImageMetadata {
+            path: path.to_path_buf(),
+            format: DiskImageFormat::Raw,
+            ewf: None,
+        };
+
+        return Ok((Box::new(image), metadata));
+    }
+
+    let image = EwfImage::open(path)
+        .wrap_err_with(|| format!("Failed to open EWF image {}.", path.display()))?;
+    let ewf_metadata = image
+        .verify()
+        .wrap_err_with(|| format!("Failed to verify EWF image {}.", path.display()))?;
+    let metadata = DiskImageMetadata {
+        path: path.to_path_buf(),
+        format: DiskImageFormat::Ewf,
+        ewf: Some(ewf_metadata),
+    };
+
+    Ok((Box::new(image), metadata))
+}
+
+/// Files that make up the image: all segment files of an EWF image, or the image itself.
+pub fn disk_image_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if is_ewf_file(path) {
+        ewf::find_segment_paths(path)
+    } else {
+        Ok(vec![path.to_path_buf()])
+    }
+}
+
+/// A file read out of a disk image.
+#[derive(Debug, Clone)]
+pub struct ExtractedFile {
+    /// Path of the file within its volume, with `\` as the separator.
+    pub path_in_volume: String,
+    pub mft_record_number: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl ExtractedFile {
+    /// Describes the file without its content, for the output metadata.
+    pub fn metadata(&self) -> ExtractedFileMetadata {
+        ExtractedFileMetadata {
+            path_in_volume: self.path_in_volume.clone(),
+            mft_record_number: self.mft_record_number,
+            size: self.bytes.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&self.bytes)),
+        }
+    }
+}
+
+/// An `EventTranscript.db` found in a disk image, with its `-wal` and `-shm` files.
+pub struct ExtractedTranscript {
+    pub image_path: PathBuf,
+    pub partition: Partition,
+    pub database: ExtractedFile,
+    pub wal: Option<ExtractedFile>,
+    pub shm: Option<ExtractedFile>,
+}
+
+impl ExtractedTranscript {
+    /// Path under which the database is reported: the image path followed by
+    /// the partition and the path within the volume.
+    pub fn display_path(&self) -> PathBuf {
+        let mut display_path = self
+            .image_path
+            .join(format!("partition-{}", self.partition.index));
+        for component in self.database.path_in_volume.split('\\') {
+            display_path.push(component);
+        }
+
+        display_path
+    }
+
+    pub fn metadata(&self) -> ImageSourceMetadata {
+        ImageSourceMetadata {
+            image_path: self.image_path.clone(),
+            partition: self.partition.clone(),
+            database: self.database.metadata(),
+            wal: self.wal.as_ref().map(ExtractedFile::metadata),
+            shm: self.shm.as_ref().map(ExtractedFile::metadata),
+        }
+    }
+}
+
+/// Size and hash of an extracted file.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractedFileMetadata {
+    pub path_in_volume: String,
+    pub mft_record_number: u64,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Where in a disk image a database was read from.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageSourceMetadata {
+    pub image_path: PathBuf,
+    pub partition: Partition,
+    pub database: ExtractedFileMetadata,
+    pub wal: Option<ExtractedFileMetadata>,
+    pub shm: Option<ExtractedFileMetadata>,
+}
+
+/// Looks for `EventTranscript.db` on every NTFS volume of the image.
+///
+/// Volumes that cannot be read are logged and skipped.
+pub fn extract_event_transcripts(
+    image_path: &Path,
+    image: &dyn DiskImage,
+) -> Result<Vec<ExtractedTranscript>> {
+    let mut transcripts = Vec::new();
+
+    for partition in find_partitions(image)? {
+        let volume = match NtfsVolume::open(image, partition.offset) {
+            Ok(Some(volume)) => volume,
+            Ok(None) => continue,
+            Err(error) => {
+                warn!(
+                    "Skipping NTFS volume at offset {} of {}: {:?}",
+                    partition.offset,
+                    image_path.display(),
+                    error
+                );
+                continue;
+            }
+        };
+
+        match extract_event_transcript_from_volume(&volume) {
+            Ok(Some((database, wal, shm))) => {
+                info!(
+                    "Found {} on partition {} of {}.",
+                    database.path_in_volume,
+                    partition.index,
+                    image_path.display()
+                );
+
+                transcripts.push(ExtractedTranscript {
+                    image_path: image_path.to_path_buf(),
+                    partition,
+                    database,
+                    wal,
+                    shm,
+                });
+            }
+            Ok(None) => info!(
+                "No EventTranscript.db on partition {} of {}.",
+                partition.index,
+                image_path.display()
+            ),
+            Err(error) => warn!(
+                "Failed to read EventTranscript.db from partition {} of {}: {:?}",
+                partition.index,
+                image_path.display(),
+                error
+            ),
+        }
+    }
+
+    if transcripts.is_empty() {
+        return Err(miette!(
+            "No EventTranscript.db found on any NTFS volume of {}.",
+            image_path.display()
+        ));
+    }
+
+    Ok(transcripts)
+}
+
+type ExtractedTranscriptFiles = (
+    ExtractedFile,
+    Option<ExtractedFile>,
+    Option<ExtractedFile>,
+);
+
+fn extract_event_transcript_from_volume(
+    volume: &NtfsVolume,
+) -> Result<Option<ExtractedTranscriptFiles>> {
+    let Some(database) = volume.read_file_at_path(&EVENT_TRANSCRIPT_PATH)? else {
+        return Ok(None);
+    };
+
+    let (directory, file_name) = EVENT_TRANSCRIPT_PATH.split_at(EVENT_TRANSCRIPT_PATH.len() - 1);
+    let read_sidecar = |suffix: &str| {
+        let sidecar_name = format!("{}{}", file_name[0], suffix);
+        let sidecar_path = [directory, &[sidecar_name.as_str()]].concat();
+        volume.read_file_at_path(&sidecar_path)
+    };
+
+    Ok(Some((
+        database,
+        read_sidecar("-wal")?,
+        read_sidecar("-shm")?,
+    )))
+}
@@ -0,0 +1,1277 @@
+//! Read-only subset of NTFS: finding a file by its path and reading its unnamed data stream.
+//!
+//! Follows the layout documented in <https://github.com/libyal/libfsntfs/blob/main/documentation/>.
+//! Compressed and encrypted files are not supported.
+
+use miette::{miette, Context, Result};
+
+use super::{DiskImage, ExtractedFile};
+
+pub(super) const NTFS_OEM_ID: &[u8; 8] = b"NTFS    ";
+const BOOT_SECTOR_SIZE: usize = 512;
+
+/// Record numbers of system files.
+const MFT_RECORD_NUMBER: u64 = 0;
+const ROOT_DIRECTORY_RECORD_NUMBER: u64 = 5;
+
+/// Attribute type codes.
+const ATTRIBUTE_LIST: u32 = 0x20;
+const DATA: u32 = 0x80;
+const INDEX_ROOT: u32 = 0x90;
+const INDEX_ALLOCATION: u32 = 0xa0;
+const END_OF_ATTRIBUTES: u32 = 0xffff_ffff;
+
+/// Name of the attributes that index file names in a directory.
+const FILE_NAME_INDEX: &str = "$I30";
+
+const MFT_RECORD_SIGNATURE: &[u8; 4] = b"FILE";
+const INDEX_RECORD_SIGNATURE: &[u8; 4] = b"INDX";
+
+/// Multi-sector structures are protected in strides of 512 bytes, whatever the sector size.
+const UPDATE_SEQUENCE_STRIDE: usize = 512;
+
+const MFT_RECORD_IN_USE: u16 = 0x0001;
+const MFT_RECORD_IS_DIRECTORY: u16 = 0x0002;
+
+const ATTRIBUTE_COMPRESSED: u16 = 0x0001;
+const ATTRIBUTE_ENCRYPTED: u16 = 0x4000;
+
+const INDEX_ENTRY_IS_LAST: u32 = 0x0002;
+
+/// File names in the DOS (8.3) namespace only duplicate a long name.
+const FILE_NAME_NAMESPACE_DOS: u8 = 2;
+
+/// Lower 48 bits of a file reference are the record number, upper 16 the sequence number.
+const FILE_REFERENCE_RECORD_NUMBER_MASK: u64 = 0x0000_ffff_ffff_ffff;
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|value| u16::from_le_bytes([value[0], value[1]]))
+        .ok_or_else(|| miette!("Structure is truncated at offset {}.", offset))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|value| u32::from_le_bytes(value.try_into().unwrap()))
+        .ok_or_else(|| miette!("Structure is truncated at offset {}.", offset))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    bytes
+        .get(offset..offset + 8)
+        .map(|value| u64::from_le_bytes(value.try_into().unwrap()))
+        .ok_or_else(|| miette!("Structure is truncated at offset {}.", offset))
+}
+
+fn slice(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(length)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| miette!("Structure is truncated at offset {}.", offset))
+}
+
+/// Sizes in the boot sector are either a number of clusters or, if negative,
+/// the binary logarithm of the size in bytes.
+fn decode_structure_size(value: i8, cluster_size: u64) -> Result<u64> {
+    let size = if value > 0 {
+        (value as u64).checked_mul(cluster_size)
+    } else {
+        1u64.checked_shl(u32::from(value.unsigned_abs()))
+    };
+
+    size.ok_or_else(|| {
+        miette!(
+            "Invalid structure size {:#04x} in boot sector.",
+            value
+        )
+    })
+}
+
+/// Verifies and removes the update sequence of an MFT or index record.
+///
+/// The last two bytes of every 512-byte stride are replaced by the update sequence number
+/// when the record is written; the original bytes are kept in the update sequence array.
+fn apply_fixups(record: &mut [u8]) -> Result<()> {
+    let array_offset = read_u16(record, 4)? as usize;
+    let array_length = read_u16(record, 6)? as usize;
+
+    if array_length == 0 {
+        return Err(miette!("Record has no update sequence."));
+    }
+
+    let array = slice(record, array_offset, array_length * 2)?.to_vec();
+    let sequence_number = &array[0..2];
+
+    for stride_index in 1..array_length {
+        let position = stride_index * UPDATE_SEQUENCE_STRIDE - 2;
+        let Some(stride_end) = record.get_mut(position..position + 2) else {
+            break;
+        };
+
+        if stride_end != sequence_number {
+            return Err(miette!(
+                "Update sequence mismatch in stride {} (torn write or damaged record).",
+                stride_index
+            ));
+        }
+
+        stride_end.copy_from_slice(&array[stride_index * 2..stride_index * 2 + 2]);
+    }
+
+    Ok(())
+}
+
+/// The NTFS boot sector (`$Boot`).
+#[derive(Debug, Clone)]
+struct BootSector {
+    cluster_size: u64,
+    mft_cluster: u64,
+    mft_record_size: usize,
+    index_record_size: usize,
+    volume_size: u64,
+}
+
+impl BootSector {
+    /// Returns `None` if the sector is not an NTFS boot sector,
+    /// and an error if it is one but its values are out of range.
+    fn parse(bytes: &[u8]) -> Result<Option<Self>> {
+        if bytes.get(3..11) != Some(NTFS_OEM_ID) {
+            return Ok(None);
+        }
+
+        let bytes_per_sector = u64::from(read_u16(bytes, 0x0b)?);
+        let sectors_per_cluster_value = *bytes
+            .get(0x0d)
+            .ok_or_else(|| miette!("Boot sector is truncated."))?;
+        let sectors_per_cluster = match sectors_per_cluster_value {
+            // Values above 0x80 are the negated binary logarithm (used for 2 MiB clusters and more).
+            value if value > 0x80 => 1u64.checked_shl(256 - u32::from(value)),
+            value => Some(u64::from(value)),
+        };
+        let cluster_size = sectors_per_cluster
+            .and_then(|sectors_per_cluster| sectors_per_cluster.checked_mul(bytes_per_sector))
+            .filter(|cluster_size| *cluster_size != 0 && cluster_size.is_power_of_two())
+            .ok_or_else(|| {
+                miette!(
+                    "Invalid cluster size in boot sector ({} bytes per sector, sectors per cluster {:#04x}).",
+                    bytes_per_sector,
+                    sectors_per_cluster_value
+                )
+            })?;
+
+        let structure_size = |offset: usize| {
+            let value = *bytes
+                .get(offset)
+                .ok_or_else(|| miette!("Boot sector is truncated."))?;
+            decode_structure_size(value as i8, cluster_size)
+        };
+        let mft_record_size = structure_size(0x40)?;
+        let index_record_size = structure_size(0x44)?;
+
+        // Reject values no real volume has, to avoid huge allocations on garbage.
+        if !(256..=65536).contains(&mft_record_size) || !(256..=65536).contains(&index_record_size) {
+            return Err(miette!(
+                "Unsupported MFT record size {} or index record size {}.",
+                mft_record_size,
+                index_record_size
+            ));
+        }
+
+        let volume_size = read_u64(bytes, 0x28)?
+            .checked_mul(bytes_per_sector)
+            .ok_or_else(|| miette!("Volume size in boot sector is out of range."))?;
+
+        Ok(Some(Self {
+            cluster_size,
+            mft_cluster: read_u64(bytes, 0x30)?,
+            mft_record_size: mft_record_size as usize,
+            index_record_size: index_record_size as usize,
+            volume_size,
+        }))
+    }
+}
+
+/// A contiguous range of clusters of a non-resident attribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DataRun {
+    number_of_clusters: u64,
+
+    /// First cluster on the volume, or `None` for a sparse run (which reads as zeros).
+    first_cluster: Option<u64>,
+}
+
+/// Decodes the mapping pairs ("data runs") of a non-resident attribute.
+fn decode_data_runs(bytes: &[u8]) -> Result<Vec<DataRun>> {
+    let mut runs = Vec::new();
+    let mut position = 0;
+    let mut previous_cluster: i64 = 0;
+
+    while let Some(&header) = bytes.get(position) {
+        if header == 0 {
+            break;
+        }
+
+        let length_size = usize::from(header & 0x0f);
+        let offset_size = usize::from(header >> 4);
+        position += 1;
+
+        if length_size == 0 || length_size > 8 || offset_size > 8 {
+            return Err(miette!(
+                "Invalid data run header {:#04x}.",
+                header
+            ));
+        }
+
+        let length_bytes = slice(bytes, position, length_size)?;
+        let mut number_of_clusters = 0u64;
+        for (byte_index, &byte) in length_bytes.iter().enumerate() {
+            number_of_clusters |= u64::from(byte) << (8 * byte_index);
+        }
+        position += length_size;
+
+        let first_cluster = if offset_size == 0 {
+            None
+        } else {
+            let offset_bytes = slice(bytes, position, offset_size)?;
+            let mut relative_offset = 0i64;
+            for (byte_index, &byte) in offset_bytes.iter().enumerate() {
+                relative_offset |= i64::from(byte) << (8 * byte_index);
+            }
+
+            // The offset is a signed number relative to the previous run.
+            let unused_bits = 64 - 8 * offset_size as u32;
+            relative_offset = (relative_offset << unused_bits) >> unused_bits;
+            position += offset_size;
+
+            previous_cluster = previous_cluster
+                .checked_add(relative_offset)
+                .filter(|&cluster| cluster >= 0)
+                .ok_or_else(|| miette!("Data run points before the start of the volume."))?;
+
+            Some(previous_cluster as u64)
+        };
+
+        runs.push(DataRun {
+            number_of_clusters,
+            first_cluster,
+        });
+    }
+
+    Ok(runs)
+}
+
+#[derive(Debug, Clone)]
+enum AttributeContent {
+    Resident(Vec<u8>),
+    NonResident {
+        first_vcn: u64,
+        runs: Vec<DataRun>,
+
+        /// Sizes are only valid in the piece that starts at VCN 0.
+        data_size: u64,
+        initialized_size: u64,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Attribute {
+    type_code: u32,
+    name: String,
+    flags: u16,
+    content: AttributeContent,
+}
+
+impl Attribute {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let type_code = read_u32(bytes, 0)?;
+        let is_non_resident = *bytes
+            .get(8)
+            .ok_or_else(|| miette!("Attribute is truncated."))?
+            != 0;
+        let name_length = usize::from(
+            *bytes
+                .get(9)
+                .ok_or_else(|| miette!("Attribute is truncated."))?,
+        );
+        let name_offset = usize::from(read_u16(bytes, 0x0a)?);
+        let flags = read_u16(bytes, 0x0c)?;
+
+        let name = decode_utf16(slice(bytes, name_offset, name_length * 2)?);
+
+        let content = if is_non_resident {
+            let runs_offset = usize::from(read_u16(bytes, 0x20)?);
+
+            AttributeContent::NonResident {
+                first_vcn: read_u64(bytes, 0x10)?,
+                runs: decode_data_runs(bytes.get(runs_offset..).unwrap_or_default())?,
+                data_size: read_u64(bytes, 0x30)?,
+                initialized_size: read_u64(bytes, 0x38)?,
+            }
+        } else {
+            let value_length = read_u32(bytes, 0x10)? as usize;
+            let value_offset = usize::from(read_u16(bytes, 0x14)?);
+
+            AttributeContent::Resident(slice(bytes, value_offset, value_length)?.to_vec())
+        };
+
+        Ok(Self {
+            type_code,
+            name,
+            flags,
+            content,
+        })
+    }
+}
+
+fn decode_utf16(bytes: &[u8]) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect::<Vec<_>>();
+
+    String::from_utf16_lossy(&units)
+}
+
+/// A record of the master file table.
+#[derive(Debug, Clone)]
+struct MftRecord {
+    record_number: u64,
+    sequence_number: u16,
+    flags: u16,
+    attributes: Vec<Attribute>,
+}
+
+impl MftRecord {
+    fn parse(mut bytes: Vec<u8>, record_number: u64) -> Result<Self> {
+        if bytes.get(0..4) != Some(MFT_RECORD_SIGNATURE) {
+            return Err(miette!(
+                "MFT record {} has no FILE signature.",
+                record_number
+            ));
+        }
+
+        apply_fixups(&mut bytes)
+            .wrap_err_with(|| format!("MFT record {} is damaged.", record_number))?;
+
+        let sequence_number = read_u16(&bytes, 0x10)?;
+        let first_attribute_offset = usize::from(read_u16(&bytes, 0x14)?);
+        let flags = read_u16(&bytes, 0x16)?;
+
+        let mut attributes = Vec::new();
+        let mut offset = first_attribute_offset;
+
+        loop {
+            let type_code = read_u32(&bytes, offset)?;
+            if type_code == END_OF_ATTRIBUTES {
+                break;
+            }
+
+            let length = read_u32(&bytes, offset + 4)? as usize;
+            if length == 0 {
+                break;
+            }
+
+            let attribute_bytes = slice(&bytes, offset, length)?;
+            attributes.push(
+                Attribute::parse(attribute_bytes).wrap_err_with(|| {
+                    format!(
+                        "Failed to parse attribute {:#x} of MFT record {}.",
+                        type_code, record_number
+                    )
+                })?,
+            );
+
+            offset += length;
+        }
+
+        Ok(Self {
+            record_number,
+            sequence_number,
+            flags,
+            attributes,
+        })
+    }
+
+    fn is_in_use(&self) -> bool {
+        self.flags & MFT_RECORD_IN_USE != 0
+    }
+
+    fn is_directory(&self) -> bool {
+        self.flags & MFT_RECORD_IS_DIRECTORY != 0
+    }
+
+    fn attribute(&self, type_code: u32, name: &str) -> Option<&Attribute> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.type_code == type_code && attribute.name == name)
+    }
+}
+
+/// An entry of an `$ATTRIBUTE_LIST`: where (part of) an attribute of the file is stored.
+struct AttributeListEntry {
+    type_code: u32,
+    name: String,
+    first_vcn: u64,
+    record_number: u64,
+}
+
+fn parse_attribute_list(bytes: &[u8]) -> Result<Vec<AttributeListEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset + 0x1a <= bytes.len() {
+        let length = usize::from(read_u16(bytes, offset + 4)?);
+        if length == 0 {
+            break;
+        }
+
+        let name_length = usize::from(bytes[offset + 6]);
+        let name_offset = usize::from(bytes[offset + 7]);
+
+        entries.push(AttributeListEntry {
+            type_code: read_u32(bytes, offset)?,
+            name: decode_utf16(slice(
+                bytes,
+                offset + name_offset,
+                name_length * 2,
+            )?),
+            first_vcn: read_u64(bytes, offset + 8)?,
+            record_number: read_u64(bytes, offset + 0x10)? & FILE_REFERENCE_RECORD_NUMBER_MASK,
+        });
+
+        offset += length;
+    }
+
+    Ok(entries)
+}
+
+/// Entry of a directory index.
+struct IndexEntry {
+    file_reference: u64,
+    name: String,
+    namespace: u8,
+}
+
+/// Parses the entries of an index node, starting at its node header.
+fn parse_index_entries(node: &[u8]) -> Result<Vec<IndexEntry>> {
+    let entries_offset = read_u32(node, 0)? as usize;
+    let entries_end = (read_u32(node, 4)? as usize).min(node.len());
+
+    let mut entries = Vec::new();
+    let mut offset = entries_offset;
+
+    while offset + 16 <= entries_end {
+        let file_reference = read_u64(node, offset)?;
+        let entry_length = usize::from(read_u16(node, offset + 8)?);
+        let key_length = usize::from(read_u16(node, offset + 10)?);
+        let flags = read_u32(node, offset + 12)?;
+
+        if flags & INDEX_ENTRY_IS_LAST != 0 || entry_length == 0 {
+            break;
+        }
+
+        // The key is the `$FILE_NAME` attribute of the file.
+        if key_length >= 0x42 {
+            let key = slice(node, offset + 16, key_length)?;
+            let name_length = usize::from(key[0x40]);
+
+            entries.push(IndexEntry {
+                file_reference,
+                name: decode_utf16(slice(key, 0x42, name_length * 2)?),
+                namespace: key[0x41],
+            });
+        }
+
+        offset += entry_length;
+    }
+
+    Ok(entries)
+}
+
+/// An NTFS volume within a disk image.
+pub struct NtfsVolume<'a> {
+    image: &'a dyn DiskImage,
+
+    /// Offset of the volume in the image, in bytes.
+    offset: u64,
+    boot_sector: BootSector,
+
+    /// Where the records of the MFT are stored.
+    mft_runs: Vec<DataRun>,
+}
+
+impl<'a> NtfsVolume<'a> {
+    /// Opens the volume at `offset`, or returns `None` if there is no NTFS boot sector there.
+    pub fn open(image: &'a dyn DiskImage, offset: u64) -> Result<Option<Self>> {
+        if offset
+            .checked_add(BOOT_SECTOR_SIZE as u64)
+            .is_none_or(|end| end > image.size())
+        {
+            return Ok(None);
+        }
+
+        let mut boot_sector_bytes = [0u8; BOOT_SECTOR_SIZE];
+        image.read_exact_at(offset, &mut boot_sector_bytes)?;
+
+        let Some(mut boot_sector) = BootSector::parse(&boot_sector_bytes)? else {
+            return Ok(None);
+        };
+
+        // Nothing beyond the end of the image can be read, so a larger volume size
+        // (of a truncated image or a damaged boot sector) only allows huge allocations.
+        boot_sector.volume_size = boot_sector.volume_size.min(image.size() - offset);
+
+        let mut volume = Self {
+            image,
+            offset,
+            mft_runs: Vec::new(),
+            boot_sector,
+        };
+
+        // The first record of the MFT describes the MFT itself. It is read from where the
+        // boot sector points, then its runs are used to find all other records.
+        let mft_offset = volume
+            .boot_sector
+            .mft_cluster
+            .checked_mul(volume.boot_sector.cluster_size)
+            .ok_or_else(|| miette!("MFT cluster in boot sector is out of range."))?;
+        let mut mft_record_bytes = vec![0u8; volume.boot_sector.mft_record_size];
+        volume.read_volume_bytes(mft_offset, &mut mft_record_bytes)?;
+        let mft_record = MftRecord::parse(mft_record_bytes, MFT_RECORD_NUMBER)?;
+
+        // An attribute list of the MFT can only point to records covered by the runs
+        // of its base record, so those are enough to resolve it.
+        let base_runs = mft_record
+            .attribute(DATA, "")
+            .and_then(|attribute| match &attribute.content {
+                AttributeContent::NonResident { runs, .. } => Some(runs.clone()),
+                AttributeContent::Resident(_) => None,
+            })
+            .ok_or_else(|| miette!("MFT has no non-resident data."))?;
+        volume.mft_runs = base_runs;
+
+        let (mft_runs, _) = volume.non_resident_data_runs(&mft_record)?;
+        volume.mft_runs = mft_runs;
+
+        Ok(Some(volume))
+    }
+
+    /// Reads bytes at `offset` from the start of the volume.
+    fn read_volume_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        if offset
+            .checked_add(buffer.len() as u64)
+            .is_none_or(|end| end > self.boot_sector.volume_size)
+        {
+            return Err(miette!(
+                "Read at offset {} is beyond the end of the volume.",
+                offset
+            ));
+        }
+
+        self.image.read_exact_at(self.offset + offset, buffer)
+    }
+
+    /// Fills `buffer` with the data described by `runs`, starting at byte `offset` of the data.
+    fn read_runs(&self, runs: &[DataRun], offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let cluster_size = self.boot_sector.cluster_size;
+        let mut run_start = 0u64;
+        let mut position = 0usize;
+
+        let out_of_range = || miette!("Data run is out of range of the volume.");
+
+        for run in runs {
+            let run_length = run
+                .number_of_clusters
+                .checked_mul(cluster_size)
+                .ok_or_else(out_of_range)?;
+            let run_end = run_start.checked_add(run_length).ok_or_else(out_of_range)?;
+            let wanted_start = offset
+                .checked_add(position as u64)
+                .ok_or_else(out_of_range)?;
+
+            if position < buffer.len() && wanted_start < run_end {
+                let offset_in_run = wanted_start - run_start;
+                let length =
+                    (run_length - offset_in_run).min((buffer.len() - position) as u64) as usize;
+                let target = &mut buffer[position..position + length];
+
+                match run.first_cluster {
+                    Some(first_cluster) => {
+                        let volume_offset = first_cluster
+                            .checked_mul(cluster_size)
+                            .and_then(|run_offset| run_offset.checked_add(offset_in_run))
+                            .ok_or_else(out_of_range)?;
+                        self.read_volume_bytes(volume_offset, target)?;
+                    }
+                    None => target.fill(0),
+                }
+
+                position += length;
+            }
+
+            run_start = run_end;
+        }
+
+        if position < buffer.len() {
+            return Err(miette!(
+                "Data runs end before offset {}.",
+                offset + position as u64
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn read_mft_record(&self, record_number: u64) -> Result<MftRecord> {
+        let record_size = self.boot_sector.mft_record_size;
+        let mut bytes = vec![0u8; record_size];
+
+        let record_offset = record_number
+            .checked_mul(record_size as u64)
+            .ok_or_else(|| {
+                miette!(
+                    "MFT record number {} is out of range.",
+                    record_number
+                )
+            })?;
+
+        self.read_runs(&self.mft_runs, record_offset, &mut bytes)
+            .wrap_err_with(|| format!("Failed to read MFT record {}.", record_number))?;
+
+        MftRecord::parse(bytes, record_number)
+    }
+
+    /// Collects all pieces of an attribute, following the `$ATTRIBUTE_LIST` of the record
+    /// into extension records if there is one.
+    fn attribute_pieces(
+        &self,
+        record: &MftRecord,
+        type_code: u32,
+        name: &str,
+    ) -> Result<Vec<Attribute>> {
+        let Some(attribute_list) = record.attribute(ATTRIBUTE_LIST, "") else {
+            return Ok(record
+                .attributes
+                .iter()
+                .filter(|attribute| attribute.type_code == type_code && attribute.name == name)
+                .cloned()
+                .collect());
+        };
+
+        let attribute_list_bytes = self.read_attribute_value(attribute_list)?;
+        let mut list_entries = parse_attribute_list(&attribute_list_bytes)?
+            .into_iter()
+            .filter(|entry| entry.type_code == type_code && entry.name == name)
+            .collect::<Vec<_>>();
+        list_entries.sort_by_key(|entry| entry.first_vcn);
+
+        let mut pieces: Vec<Attribute> = Vec::new();
+        for entry in list_entries {
+            let extension_record;
+            let source_record = if entry.record_number == record.record_number {
+                record
+            } else {
+                extension_record = self.read_mft_record(entry.record_number)?;
+                &extension_record
+            };
+
+            let piece = source_record
+                .attributes
+                .iter()
+                .find(|attribute| {
+                    attribute.type_code == type_code
+                        && attribute.name == name
+                        && match attribute.content {
+                            AttributeContent::NonResident { first_vcn, .. } => {
+                                first_vcn == entry.first_vcn
+                            }
+                            AttributeContent::Resident(_) => true,
+                        }
+                })
+                .ok_or_else(|| {
+                    miette!(
+                        "Attribute {:#x} listed in record {} is missing from record {}.",
+                        type_code,
+                        record.record_number,
+                        entry.record_number
+                    )
+                })?;
+
+            pieces.push(piece.clone());
+        }
+
+        Ok(pieces)
+    }
+
+    /// Data runs and size of a non-resident unnamed `$DATA` attribute, over all of its pieces.
+    fn non_resident_data_runs(&self, record: &MftRecord) -> Result<(Vec<DataRun>, u64)> {
+        let mut runs = Vec::new();
+        let mut data_size = None;
+
+        for piece in self.attribute_pieces(record, DATA, "")? {
+            match piece.content {
+                AttributeContent::NonResident {
+                    first_vcn,
+                    runs: piece_runs,
+                    data_size: piece_data_size,
+                    ..
+                } => {
+                    if first_vcn == 0 {
+                        data_size = Some(piece_data_size);
+                    }
+                    runs.extend(piece_runs);
+                }
+                AttributeContent::Resident(_) => {
+                    return Err(miette!("Expected non-resident data."));
+                }
+            }
+        }
+
+        let data_size = data_size.ok_or_else(|| miette!("Data attribute has no first piece."))?;
+        Ok((runs, data_size))
+    }
+
+    /// Reads the complete value of a single-piece attribute.
+    fn read_attribute_value(&self, attribute: &Attribute) -> Result<Vec<u8>> {
+        self.read_attribute_pieces(std::slice::from_ref(attribute))
+    }
+
+    /// Reads the complete value of an attribute stored in one or more pieces.
+    fn read_attribute_pieces(&self, pieces: &[Attribute]) -> Result<Vec<u8>> {
+        let Some(first_piece) = pieces.first() else {
+            return Err(miette!("Attribute has no content."));
+        };
+
+        if first_piece.flags & ATTRIBUTE_COMPRESSED != 0 {
+            return Err(miette!("Compressed files are not supported."));
+        }
+        if first_piece.flags & ATTRIBUTE_ENCRYPTED != 0 {
+            return Err(miette!("Encrypted files are not supported."));
+        }
+
+        let (data_size, initialized_size) = match &first_piece.content {
+            AttributeContent::Resident(value) => return Ok(value.clone()),
+            AttributeContent::NonResident {
+                data_size,
+                initialized_size,
+                ..
+            } => (*data_size, *initialized_size),
+        };
+
+        if data_size > self.boot_sector.volume_size {
+            return Err(miette!(
+                "Attribute size {} is larger than the volume.",
+                data_size
+            ));
+        }
+
+        let runs = pieces
+            .iter()
+            .flat_map(|piece| match &piece.content {
+                AttributeContent::NonResident { runs, .. } => runs.clone(),
+                AttributeContent::Resident(_) => Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        // Bytes past the initialized size were never written and read as zeros.
+        let mut value = vec![0u8; data_size as usize];
+        let initialized_length = initialized_size.min(data_size) as usize;
+        self.read_runs(&runs, 0, &mut value[..initialized_length])?;
+
+        Ok(value)
+    }
+
+    /// Looks up `name` in a directory and returns the record of the file it refers to.
+    ///
+    /// The whole index is scanned instead of descending the b-tree, so that a damaged
+    /// node does not hide other entries. Stale entries (pointing to a reused or deleted
+    /// record) are skipped by checking the sequence number and the in-use flag.
+    fn find_in_directory(&self, directory: &MftRecord, name: &str) -> Result<Option<MftRecord>> {
+        let index_root = directory
+            .attribute(INDEX_ROOT, FILE_NAME_INDEX)
+            .ok_or_else(|| {
+                miette!(
+                    "Directory record {} has no file name index.",
+                    directory.record_number
+                )
+            })?;
+        let index_root = self.read_attribute_value(index_root)?;
+
+        let index_record_size = match read_u32(&index_root, 8)? as usize {
+            0 => self.boot_sector.index_record_size,
+            index_record_size => index_record_size,
+        };
+
+        // Same bounds as in the boot sector; the node header starts at 0x18.
+        if !(256..=65536).contains(&index_record_size) {
+            return Err(miette!(
+                "Unsupported index record size {} in directory record {}.",
+                index_record_size,
+                directory.record_number
+            ));
+        }
+
+        let index_root_node = index_root
+            .get(0x10..)
+            .ok_or_else(|| miette!("Index root is truncated."))?;
+        let mut entries = parse_index_entries(index_root_node)?;
+
+        let index_allocation =
+            self.attribute_pieces(directory, INDEX_ALLOCATION, FILE_NAME_INDEX)?;
+        if !index_allocation.is_empty() {
+            let index_allocation = self.read_attribute_pieces(&index_allocation)?;
+
+            for index_record in index_allocation.chunks_exact(index_record_size) {
+                if index_record.get(0..4) != Some(INDEX_RECORD_SIGNATURE) {
+                    continue;
+                }
+
+                let mut index_record = index_record.to_vec();
+                if apply_fixups(&mut index_record).is_err() {
+                    continue;
+                }
+
+                let Some(node) = index_record.get(0x18..) else {
+                    continue;
+                };
+                if let Ok(node_entries) = parse_index_entries(node) {
+                    entries.extend(node_entries);
+                }
+            }
+        }
+
+        let wanted_name = name.to_lowercase();
+
+        for entry in entries {
+            if entry.namespace == FILE_NAME_NAMESPACE_DOS || entry.name.to_lowercase() != wanted_name
+            {
+                continue;
+            }
+
+            let record_number = entry.file_reference & FILE_REFERENCE_RECORD_NUMBER_MASK;
+            let sequence_number = (entry.file_reference >> 48) as u16;
+
+            let Ok(record) = self.read_mft_record(record_number) else {
+                continue;
+            };
+
+            if record.is_in_use() && record.sequence_number == sequence_number {
+                return Ok(Some(record));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the unnamed data stream of the file at `path` (given by its components,
+    /// compared case-insensitively), or returns `None` if there is no such file.
+    pub fn read_file_at_path(&self, path: &[&str]) -> Result<Option<ExtractedFile>> {
+        let mut record = self.read_mft_record(ROOT_DIRECTORY_RECORD_NUMBER)?;
+
+        for component in path {
+            if !record.is_directory() {
+                return Ok(None);
+            }
+
+            match self
+                .find_in_directory(&record, component)
+                .wrap_err_with(|| format!("Failed to look up {component}."))?
+            {
+                Some(child_record) => record = child_record,
+                None => return Ok(None),
+            }
+        }
+
+        if record.is_directory() {
+            return Ok(None);
+        }
+
+        let data_pieces = self.attribute_pieces(&record, DATA, "")?;
+        let bytes = self
+            .read_attribute_pieces(&data_pieces)
+            .wrap_err_with(|| format!("Failed to read the data of {}.", path.join("\\")))?;
+
+        Ok(Some(ExtractedFile {
+            path_in_volume: path.join("\\"),
+            mft_record_number: record.record_number,
+            bytes,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLUSTER_SIZE: usize = 4096;
+    const MFT_RECORD_SIZE: usize = 1024;
+    const MFT_CLUSTER: usize = 1;
+    const NUMBER_OF_CLUSTERS: usize = 4;
+
+    struct TestImage(Vec<u8>);
+
+    impl DiskImage for TestImage {
+        fn size(&self) -> u64 {
+            self.0.len() as u64
+        }
+
+        fn read_exact_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+            let bytes = slice(&self.0, offset as usize, buffer.len())?;
+            buffer.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+    }
+
+    /// Boot sector with 512-byte sectors, 1024-byte MFT records and 4096-byte index records.
+    fn boot_sector_bytes(sectors_per_cluster: u8, total_sectors: u64) -> Vec<u8> {
+        let mut bytes = vec![0u8; BOOT_SECTOR_SIZE];
+        put(&mut bytes, 3, NTFS_OEM_ID);
+        put(&mut bytes, 0x0b, &512u16.to_le_bytes());
+        bytes[0x0d] = sectors_per_cluster;
+        put(&mut bytes, 0x28, &total_sectors.to_le_bytes());
+        put(
+            &mut bytes,
+            0x30,
+            &(MFT_CLUSTER as u64).to_le_bytes(),
+        );
+        bytes[0x40] = (-10i8) as u8;
+        bytes[0x44] = (-12i8) as u8;
+        bytes
+    }
+
+    fn utf16(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn resident_attribute(type_code: u32, name: &str, value: &[u8]) -> Vec<u8> {
+        let name = utf16(name);
+        let value_offset = (0x18 + name.len()).next_multiple_of(8);
+        let length = (value_offset + value.len()).next_multiple_of(8);
+
+        let mut bytes = vec![0u8; length];
+        put(&mut bytes, 0, &type_code.to_le_bytes());
+        put(&mut bytes, 4, &(length as u32).to_le_bytes());
+        bytes[9] = (name.len() / 2) as u8;
+        put(&mut bytes, 0x0a, &0x18u16.to_le_bytes());
+        put(
+            &mut bytes,
+            0x10,
+            &(value.len() as u32).to_le_bytes(),
+        );
+        put(
+            &mut bytes,
+            0x14,
+            &(value_offset as u16).to_le_bytes(),
+        );
+        put(&mut bytes, 0x18, &name);
+        put(&mut bytes, value_offset, value);
+        bytes
+    }
+
+    fn non_resident_attribute(type_code: u32, runs: &[u8], data_size: u64) -> Vec<u8> {
+        let length = (0x40 + runs.len()).next_multiple_of(8);
+
+        let mut bytes = vec![0u8; length];
+        put(&mut bytes, 0, &type_code.to_le_bytes());
+        put(&mut bytes, 4, &(length as u32).to_le_bytes());
+        bytes[8] = 1;
+        put(&mut bytes, 0x0a, &0x40u16.to_le_bytes());
+        put(&mut bytes, 0x20, &0x40u16.to_le_bytes());
+        put(&mut bytes, 0x28, &data_size.to_le_bytes());
+        put(&mut bytes, 0x30, &data_size.to_le_bytes());
+        put(&mut bytes, 0x38, &data_size.to_le_bytes());
+        put(&mut bytes, 0x40, runs);
+        bytes
+    }
+
+    /// MFT record with the given attributes, protected by an update sequence.
+    fn mft_record(flags: u16, sequence_number: u16, attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0u8; MFT_RECORD_SIZE];
+        put(&mut bytes, 0, MFT_RECORD_SIGNATURE);
+        put(&mut bytes, 4, &0x30u16.to_le_bytes());
+        put(&mut bytes, 6, &3u16.to_le_bytes());
+        put(&mut bytes, 0x10, &sequence_number.to_le_bytes());
+        put(&mut bytes, 0x14, &0x38u16.to_le_bytes());
+        put(&mut bytes, 0x16, &flags.to_le_bytes());
+
+        let mut offset = 0x38;
+        for attribute in attributes {
+            put(&mut bytes, offset, attribute);
+            offset += attribute.len();
+        }
+        put(
+            &mut bytes,
+            offset,
+            &END_OF_ATTRIBUTES.to_le_bytes(),
+        );
+
+        let update_sequence_number = [0x2a, 0x00];
+        put(&mut bytes, 0x30, &update_sequence_number);
+        for stride_index in 1..=2 {
+            let stride_end = stride_index * UPDATE_SEQUENCE_STRIDE - 2;
+            let original = [bytes[stride_end], bytes[stride_end + 1]];
+            put(&mut bytes, 0x30 + stride_index * 2, &original);
+            put(&mut bytes, stride_end, &update_sequence_number);
+        }
+
+        bytes
+    }
+
+    /// Value of an `$INDEX_ROOT` attribute with one entry per (name, file reference).
+    fn index_root(index_record_size: u32, entries: &[(&str, u64)]) -> Vec<u8> {
+        let mut entry_bytes = Vec::new();
+        for (name, file_reference) in entries {
+            let name = utf16(name);
+            let key_length = 0x42 + name.len();
+            let entry_length = (16 + key_length).next_multiple_of(8);
+
+            let mut entry = vec![0u8; entry_length];
+            put(&mut entry, 0, &file_reference.to_le_bytes());
+            put(
+                &mut entry,
+                8,
+                &(entry_length as u16).to_le_bytes(),
+            );
+            put(&mut entry, 10, &(key_length as u16).to_le_bytes());
+            entry[16 + 0x40] = (name.len() / 2) as u8;
+            entry[16 + 0x41] = 1;
+            put(&mut entry, 16 + 0x42, &name);
+            entry_bytes.extend(entry);
+        }
+
+        let mut last_entry = vec![0u8; 16];
+        put(&mut last_entry, 8, &16u16.to_le_bytes());
+        put(
+            &mut last_entry,
+            12,
+            &INDEX_ENTRY_IS_LAST.to_le_bytes(),
+        );
+        entry_bytes.extend(last_entry);
+
+        let mut bytes = vec![0u8; 0x20];
+        put(&mut bytes, 8, &index_record_size.to_le_bytes());
+        put(&mut bytes, 0x10, &0x10u32.to_le_bytes());
+        put(
+            &mut bytes,
+            0x14,
+            &(0x10 + entry_bytes.len() as u32).to_le_bytes(),
+        );
+        bytes.extend(entry_bytes);
+        bytes
+    }
+
+    /// A volume whose root directory contains `Diagnosis.txt` (record 6, sequence number 3).
+    fn volume_image(root_index: Vec<u8>) -> TestImage {
+        let mut bytes = vec![0u8; NUMBER_OF_CLUSTERS * CLUSTER_SIZE];
+        put(
+            &mut bytes,
+            0,
+            &boot_sector_bytes(
+                8,
+                (NUMBER_OF_CLUSTERS * CLUSTER_SIZE / 512) as u64,
+            ),
+        );
+
+        // The MFT takes clusters 1 and 2: one run of 2 clusters at cluster 1.
+        let mft_data = non_resident_attribute(DATA, &[0x11, 0x02, 0x01, 0x00], 8192);
+        let records = [
+            (0, mft_record(MFT_RECORD_IN_USE, 1, &[mft_data])),
+            (
+                ROOT_DIRECTORY_RECORD_NUMBER as usize,
+                mft_record(
+                    MFT_RECORD_IN_USE | MFT_RECORD_IS_DIRECTORY,
+                    5,
+                    &[resident_attribute(INDEX_ROOT, FILE_NAME_INDEX, &root_index)],
+                ),
+            ),
+            (
+                6,
+                mft_record(
+                    MFT_RECORD_IN_USE,
+                    3,
+                    &[resident_attribute(DATA, "", b"telemetry")],
+                ),
+            ),
+        ];
+        for (record_number, record) in records {
+            put(
+                &mut bytes,
+                MFT_CLUSTER * CLUSTER_SIZE + record_number * MFT_RECORD_SIZE,
+                &record,
+            );
+        }
+
+        TestImage(bytes)
+    }
+
+    fn file_reference(record_number: u64, sequence_number: u16) -> u64 {
+        record_number | (u64::from(sequence_number) << 48)
+    }
+
+    #[test]
+    fn parses_boot_sector() {
+        let boot_sector = BootSector::parse(&boot_sector_bytes(8, 1000))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(boot_sector.cluster_size, 4096);
+        assert_eq!(boot_sector.mft_cluster, 1);
+        assert_eq!(boot_sector.mft_record_size, 1024);
+        assert_eq!(boot_sector.index_record_size, 4096);
+        assert_eq!(boot_sector.volume_size, 512_000);
+
+        // 0xf4 is the negated logarithm of 4096 sectors per cluster (2 MiB clusters).
+        let boot_sector = BootSector::parse(&boot_sector_bytes(0xf4, 1000))
+            .unwrap()
+            .unwrap();
+        assert_eq!(boot_sector.cluster_size, 2 * 1024 * 1024);
+    }
+
+    #[test]
+    fn rejects_malformed_boot_sectors() {
+        assert!(BootSector::parse(&[0u8; BOOT_SECTOR_SIZE])
+            .unwrap()
+            .is_none());
+        assert!(BootSector::parse(&boot_sector_bytes(8, 1000)[..0x20]).is_err());
+
+        // Shifts by 127 and by 64 bits, and no sectors at all.
+        for sectors_per_cluster in [0x81, 0xc0, 0x00] {
+            assert!(BootSector::parse(&boot_sector_bytes(sectors_per_cluster, 1000)).is_err());
+        }
+
+        // 2^-(-128) bytes per MFT record, and more than the u64 range of bytes.
+        let mut bytes = boot_sector_bytes(8, 1000);
+        bytes[0x40] = 0x80;
+        assert!(BootSector::parse(&bytes).is_err());
+        assert!(BootSector::parse(&boot_sector_bytes(8, u64::MAX)).is_err());
+
+        let mut bytes = boot_sector_bytes(8, 1000);
+        put(&mut bytes, 0x0b, &0u16.to_le_bytes());
+        assert!(BootSector::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn decodes_data_runs() {
+        let runs = decode_data_runs(&[
+            0x21, 0x10, 0x00, 0x01, // 16 clusters at 0x100
+            0x11, 0x20, 0xf0, // 32 clusters 16 before the previous run
+            0x01, 0x05, // 5 sparse clusters
+            0x00, 0xff, // end (the rest is ignored)
+        ])
+        .unwrap();
+
+        assert_eq!(
+            runs,
+            vec![
+                DataRun {
+                    number_of_clusters: 0x10,
+                    first_cluster: Some(0x100),
+                },
+                DataRun {
+                    number_of_clusters: 0x20,
+                    first_cluster: Some(0xf0),
+                },
+                DataRun {
+                    number_of_clusters: 5,
+                    first_cluster: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_data_runs() {
+        // Length field of 9 bytes, truncated run and a run before the start of the volume.
+        assert!(decode_data_runs(&[0x09, 0x01]).is_err());
+        assert!(decode_data_runs(&[0x21, 0x10]).is_err());
+        assert!(decode_data_runs(&[0x11, 0x01, 0xff]).is_err());
+    }
+
+    #[test]
+    fn parses_mft_records() {
+        let record = MftRecord::parse(
+            mft_record(
+                MFT_RECORD_IN_USE,
+                7,
+                &[resident_attribute(DATA, "stream", &[0xaa; 600])],
+            ),
+            42,
+        )
+        .unwrap();
+
+        assert!(record.is_in_use());
+        assert!(!record.is_directory());
+        assert_eq!(record.sequence_number, 7);
+
+        // The value crosses the end of the first stride, whose bytes the fixups restore.
+        let attribute = record.attribute(DATA, "stream").unwrap();
+        assert!(matches!(
+            &attribute.content,
+            AttributeContent::Resident(value) if *value == vec![0xaa; 600]
+        ));
+
+        let mut torn_record = mft_record(MFT_RECORD_IN_USE, 7, &[]);
+        torn_record[UPDATE_SEQUENCE_STRIDE - 2] = 0;
+        assert!(MftRecord::parse(torn_record, 42).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_attributes() {
+        let attribute = resident_attribute(DATA, "", b"value");
+
+        // Everything up to the end of the value (the padding after it is not needed).
+        for length in 0..0x18 + b"value".len() {
+            assert!(Attribute::parse(&attribute[..length]).is_err());
+        }
+        assert!(Attribute::parse(&attribute).is_ok());
+    }
+
+    #[test]
+    fn reads_file_by_path() {
+        let image = volume_image(index_root(
+            4096,
+            &[
+                ("Stale.txt", file_reference(6, 2)),
+                ("Diagnosis.txt", file_reference(6, 3)),
+            ],
+        ));
+        let volume = NtfsVolume::open(&image, 0).unwrap().unwrap();
+
+        let file = volume
+            .read_file_at_path(&["diagnosis.TXT"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(file.mft_record_number, 6);
+        assert_eq!(file.bytes, b"telemetry");
+
+        // An entry whose sequence number does not match the record is stale.
+        assert!(volume.read_file_at_path(&["Stale.txt"]).unwrap().is_none());
+        assert!(volume
+            .read_file_at_path(&["Missing.txt"])
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_directory_indexes() {
+        let reference = file_reference(6, 3);
+
+        // Index record sizes of 0xffff_ffff and 1 byte.
+        for index_record_size in [u32::MAX, 1] {
+            let image = volume_image(index_root(index_record_size, &[("A", reference)]));
+            let volume = NtfsVolume::open(&image, 0).unwrap().unwrap();
+            assert!(volume.read_file_at_path(&["A"]).is_err());
+        }
+
+        // Index root shorter than its header.
+        let image = volume_image(vec![0u8; 12]);
+        let volume = NtfsVolume::open(&image, 0).unwrap().unwrap();
+        assert!(volume.read_file_at_path(&["A"]).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! Partition tables: MBR (including extended partitions) and GPT.
+
+use miette::Result;
+use serde::Serialize;
+
+use super::{ntfs::NTFS_OEM_ID, DiskImage};
+
+/// Partition tables address the disk in 512-byte sectors (4K-native disks are not supported).
+const SECTOR_SIZE: u64 = 512;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_PARTITION_TABLE_OFFSET: usize = 446;
+const MBR_PARTITION_ENTRY_SIZE: usize = 16;
+
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_EXTENDED_CHS: u8 = 0x05;
+const MBR_TYPE_EXTENDED_LBA: u8 = 0x0f;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// Upper bound on the number of logical partitions followed in an extended partition,
+/// in case its chain of boot records loops.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
+/// Upper bound on the number of GPT entries read, in case the header is damaged.
+const MAX_GPT_ENTRIES: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionScheme {
+    /// The image has no partition table: it is a single volume.
+    None,
+    Mbr,
+    Gpt,
+}
+
+/// A region of the image that may hold a file system.
+#[derive(Debug, Clone, Serialize)]
+pub struct Partition {
+    /// 1-based number of the partition in its table (0 for a volume image).
+    pub index: usize,
+    pub scheme: PartitionScheme,
+
+    /// Offset of the partition in the image, in bytes.
+    pub offset: u64,
+
+    /// Size of the partition in bytes.
+    pub size: u64,
+}
+
+/// Lists all partitions of the image.
+///
+/// The whole image is included as well (with index 0), so that images of a single volume
+/// are handled too; it only matters if it starts with a file system.
+pub fn find_partitions(image: &dyn DiskImage) -> Result<Vec<Partition>> {
+    let mut partitions = vec![Partition {
+        index: 0,
+        scheme: PartitionScheme::None,
+        offset: 0,
+        size: image.size(),
+    }];
+
+    if image.size() < SECTOR_SIZE {
+        return Ok(partitions);
+    }
+
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    image.read_exact_at(0, &mut mbr)?;
+
+    // Volume boot records end with the same signature as an MBR,
+    // but their partition table area holds boot code.
+    if mbr[510..512] != MBR_SIGNATURE || mbr[3..11] == *NTFS_OEM_ID {
+        return Ok(partitions);
+    }
+
+    let entries = mbr_partition_entries(&mbr);
+
+    if entries
+        .iter()
+        .any(|entry| entry.partition_type == MBR_TYPE_GPT_PROTECTIVE)
+    {
+        partitions.extend(gpt_partitions(image)?);
+        return Ok(partitions);
+    }
+
+    for (entry_index, entry) in entries.iter().enumerate() {
+        match entry.partition_type {
+            MBR_TYPE_EMPTY => {}
+            MBR_TYPE_EXTENDED_CHS | MBR_TYPE_EXTENDED_LBA => {
+                // Logical partitions are numbered from 5, after the four primary ones.
+                let first_index = 5 + partitions
+                    .iter()
+                    .filter(|partition| partition.index >= 5)
+                    .count();
+                partitions.extend(logical_partitions(image, entry, first_index)?);
+            }
+            _ => partitions.push(Partition {
+                index: entry_index + 1,
+                scheme: PartitionScheme::Mbr,
+                offset: u64::from(entry.first_sector) * SECTOR_SIZE,
+                size: u64::from(entry.number_of_sectors) * SECTOR_SIZE,
+            }),
+        }
+    }
+
+    Ok(partitions)
+}
+
+/// A partition entry of an MBR or of an extended boot record.
+struct MbrPartitionEntry {
+    partition_type: u8,
+    first_sector: u32,
+    number_of_sectors: u32,
+}
+
+fn mbr_partition_entries(boot_record: &[u8]) -> Vec<MbrPartitionEntry> {
+    (0..4)
+        .map(|entry_index| {
+            let entry = &boot_record
+                [MBR_PARTITION_TABLE_OFFSET + entry_index * MBR_PARTITION_ENTRY_SIZE..]
+                [..MBR_PARTITION_ENTRY_SIZE];
+
+            MbrPartitionEntry {
+                partition_type: entry[4],
+                first_sector: u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]),
+                number_of_sectors: u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]),
+            }
+        })
+        .collect()
+}
+
+/// Follows the chain of extended boot records of an extended partition.
+///
+/// The first entry of every boot record describes a logical partition (relative to that
+/// boot record), the second one the next boot record (relative to the extended partition).
+fn logical_partitions(
+    image: &dyn DiskImage,
+    extended_partition: &MbrPartitionEntry,
+    first_index: usize,
+) -> Result<Vec<Partition>> {
+    let extended_partition_start = u64::from(extended_partition.first_sector);
+    let mut boot_record_sector = extended_partition_start;
+    let mut partitions = Vec::new();
+
+    while partitions.len() < MAX_LOGICAL_PARTITIONS {
+        let boot_record_offset = boot_record_sector * SECTOR_SIZE;
+        if boot_record_offset + SECTOR_SIZE > image.size() {
+            break;
+        }
+
+        let mut boot_record = [0u8; SECTOR_SIZE as usize];
+        image.read_exact_at(boot_record_offset, &mut boot_record)?;
+
+        if boot_record[510..512] != MBR_SIGNATURE {
+            break;
+        }
+
+        let entries = mbr_partition_entries(&boot_record);
+
+        if entries[0].partition_type != MBR_TYPE_EMPTY {
+            partitions.push(Partition {
+                index: first_index + partitions.len(),
+                scheme: PartitionScheme::Mbr,
+                offset: (boot_record_sector + u64::from(entries[0].first_sector)) * SECTOR_SIZE,
+                size: u64::from(entries[0].number_of_sectors) * SECTOR_SIZE,
+            });
+        }
+
+        if entries[1].partition_type == MBR_TYPE_EMPTY || entries[1].first_sector == 0 {
+            break;
+        }
+        boot_record_sector = extended_partition_start + u64::from(entries[1].first_sector);
+    }
+
+    Ok(partitions)
+}
+
+fn gpt_partitions(image: &dyn DiskImage) -> Result<Vec<Partition>> {
+    let mut header = [0u8; SECTOR_SIZE as usize];
+    if image.size() < 2 * SECTOR_SIZE {
+        return Ok(Vec::new());
+    }
+    image.read_exact_at(SECTOR_SIZE, &mut header)?;
+
+    if &header[0..8] != GPT_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            header[offset],
+            header[offset + 1],
+            header[offset + 2],
+            header[offset + 3],
+        ])
+    };
+
+    let entries_sector = u64::from_le_bytes(header[0x48..0x50].try_into().unwrap());
+    let number_of_entries = read_u32(0x50).min(MAX_GPT_ENTRIES);
+    let entry_size = read_u32(0x54) as usize;
+
+    let entries_end = entries_sector
+        .saturating_mul(SECTOR_SIZE)
+        .saturating_add(u64::from(number_of_entries) * entry_size as u64);
+    if entry_size < 48 || entries_end > image.size() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = vec![0u8; number_of_entries as usize * entry_size];
+    image.read_exact_at(entries_sector * SECTOR_SIZE, &mut entries)?;
+
+    let partitions = entries
+        .chunks_exact(entry_size)
+        .enumerate()
+        .filter(|(_, entry)| entry[0..16].iter().any(|&byte| byte != 0))
+        .map(|(entry_index, entry)| {
+            let first_sector = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+            let last_sector = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+
+            Partition {
+                index: entry_index + 1,
+                scheme: PartitionScheme::Gpt,
+                offset: first_sector * SECTOR_SIZE,
+                size: (last_sector.saturating_sub(first_sector) + 1) * SECTOR_SIZE,
+            }
+        })
+        .collect();
+
+    Ok(partitions)
+}
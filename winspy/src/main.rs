@@ -1,27 +1,168 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
 
 use argh::FromArgs;
-use detectors::{AllDetectors, EventTranscriptProcessor};
-use miette::{Context, IntoDiagnostic, Result};
+use detectors::{DetectorRegistry, EventTranscriptProcessor};
+use miette::{miette, Context, IntoDiagnostic, Result};
 use tracing::warn;
 use tracing_subscriber::EnvFilter;
 
-use crate::{logging::initialize_tracing, reader::EventTranscriptReader};
+use crate::{
+    archive::ExtractedArchive,
+    clock_skew::DEFAULT_CLOCK_JUMP_THRESHOLD_SECONDS,
+    diagnosis::{find_files, ArtifactKind, DiagnosisFolder},
+    downloaded_settings::{CollectionCoverage, DownloadedSettings},
+    evidence::{EvidenceGuard, EvidenceSnapshot},
+    image::{disk_image_files, extract_event_transcripts, open_disk_image},
+    logging::initialize_tracing,
+    models::tag_description::DEFAULT_TAG_LOCALE,
+    os_version::{OsVersionFile, OsVersionReport},
+    output::{
+        AnalysisOutput, DatabaseMetadata, EvidenceMetadata, OutputMetadata, RejectedRowsReport,
+    },
+    reader::{
+        event_store::EventStoreReader, filter::EventFilter, in_memory::InMemoryDatabase,
+        EventSourceReader, EventTranscriptReader, EventTranscriptReaderOptions,
+    },
+};
 
+mod archive;
+mod clock_skew;
 mod detectors;
+mod diagnosis;
+mod downloaded_settings;
+mod evidence;
+mod evidence_summary;
+mod image;
 mod logging;
 mod models;
+mod os_version;
+mod output;
 mod reader;
+mod sqlite;
+mod temporary_database;
+mod user;
 
 #[derive(FromArgs)]
 /// A simple Windows 10/11 event parser and vizualizer
 pub struct CmdArguments {
-    /// path to the EventTrancript.db
+    /// path to the EventTrancript.db (can be given multiple times,
+    /// events found in several copies are only reported once)
     #[argh(option, short = 'i')]
-    pub database_path: String,
-    /// path to the output JSON file
+    pub database_paths: Vec<String>,
+    /// path to an EventStore.db (can be given multiple times)
+    #[argh(option, short = 'e')]
+    pub event_store_paths: Vec<String>,
+    /// path to a raw (dd) or EWF disk image (the first segment, .E01), from whose NTFS
+    /// volumes the EventTranscript.db is read directly (can be given multiple times)
+    #[argh(option)]
+    pub image: Vec<String>,
+    /// path to a ZIP triage archive (e.g. from KAPE or Velociraptor), from which the
+    /// Diagnosis folder is extracted into a temporary directory (can be given multiple times)
+    #[argh(option)]
+    pub archive: Vec<String>,
+    /// path to a Diagnosis folder (C:\ProgramData\Microsoft\Diagnosis),
+    /// whose artifacts are discovered automatically
+    #[argh(option, short = 'd')]
+    pub diagnosis_folder: Option<String>,
+    /// path to a DownloadedSettings folder, used to report which events
+    /// could have been collected (taken from the Diagnosis folder if not given)
+    #[argh(option)]
+    pub downloaded_settings: Option<String>,
+    /// path to an osver.txt, compared with the OS version events report
+    /// (taken from the Diagnosis folder if not given)
+    #[argh(option)]
+    pub osver: Option<String>,
+    /// path to the output JSON file (required unless listing the detectors)
     #[argh(option, short = 'o')]
-    pub output_file: String,
+    pub output_file: Option<String>,
+    /// carve deleted events from free pages and unused space of the database
+    #[argh(switch)]
+    pub carve: bool,
+    /// skip rows that cannot be decoded and report them next to the output file
+    #[argh(switch)]
+    pub lenient: bool,
+    /// only events at or after this time (RFC 3339, e.g. 2024-05-01T00:00:00Z)
+    #[argh(option)]
+    pub start_time: Option<DateTime<Utc>>,
+    /// only events before this time (RFC 3339)
+    #[argh(option)]
+    pub end_time: Option<DateTime<Utc>>,
+    /// only events whose name starts with this prefix
+    #[argh(option)]
+    pub event_name_prefix: Option<String>,
+    /// only events whose name matches this glob pattern (*, ? and [...])
+    #[argh(option)]
+    pub event_name_glob: Option<String>,
+    /// only events of the producer with this name (e.g. Windows)
+    #[argh(option)]
+    pub producer: Option<String>,
+    /// only events of the provider group with this GUID
+    #[argh(option)]
+    pub provider_group_guid: Option<String>,
+    /// only core (true) or only non-core (false) events
+    #[argh(option)]
+    pub is_core: Option<bool>,
+    /// only events of this user (a SID or an ext.user.localId such as m:S-1-5-21-...)
+    #[argh(option)]
+    pub user: Option<String>,
+    /// preferred locale of tag descriptions, e.g. de-DE (default: en-US, which is also
+    /// used for tags that are not described in the preferred locale)
+    #[argh(option, default = "DEFAULT_TAG_LOCALE.to_string()")]
+    pub locale: String,
+    /// smallest change of the skew between payload times and database timestamps
+    /// that is reported as a clock jump, in seconds (default: 60)
+    #[argh(option, default = "DEFAULT_CLOCK_JUMP_THRESHOLD_SECONDS")]
+    pub clock_jump_threshold: i64,
+    /// number of threads the detectors run on (default: number of CPUs)
+    #[argh(option)]
+    pub jobs: Option<usize>,
+    /// only run these detectors (can be given multiple times or separated by commas,
+    /// see list-detectors)
+    #[argh(option)]
+    pub detectors: Vec<String>,
+    /// do not run these detectors (can be given multiple times or separated by commas)
+    #[argh(option)]
+    pub skip_detectors: Vec<String>,
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    ListDetectors(ListDetectorsCommand),
+}
+
+#[derive(FromArgs)]
+/// List the detectors, with the events each of them looks for.
+#[argh(subcommand, name = "list-detectors")]
+pub struct ListDetectorsCommand {}
+
+/// Splits detector names given on the command line, e.g. `--detectors battery,usb`.
+fn split_detector_names(arguments: &[String]) -> Vec<String> {
+    arguments
+        .iter()
+        .flat_map(|argument| argument.split(','))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn print_detectors(registry: &DetectorRegistry) {
+    for detector in registry.metadata() {
+        println!(
+            "{} {}: {}",
+            detector.name, detector.version, detector.description
+        );
+        for source_event_name in detector.source_event_names {
+            println!("    {source_event_name}");
+        }
+    }
 }
 
 #[tokio::main]
@@ -35,29 +176,370 @@ async fn main() -> Result<()> {
 
     let cli_arguments: CmdArguments = argh::from_env();
 
-    let sqlite_database_path = Path::new(&cli_arguments.database_path);
-    let database = EventTranscriptReader::new(sqlite_database_path)
-        .await
-        .wrap_err("Failed to initialize EventTranscriptReader.")?;
+    let analysis_started_at = Utc::now();
+
+    if let Some(Command::ListDetectors(_)) = cli_arguments.command {
+        print_detectors(&DetectorRegistry::new());
+        return Ok(());
+    }
+
+    let Some(output_file) = &cli_arguments.output_file else {
+        return Err(miette!("No output file: pass one with -o."));
+    };
+
+    let detectors = DetectorRegistry::new().select(
+        &split_detector_names(&cli_arguments.detectors),
+        &split_detector_names(&cli_arguments.skip_detectors),
+    )?;
+
+    let jobs = match cli_arguments.jobs {
+        Some(0) => return Err(miette!("--jobs must be at least 1.")),
+        Some(jobs) => jobs,
+        None => std::thread::available_parallelism().map_or(1, usize::from),
+    };
+
+    let diagnosis_folder = match &cli_arguments.diagnosis_folder {
+        Some(diagnosis_folder_path) => {
+            let diagnosis_folder = DiagnosisFolder::discover(Path::new(diagnosis_folder_path))
+                .wrap_err("Failed to discover the artifacts of the Diagnosis folder.")?;
+            diagnosis_folder.log_summary();
+
+            Some(diagnosis_folder)
+        }
+        None => None,
+    };
+
+    let mut database_paths = cli_arguments
+        .database_paths
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    let mut event_store_paths = cli_arguments
+        .event_store_paths
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    let mut settings_paths = match &cli_arguments.downloaded_settings {
+        Some(settings_directory) => find_files(Path::new(settings_directory), &|name| {
+            name.ends_with(".json")
+        })
+        .wrap_err("Failed to find the DownloadedSettings files.")?,
+        None => Vec::new(),
+    };
+    let image_paths = cli_arguments
+        .image
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    let archive_paths = cli_arguments
+        .archive
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    let mut os_version_path = cli_arguments.osver.as_ref().map(PathBuf::from);
+    let mut evidence_paths = [
+        database_paths.clone(),
+        event_store_paths.clone(),
+        image_paths
+            .iter()
+            .map(|image_path| disk_image_files(image_path))
+            .collect::<Result<Vec<_>>>()?
+            .concat(),
+        archive_paths.clone(),
+        settings_paths.clone(),
+        os_version_path.iter().cloned().collect(),
+    ]
+    .concat();
+
+    if let Some(diagnosis_folder) = &diagnosis_folder {
+        database_paths.extend(diagnosis_folder.valid_artifact_paths(ArtifactKind::EventTranscript));
+        event_store_paths.extend(diagnosis_folder.valid_artifact_paths(ArtifactKind::EventStore));
+        if cli_arguments.downloaded_settings.is_none() {
+            settings_paths
+                .extend(diagnosis_folder.valid_artifact_paths(ArtifactKind::DownloadedSettings));
+        }
+        if os_version_path.is_none() {
+            os_version_path = diagnosis_folder
+                .valid_artifact_paths(ArtifactKind::OsVersion)
+                .into_iter()
+                .next();
+        }
+        evidence_paths.extend(diagnosis_folder.existing_artifact_paths());
+    }
+
+    // Extracted files are not evidence themselves: the archives they come from are.
+    let archives = archive_paths
+        .iter()
+        .map(|archive_path| ExtractedArchive::extract(archive_path))
+        .collect::<Result<Vec<_>>>()?;
+
+    for archive in &archives {
+        database_paths.extend(archive.valid_artifact_paths(ArtifactKind::EventTranscript));
+        event_store_paths.extend(archive.valid_artifact_paths(ArtifactKind::EventStore));
+        if cli_arguments.downloaded_settings.is_none() {
+            settings_paths.extend(archive.valid_artifact_paths(ArtifactKind::DownloadedSettings));
+        }
+        if os_version_path.is_none() {
+            os_version_path = archive
+                .valid_artifact_paths(ArtifactKind::OsVersion)
+                .into_iter()
+                .next();
+        }
+    }
+
+    if database_paths.is_empty() && event_store_paths.is_empty() && image_paths.is_empty() {
+        return Err(miette!(
+            "No database to process: pass an EventTranscript.db with -i, an EventStore.db with -e, \
+            a Diagnosis folder containing them with -d, a disk image with --image \
+            or a triage archive with --archive."
+        ));
+    }
+
+    // Verifies the evidence again however processing ends, including on errors below.
+    let evidence_guard = EvidenceGuard::new(
+        EvidenceSnapshot::take(&evidence_paths)
+            .wrap_err("Failed to hash the evidence before processing.")?,
+    );
+
+    let os_version_file = match &os_version_path {
+        Some(os_version_path) => match OsVersionFile::load(os_version_path) {
+            Ok(os_version_file) => Some(os_version_file),
+            Err(error) => {
+                warn!(
+                    "Ignoring OS version file {}: {:?}",
+                    os_version_path.display(),
+                    error
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    let reader_options = EventTranscriptReaderOptions {
+        carve_deleted_records: cli_arguments.carve,
+        lenient: cli_arguments.lenient,
+        filter: EventFilter {
+            start_time: cli_arguments.start_time,
+            end_time: cli_arguments.end_time,
+            event_name_prefix: cli_arguments.event_name_prefix.clone(),
+            event_name_glob: cli_arguments.event_name_glob.clone(),
+            producer_name: cli_arguments.producer.clone(),
+            provider_group_guid: cli_arguments.provider_group_guid.clone(),
+            is_core: cli_arguments.is_core,
+            user: cli_arguments.user.clone(),
+        },
+    };
 
-    // for event in database.load_all_events().await? {
-    //     println!("{:?}", event);
-    // }
+    let number_of_databases = database_paths.len() + event_store_paths.len() + image_paths.len();
+    let mut readers = Vec::with_capacity(number_of_databases);
+    let mut database_metadata = Vec::with_capacity(number_of_databases);
 
-    let processor = EventTranscriptProcessor::new_from_event_transcript_reader(database)
+    for database_path in &database_paths {
+        let database = EventTranscriptReader::new(database_path, reader_options.clone())
+            .await
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to initialize EventTranscriptReader for {}.",
+                    database_path.display()
+                )
+            })?;
+
+        database_metadata.push(DatabaseMetadata {
+            path: database_path.clone(),
+            kind: ArtifactKind::EventTranscript,
+            schema: Some(database.schema_version().into()),
+            image: None,
+            archive: archives
+                .iter()
+                .find_map(|archive| archive.source_of(database_path)),
+            number_of_records_without_json_payload: None,
+        });
+        readers.push(EventSourceReader::Transcript(Box::new(database)));
+    }
+
+    let mut image_metadata = Vec::with_capacity(image_paths.len());
+
+    for image_path in &image_paths {
+        let (image, metadata) = open_disk_image(image_path)?;
+        image_metadata.push(metadata);
+
+        let transcripts =
+            extract_event_transcripts(image_path, image.as_ref()).wrap_err_with(|| {
+                format!(
+                    "Failed to read disk image {}.",
+                    image_path.display()
+                )
+            })?;
+
+        for transcript in transcripts {
+            let in_memory_database = InMemoryDatabase {
+                path: transcript.display_path(),
+                database: transcript.database.bytes.as_slice().into(),
+                wal: transcript
+                    .wal
+                    .as_ref()
+                    .map(|wal| wal.bytes.as_slice().into()),
+            };
+
+            let database =
+                EventTranscriptReader::new_in_memory(in_memory_database, reader_options.clone())
+                    .await
+                    .wrap_err_with(|| {
+                        format!(
+                            "Failed to initialize EventTranscriptReader for {}.",
+                            transcript.display_path().display()
+                        )
+                    })?;
+
+            database_metadata.push(DatabaseMetadata {
+                path: transcript.display_path(),
+                kind: ArtifactKind::EventTranscript,
+                schema: Some(database.schema_version().into()),
+                image: Some(transcript.metadata()),
+                archive: None,
+                number_of_records_without_json_payload: None,
+            });
+            readers.push(EventSourceReader::Transcript(Box::new(database)));
+        }
+    }
+
+    for event_store_path in &event_store_paths {
+        let event_store = EventStoreReader::new(event_store_path, &reader_options.filter)
+            .await
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to initialize EventStoreReader for {}.",
+                    event_store_path.display()
+                )
+            })?;
+
+        database_metadata.push(DatabaseMetadata {
+            path: event_store_path.clone(),
+            kind: ArtifactKind::EventStore,
+            schema: None,
+            image: None,
+            archive: archives
+                .iter()
+                .find_map(|archive| archive.source_of(event_store_path)),
+            // Filled in once the store has been read.
+            number_of_records_without_json_payload: None,
+        });
+        readers.push(EventSourceReader::Store(Box::new(event_store)));
+    }
+
+    let processor = EventTranscriptProcessor::new_from_readers(readers, &cli_arguments.locale)
         .await
         .wrap_err("Failed to initialize EventTranscriptProcessor.")?;
 
-    let all_detectors = AllDetectors::new();
-    let processed_events = processor.process_events(all_detectors);
+    let tag_locales = processor.tag_locales();
+    if tag_locales.number_of_tags_in_fallback_locale > 0 {
+        warn!(
+            "{} tags are not described in locale {} (available: {}).",
+            tag_locales.number_of_tags_in_fallback_locale,
+            tag_locales.preferred_locale,
+            tag_locales.available_locales.join(", ")
+        );
+    }
+
+    let collection_coverage = if settings_paths.is_empty() {
+        None
+    } else {
+        Some(CollectionCoverage::new(
+            DownloadedSettings::load_from_files(&settings_paths),
+            detectors.source_event_names_by_detector(),
+        ))
+    };
 
-    for processed_event in processed_events.iter() {
+    let detector_metadata = detectors.metadata();
+    let processing_results = processor
+        .process_events(
+            detectors,
+            jobs,
+            TimeDelta::seconds(cli_arguments.clock_jump_threshold),
+        )
+        .await
+        .wrap_err("Failed to process events.")?;
+
+    for (database_path, number_of_records) in &processing_results.records_without_json_payload {
+        if let Some(metadata) = database_metadata
+            .iter_mut()
+            .find(|metadata| metadata.path == *database_path)
+        {
+            metadata.number_of_records_without_json_payload = Some(*number_of_records);
+        }
+    }
+
+    for processed_event in processing_results.events.iter() {
         println!("{processed_event:?}");
         println!();
     }
 
-    let output_content = serde_json::to_string(&processed_events).into_diagnostic()?;
-    fs::write(cli_arguments.output_file, output_content).into_diagnostic()?;
+    processing_results.evidence_summary.print_tables();
+
+    for malformed_envelope in &processing_results.malformed_envelopes {
+        warn!(
+            "Malformed envelope in {} events {} ({}).",
+            malformed_envelope.number_of_events,
+            malformed_envelope.event_name,
+            malformed_envelope.reason
+        );
+    }
+
+    processing_results.clock_skew.log_summary();
+
+    let os_version_report = OsVersionReport::new(
+        os_version_file,
+        processing_results.observed_os_versions,
+    );
+    os_version_report.log_summary();
+
+    let (evidence_before_processing, evidence_after_processing) = evidence_guard
+        .finish()
+        .wrap_err("Evidence integrity check failed.")?;
+
+    let output = AnalysisOutput {
+        metadata: OutputMetadata::new(
+            analysis_started_at,
+            database_metadata,
+            image_metadata,
+            diagnosis_folder,
+            tag_locales,
+            detector_metadata,
+            EvidenceMetadata {
+                before_processing: evidence_before_processing,
+                after_processing: evidence_after_processing,
+            },
+        ),
+        collection_coverage,
+        os_version: os_version_report,
+        malformed_envelopes: processing_results.malformed_envelopes,
+        users: processing_results.users,
+        clock_skew: processing_results.clock_skew,
+        evidence_summary: processing_results.evidence_summary,
+        events: processing_results.events,
+    };
+
+    let output_file_path = Path::new(output_file);
+    let output_content = serde_json::to_string(&output).into_diagnostic()?;
+    fs::write(output_file_path, output_content).into_diagnostic()?;
+
+    if cli_arguments.lenient {
+        let rejected_rows_report = RejectedRowsReport::new(processing_results.rejected_rows);
+        let rejected_rows_path = output_file_path.with_extension("rejected.json");
+
+        if rejected_rows_report.number_of_rejected_rows > 0 {
+            warn!(
+                "Rejected {} rows that could not be decoded, see {}.",
+                rejected_rows_report.number_of_rejected_rows,
+                rejected_rows_path.display()
+            );
+        }
+
+        let rejected_rows_content =
+            serde_json::to_string(&rejected_rows_report).into_diagnostic()?;
+        fs::write(rejected_rows_path, rejected_rows_content).into_diagnostic()?;
+    }
 
     drop(guard);
     Ok(())
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use miette::{Context, IntoDiagnostic, Result};
 use sqlx::SqliteConnection;
 
@@ -13,30 +15,40 @@ impl CategoryId {
         Self(id)
     }
 
-    pub async fn load_all_from_database_for_event(
+    #[inline]
+    pub fn value(self) -> i64 {
+        self.0
+    }
+
+    /// Loads the whole `event_categories` table, grouped by `full_event_name_hash`.
+    pub async fn load_all_event_mappings_from_database(
         connection: &mut SqliteConnection,
-        full_event_name_hash: i64,
-    ) -> Result<Vec<Self>> {
+    ) -> Result<HashMap<i64, Vec<Self>>> {
         let query_results = sqlx::query!(
-            "SELECT category_id \
-            FROM event_categories as ec \
-            WHERE ec.full_event_name_hash = $1",
-            full_event_name_hash
+            "SELECT full_event_name_hash, category_id \
+            FROM event_categories"
         )
         .fetch_all(connection)
         .await
         .into_diagnostic()
-        .wrap_err("Failed to fetch all event categories by event name hash.")?;
+        .wrap_err("Failed to fetch all event categories.")?;
 
-        let mut matching_category_ids = Vec::with_capacity(query_results.len());
+        let mut category_ids_by_event: HashMap<i64, Vec<Self>> = HashMap::new();
 
         for category_result in query_results {
+            let full_event_name_hash: i64 = require_some!(
+                category_result.full_event_name_hash,
+                "full_event_name_hash"
+            )?;
             let category_id: i64 = require_some!(category_result.category_id, "category_id")?;
 
-            matching_category_ids.push(Self(category_id));
+            category_ids_by_event
+                .entry(full_event_name_hash)
+                .or_default()
+                .push(Self(category_id));
         }
 
-        Ok(matching_category_ids)
+        Ok(category_ids_by_event)
     }
 }
 
@@ -74,8 +86,8 @@ impl Category {
         for query_result in query_results {
             let parsed_category: Category = Self {
                 id: CategoryId::new(require_some!(
-                    query_result.producer_id,
-                    "producer_id"
+                    query_result.category_id,
+                    "category_id"
                 )?),
                 name: require_some!(query_result.category_id_text, "category_id_text")?,
                 producer_id: ProducerId::new(require_some!(
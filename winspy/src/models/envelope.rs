@@ -0,0 +1,264 @@
+//! The Common Schema envelope that wraps every telemetry payload.
+//!
+//! See <https://learn.microsoft.com/en-us/windows/privacy/diagnostic-data-viewer-overview>
+//! for the meaning of the fields. Only the ones the analysis reads are typed;
+//! everything in `data` is specific to the event.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// `ext.user`: the user the event was logged for.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserExtension {
+    pub local_id: Option<String>,
+    pub id: Option<String>,
+}
+
+/// `ext.os`: the operating system.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OsExtension {
+    pub ver: Option<String>,
+}
+
+/// `ext`: the extensions of the envelope (only those that are read).
+#[derive(Debug, Clone, Default)]
+pub struct EnvelopeExtensions {
+    pub user: UserExtension,
+    pub os: OsExtension,
+}
+
+/// Typed view of a parsed payload.
+///
+/// Fields that are missing are left empty. Fields that are present but malformed are
+/// left empty as well, and the problem is recorded in [`CommonSchemaEnvelope::errors`].
+#[derive(Debug, Clone, Default)]
+pub struct CommonSchemaEnvelope {
+    pub time: Option<DateTime<Utc>>,
+    pub ext: EnvelopeExtensions,
+
+    /// Fields of the event itself.
+    pub data: Map<String, Value>,
+
+    /// Why parts of the envelope could not be decoded, each prefixed by the field.
+    pub errors: Vec<String>,
+}
+
+impl CommonSchemaEnvelope {
+    /// Decodes the envelope of a parsed payload, field by field.
+    pub fn from_payload(payload: &Value) -> Self {
+        let mut envelope = Self::default();
+
+        let Some(payload) = payload.as_object() else {
+            envelope
+                .errors
+                .push("payload is not a JSON object".to_string());
+            return envelope;
+        };
+
+        let errors = &mut envelope.errors;
+        envelope.time = decode_field(payload.get("time"), "time", errors);
+        envelope.data = decode_field(payload.get("data"), "data", errors);
+
+        let ext: Map<String, Value> = decode_field(payload.get("ext"), "ext", errors);
+        envelope.ext = EnvelopeExtensions {
+            user: decode_field(ext.get("user"), "ext.user", errors),
+            os: decode_field(ext.get("os"), "ext.os", errors),
+        };
+
+        envelope
+    }
+}
+
+/// Decodes a single field, falling back to its default if it is missing, `null` or malformed.
+fn decode_field<T>(value: Option<&Value>, field_path: &str, errors: &mut Vec<String>) -> T
+where
+    T: DeserializeOwned + Default,
+{
+    match value {
+        None | Some(Value::Null) => T::default(),
+        Some(value) => T::deserialize(value).unwrap_or_else(|error| {
+            errors.push(format!("{field_path}: {error}"));
+            T::default()
+        }),
+    }
+}
+
+/// Events whose envelope was malformed in the same way.
+#[derive(Debug, Clone, Serialize)]
+pub struct MalformedEnvelope {
+    pub event_name: String,
+    pub reason: String,
+    pub number_of_events: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Collects malformed envelopes over all processed events, grouped by event name and reason.
+#[derive(Debug, Default)]
+pub struct MalformedEnvelopeCollector {
+    malformed: BTreeMap<(String, String), MalformedEnvelope>,
+}
+
+impl MalformedEnvelopeCollector {
+    pub fn observe(
+        &mut self,
+        event_name: &str,
+        timestamp: DateTime<Utc>,
+        envelope: &CommonSchemaEnvelope,
+    ) {
+        for reason in &envelope.errors {
+            self.malformed
+                .entry((event_name.to_string(), reason.clone()))
+                .and_modify(|malformed| {
+                    malformed.number_of_events += 1;
+                    malformed.first_seen = malformed.first_seen.min(timestamp);
+                    malformed.last_seen = malformed.last_seen.max(timestamp);
+                })
+                .or_insert_with(|| MalformedEnvelope {
+                    event_name: event_name.to_string(),
+                    reason: reason.clone(),
+                    number_of_events: 1,
+                    first_seen: timestamp,
+                    last_seen: timestamp,
+                });
+        }
+    }
+
+    pub fn into_malformed_envelopes(self) -> Vec<MalformedEnvelope> {
+        self.malformed.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn decodes_envelopes() {
+        let envelope = CommonSchemaEnvelope::from_payload(&json!({
+            "ver": "4.0",
+            "name": "Microsoft.Windows.Test",
+            "time": "2024-01-17T10:20:30.1234567Z",
+            "ext": {
+                "user": { "localId": "m:S-1-5-21-1", "id": null },
+                "os": { "ver": "10.0.19045.4291.amd64fre.vb_release.191206-1406", "bootId": 7 },
+                "utc": { "seq": "not a number" }
+            },
+            "data": { "RemainingPercentage": 42 }
+        }));
+
+        assert!(
+            envelope.errors.is_empty(),
+            "{:?}",
+            envelope.errors
+        );
+        assert_eq!(
+            envelope.time,
+            Some(
+                Utc.with_ymd_and_hms(2024, 1, 17, 10, 20, 30).unwrap()
+                    + TimeDelta::nanoseconds(123_456_700)
+            )
+        );
+        assert_eq!(
+            envelope.ext.user.local_id.as_deref(),
+            Some("m:S-1-5-21-1")
+        );
+        assert_eq!(envelope.ext.user.id, None);
+        assert_eq!(
+            envelope.ext.os.ver.as_deref(),
+            Some("10.0.19045.4291.amd64fre.vb_release.191206-1406")
+        );
+        assert_eq!(envelope.data["RemainingPercentage"], 42);
+    }
+
+    #[test]
+    fn records_malformed_fields() {
+        let envelope = CommonSchemaEnvelope::from_payload(&json!({
+            "time": "yesterday",
+            "ext": { "user": { "localId": 5 }, "os": "Windows" },
+            "data": [1, 2]
+        }));
+
+        let fields = envelope
+            .errors
+            .iter()
+            .map(|error| error.split_once(": ").unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(fields, ["time", "data", "ext.user", "ext.os"]);
+        assert_eq!(envelope.time, None);
+        assert_eq!(envelope.ext.user.local_id, None);
+        assert!(envelope.data.is_empty());
+
+        let envelope = CommonSchemaEnvelope::from_payload(&json!("payload"));
+        assert_eq!(envelope.errors, ["payload is not a JSON object"]);
+    }
+
+    #[test]
+    fn collects_malformed_envelopes() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+        let malformed_user = CommonSchemaEnvelope::from_payload(&json!({
+            "ext": { "user": { "localId": 5 } }
+        }));
+        let malformed_time_and_user = CommonSchemaEnvelope::from_payload(&json!({
+            "time": 5,
+            "ext": { "user": { "localId": 5 } }
+        }));
+        let well_formed = CommonSchemaEnvelope::from_payload(&json!({}));
+
+        let mut collector = MalformedEnvelopeCollector::default();
+        collector.observe("B", start + TimeDelta::hours(2), &malformed_user);
+        collector.observe("B", start, &malformed_time_and_user);
+        collector.observe("B", start + TimeDelta::hours(1), &well_formed);
+        collector.observe("A", start + TimeDelta::hours(3), &malformed_user);
+
+        let malformed_envelopes = collector
+            .into_malformed_envelopes()
+            .into_iter()
+            .map(|malformed| {
+                (
+                    malformed.event_name,
+                    malformed.reason.split_once(": ").unwrap().0.to_string(),
+                    malformed.number_of_events,
+                    malformed.first_seen - start,
+                    malformed.last_seen - start,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let hours = TimeDelta::hours;
+        assert_eq!(
+            malformed_envelopes,
+            [
+                (
+                    "A".to_string(),
+                    "ext.user".to_string(),
+                    1,
+                    hours(3),
+                    hours(3)
+                ),
+                (
+                    "B".to_string(),
+                    "ext.user".to_string(),
+                    2,
+                    hours(0),
+                    hours(2)
+                ),
+                (
+                    "B".to_string(),
+                    "time".to_string(),
+                    1,
+                    hours(0),
+                    hours(0)
+                ),
+            ]
+        );
+    }
+}
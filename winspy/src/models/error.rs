@@ -1,10 +1,13 @@
+use std::path::PathBuf;
+
 use miette::{Context, IntoDiagnostic, Result};
+use serde::Serialize;
 use sqlx::sqlite::SqliteRow;
 use sqlx::{Column, Row, TypeInfo, ValueRef};
 use thiserror::Error;
 
 /// Run-time serialized column from [`SqliteRow`] for diagnostics.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 pub struct SavedSqliteColumn {
     pub name: String,
     pub ordinal: usize,
@@ -17,7 +20,7 @@ pub struct SavedSqliteColumn {
 }
 
 /// Run-time serialized [`SqliteRow`] for diagnostics.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 pub struct SavedSqliteRow {
     pub columns: Vec<SavedSqliteColumn>,
 }
@@ -36,7 +39,6 @@ macro_rules! try_deserialize_some_from_row {
     }};
 }
 
-#[allow(dead_code)]
 impl SavedSqliteRow {
     pub fn from_sqlite_row(sqlite_row: &SqliteRow) -> Result<Self> {
         let mut saved_columns: Vec<SavedSqliteColumn> = Vec::with_capacity(sqlite_row.len());
@@ -51,7 +53,9 @@ impl SavedSqliteRow {
             let column_type_name = column_type_info.name();
 
             // See also: <https://docs.rs/sqlx-sqlite/0.7.4/src/sqlx_sqlite/type_info.rs.html#35>
+            // The type of a NULL value is reported as the declared type of its column.
             let saved_string_value: Option<String> = match column_type_name {
+                _ if column_value.is_null() => None,
                 "NULL" => None,
                 "TEXT" => try_deserialize_some_from_row!(sqlite_row, column, "TEXT" => String),
                 "REAL" => try_deserialize_some_from_row!(sqlite_row, column, "REAL" => f64)
@@ -69,10 +73,9 @@ impl SavedSqliteRow {
                 "DATETIME" => {
                     try_deserialize_some_from_row!(sqlite_row, column, "DATETIME" => String)
                 }
-                _ => panic!(
-                    "unexpected SQLite type name: {}",
-                    column_type_name
-                ),
+                // Not reported by sqlx for values at the moment, but a corrupt or foreign
+                // database should not abort the run over it.
+                _ => save_raw_value(sqlite_row, column.ordinal())?,
             };
 
             saved_columns.push(SavedSqliteColumn {
@@ -89,12 +92,112 @@ impl SavedSqliteRow {
     }
 }
 
-#[derive(Error, Debug)]
-#[allow(dead_code)]
+/// Saves a value of a type not listed above as text if it is valid UTF-8,
+/// otherwise as its bytes (formatted like a `BLOB`).
+fn save_raw_value(sqlite_row: &SqliteRow, ordinal: usize) -> Result<Option<String>> {
+    let bytes: Vec<u8> = sqlite_row
+        .try_get_unchecked(ordinal)
+        .into_diagnostic()
+        .wrap_err("Column has an unknown type and could not be read as bytes.")?;
+
+    Ok(Some(match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => format!("[{}]", itertools::join(error.into_bytes(), ", ")),
+    }))
+}
+
+#[derive(Error, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum EventReaderError {
-    #[error("failed to parse record from table {table_name}")]
+    #[error("failed to parse record from table {table_name} of {}: {reason}", database_path.display())]
     RecordParsingError {
+        database_path: PathBuf,
         table_name: String,
+
+        /// Why the record could not be parsed.
+        reason: String,
         saved_row: SavedSqliteRow,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions};
+
+    use super::*;
+
+    async fn fetch_row(query: &str) -> SqliteRow {
+        let mut connection = "sqlite::memory:"
+            .parse::<SqliteConnectOptions>()
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE events_persisted (timestamp FILETIME, sid VARCHAR(16), payload); \
+            INSERT INTO events_persisted VALUES (133499232001234567, 'S-1-5-18', x'00ff'); \
+            INSERT INTO events_persisted VALUES (NULL, 'text', CAST('{}' AS BLOB));",
+        )
+        .execute(&mut connection)
+        .await
+        .unwrap();
+
+        sqlx::query(query).fetch_one(&mut connection).await.unwrap()
+    }
+
+    fn saved_values(saved_row: &SavedSqliteRow) -> Vec<(&str, &str, Option<&str>)> {
+        saved_row
+            .columns
+            .iter()
+            .map(|column| {
+                (
+                    column.name.as_str(),
+                    column.r#type.as_str(),
+                    column.value.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn saves_rows_with_unusual_declared_types() {
+        let row = fetch_row("SELECT * FROM events_persisted WHERE timestamp IS NOT NULL").await;
+        assert_eq!(
+            saved_values(&SavedSqliteRow::from_sqlite_row(&row).unwrap()),
+            [
+                ("timestamp", "INTEGER", Some("133499232001234567")),
+                ("sid", "TEXT", Some("S-1-5-18")),
+                ("payload", "BLOB", Some("[0, 255]")),
+            ]
+        );
+
+        let row = fetch_row("SELECT * FROM events_persisted WHERE timestamp IS NULL").await;
+        assert_eq!(
+            saved_values(&SavedSqliteRow::from_sqlite_row(&row).unwrap())[0],
+            ("timestamp", "NULL", None)
+        );
+    }
+
+    #[tokio::test]
+    async fn saves_values_of_unknown_types_raw() {
+        let row = fetch_row("SELECT * FROM events_persisted WHERE timestamp IS NOT NULL").await;
+        assert_eq!(
+            save_raw_value(&row, 0).unwrap().as_deref(),
+            Some("133499232001234567")
+        );
+        assert_eq!(
+            save_raw_value(&row, 1).unwrap().as_deref(),
+            Some("S-1-5-18")
+        );
+        assert_eq!(
+            save_raw_value(&row, 2).unwrap().as_deref(),
+            Some("[0, 255]")
+        );
+
+        let row = fetch_row("SELECT * FROM events_persisted WHERE timestamp IS NULL").await;
+        assert_eq!(
+            save_raw_value(&row, 2).unwrap().as_deref(),
+            Some("{}")
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A Windows `FILETIME` (also known as an LDAP timestamp):
+/// the number of 100-nanosecond intervals since 1. 1. 1601 UTC.
+///
+/// This is the format of the `timestamp` column in `EventTrancript.db`.
+#[derive(
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Clone,
+    Copy,
+    Hash,
+    Serialize,
+    Deserialize
+)]
+#[serde(transparent)]
+pub struct FileTime(i64);
+
+/// The number of 100-nanosecond intervals (ticks) in a second.
+const TICKS_PER_SECOND: i64 = 10_000_000;
+
+/// The number of nanoseconds in a second.
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// The number of nanoseconds in a tick.
+const NANOS_PER_TICK: i64 = 100;
+
+#[allow(dead_code)]
+impl FileTime {
+    #[inline]
+    pub fn from_ticks(ticks: i64) -> Self {
+        Self(ticks)
+    }
+
+    /// Raw number of 100-nanosecond intervals since 1. 1. 1601 UTC.
+    #[inline]
+    pub fn ticks(self) -> i64 {
+        self.0
+    }
+
+    fn epoch() -> DateTime<Utc> {
+        // PANIC SAFETY: 1. 1. 1601 00:00:00 is a valid, unambiguous UTC date.
+        Utc.with_ymd_and_hms(1601, 1, 1, 0, 0, 0).single().unwrap()
+    }
+
+    /// Converts the timestamp into a UTC one, keeping the full 100-nanosecond precision.
+    ///
+    /// Returns `None` if the timestamp is out of the range supported by [`chrono`].
+    pub fn to_utc(self) -> Option<DateTime<Utc>> {
+        let seconds = self.0.div_euclid(TICKS_PER_SECOND);
+        let subsecond_nanos = (self.0.rem_euclid(TICKS_PER_SECOND) * NANOS_PER_TICK) as u32;
+
+        let time_delta_since_epoch = TimeDelta::new(seconds, subsecond_nanos)?;
+
+        Self::epoch().checked_add_signed(time_delta_since_epoch)
+    }
+
+    /// Converts a UTC timestamp into a `FILETIME`, rounding down to whole 100 nanoseconds
+    /// (the inverse of [`FileTime::to_utc`]).
+    ///
+    /// Returns `None` if the timestamp does not fit into a `FILETIME`.
+    pub fn from_utc(timestamp: &DateTime<Utc>) -> Option<Self> {
+        let time_delta_since_epoch = timestamp.signed_duration_since(Self::epoch());
+
+        // Before the epoch, both parts are negative; the nanoseconds are made positive,
+        // like in `to_utc`.
+        let mut seconds = time_delta_since_epoch.num_seconds();
+        let mut subsecond_nanos = i64::from(time_delta_since_epoch.subsec_nanos());
+        if subsecond_nanos < 0 {
+            seconds -= 1;
+            subsecond_nanos += NANOS_PER_SECOND;
+        }
+
+        // Computed in 128 bits, as the seconds alone may not fit near the limits.
+        let ticks = i128::from(seconds) * i128::from(TICKS_PER_SECOND)
+            + i128::from(subsecond_nanos / NANOS_PER_TICK);
+
+        i64::try_from(ticks).ok().map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_known_timestamps() {
+        assert_eq!(
+            FileTime::from_ticks(0).to_utc(),
+            Some(FileTime::epoch())
+        );
+        assert_eq!(
+            FileTime::from_utc(&FileTime::epoch()),
+            Some(FileTime::from_ticks(0))
+        );
+
+        let unix_epoch = DateTime::UNIX_EPOCH;
+        assert_eq!(
+            FileTime::from_utc(&unix_epoch),
+            Some(FileTime::from_ticks(116_444_736_000_000_000))
+        );
+
+        let timestamp = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap()
+            + TimeDelta::nanoseconds(123_456_700);
+        assert_eq!(
+            FileTime::from_utc(&timestamp),
+            Some(FileTime::from_ticks(133_499_232_001_234_567))
+        );
+    }
+
+    #[test]
+    fn round_trips_ticks() {
+        for ticks in [
+            0,
+            1,
+            9_999_999,
+            10_000_000,
+            133_499_232_001_234_567,
+            -1,
+            -9_999_999,
+            -10_000_000,
+            -10_000_001,
+            -133_499_232_001_234_567,
+            i64::MAX,
+            i64::MIN,
+        ] {
+            let timestamp = FileTime::from_ticks(ticks).to_utc().unwrap();
+            assert_eq!(
+                FileTime::from_utc(&timestamp),
+                Some(FileTime::from_ticks(ticks)),
+                "{ticks} ({timestamp})"
+            );
+        }
+    }
+
+    #[test]
+    fn rounds_down_below_a_tick() {
+        let epoch = FileTime::epoch();
+
+        assert_eq!(
+            FileTime::from_utc(&(epoch + TimeDelta::nanoseconds(150))),
+            Some(FileTime::from_ticks(1))
+        );
+        assert_eq!(
+            FileTime::from_utc(&(epoch - TimeDelta::nanoseconds(50))),
+            Some(FileTime::from_ticks(-1))
+        );
+        assert_eq!(
+            FileTime::from_utc(&(epoch - TimeDelta::nanoseconds(1_000_000_050))),
+            Some(FileTime::from_ticks(-10_000_001))
+        );
+    }
+
+    #[test]
+    fn rejects_timestamps_out_of_range() {
+        let after_limit =
+            FileTime::from_ticks(i64::MAX).to_utc().unwrap() + TimeDelta::microseconds(1);
+        assert_eq!(FileTime::from_utc(&after_limit), None);
+
+        let before_limit =
+            FileTime::from_ticks(i64::MIN).to_utc().unwrap() - TimeDelta::microseconds(1);
+        assert_eq!(FileTime::from_utc(&before_limit), None);
+    }
+}
@@ -20,6 +20,7 @@ macro_rules! require_some {
 #[macro_export]
 macro_rules! require_json_object {
     ($value:expr) => {{
+        #[allow(clippy::question_mark)]
         let Some(object) = $value.as_object() else {
             return None;
         };
@@ -31,6 +32,7 @@ macro_rules! require_json_object {
 #[macro_export]
 macro_rules! extract_value_from_json_object {
     ($json_object:expr, $key:expr => object) => {{
+        #[allow(clippy::question_mark)]
         let Some(string) = $json_object.get($key).and_then(|value| value.as_object()) else {
             return None;
         };
@@ -39,6 +41,7 @@ macro_rules! extract_value_from_json_object {
     }};
 
     ($json_object:expr, $key:expr => str) => {{
+        #[allow(clippy::question_mark)]
         let Some(string) = $json_object.get($key).and_then(|value| value.as_str()) else {
             return None;
         };
@@ -47,6 +50,7 @@ macro_rules! extract_value_from_json_object {
     }};
 
     ($json_object:expr, $key:expr => i64) => {{
+        #[allow(clippy::question_mark)]
         let Some(string) = $json_object.get($key).and_then(|value| value.as_i64()) else {
             return None;
         };
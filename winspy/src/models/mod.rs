@@ -1,7 +1,10 @@
 pub mod category;
+pub mod envelope;
 pub mod error;
+pub mod filetime;
 mod macros;
 pub mod persisted_event;
 pub mod producer;
+pub mod provenance;
 pub mod provider_group;
 pub mod tag_description;
@@ -1,32 +1,77 @@
 use std::fmt::Debug;
 
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use super::category::CategoryId;
+use super::envelope::CommonSchemaEnvelope;
+use super::filetime::FileTime;
 use super::producer::ProducerId;
+use super::provenance::EventProvenance;
 use super::provider_group::ProviderGroup;
 use super::tag_description::TagDescriptionId;
 
+#[allow(dead_code)]
 pub enum PersistedEventPayload {
     None,
     Invalid { raw_payload: String },
     Parsed { payload: serde_json::Value },
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoggingBinary {
     pub name: String,
     pub friendly_name: String,
 }
 
+/// Identifies the same event across different copies or versions of a database.
+///
+/// Uses the event name rather than `full_event_name_hash`: the event store does not
+/// keep the hash (and how it is computed is not documented), but both keep the name.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct EventKey {
+    device_id: String,
+    filetime: FileTime,
+    event_name: String,
+    payload_sha256: [u8; 32],
+}
+
+impl EventKey {
+    /// SHA-256 over all parts of the key, for storing it in fixed size.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update((self.device_id.len() as u64).to_le_bytes());
+        hasher.update(self.device_id.as_bytes());
+        hasher.update(self.filetime.ticks().to_le_bytes());
+        hasher.update((self.event_name.len() as u64).to_le_bytes());
+        hasher.update(self.event_name.as_bytes());
+        hasher.update(self.payload_sha256);
+
+        hasher.finalize().into()
+    }
+}
+
 /// Event captured by the database.
 #[allow(dead_code)]
 pub struct PersistedEvent {
     pub(super) device_id: String,
 
+    /// `timestamp` in the database, with the full 100-nanosecond precision.
     pub(super) timestamp: DateTime<Utc>,
 
+    /// `timestamp` in the database, as stored (a `FILETIME`).
+    pub(super) filetime: FileTime,
+
     pub(super) payload: PersistedEventPayload,
 
+    /// Common Schema envelope of the payload, decoded once when the event is read.
+    pub(super) envelope: CommonSchemaEnvelope,
+
+    /// SHA-256 of the raw `payload` column (of an empty string if there is none).
+    pub(super) payload_sha256: [u8; 32],
+
     /// `full_event_name` in the database
     pub(super) event_name: String,
 
@@ -45,6 +90,8 @@ pub struct PersistedEvent {
     pub(super) categories: Vec<CategoryId>,
 
     pub(super) tags: Vec<TagDescriptionId>,
+
+    pub(super) provenance: EventProvenance,
 }
 
 impl Debug for PersistedEvent {
@@ -65,7 +112,9 @@ impl PersistedEvent {
     pub fn new(
         device_id: String,
         timestamp: DateTime<Utc>,
+        filetime: FileTime,
         payload: PersistedEventPayload,
+        payload_sha256: [u8; 32],
         event_name: String,
         event_name_hash: i64,
         is_core: bool,
@@ -74,11 +123,24 @@ impl PersistedEvent {
         producer_id: ProducerId,
         categories: Vec<CategoryId>,
         tags: Vec<TagDescriptionId>,
+        provenance: EventProvenance,
     ) -> Self {
+        let envelope = match &payload {
+            PersistedEventPayload::None => CommonSchemaEnvelope::default(),
+            PersistedEventPayload::Invalid { .. } => CommonSchemaEnvelope {
+                errors: vec!["payload is not valid JSON".to_string()],
+                ..Default::default()
+            },
+            PersistedEventPayload::Parsed { payload } => CommonSchemaEnvelope::from_payload(payload),
+        };
+
         Self {
             device_id,
             timestamp,
+            filetime,
             payload,
+            envelope,
+            payload_sha256,
             event_name,
             event_name_hash,
             is_core,
@@ -87,6 +149,7 @@ impl PersistedEvent {
             producer_id,
             categories,
             tags,
+            provenance,
         }
     }
 
@@ -95,15 +158,27 @@ impl PersistedEvent {
         &self.device_id
     }
 
-    /// Timestamp of the event.
+    /// Timestamp of the event (with 100-nanosecond precision).
     pub fn timestamp(&self) -> &DateTime<Utc> {
         &self.timestamp
     }
 
+    /// Timestamp of the event, as the raw `FILETIME` tick value.
+    pub fn filetime(&self) -> FileTime {
+        self.filetime
+    }
+
     pub fn payload(&self) -> &PersistedEventPayload {
         &self.payload
     }
 
+    /// Typed Common Schema envelope of the payload (empty if there is no payload).
+    ///
+    /// Malformed fields are empty; see [`CommonSchemaEnvelope::errors`].
+    pub fn envelope(&self) -> &CommonSchemaEnvelope {
+        &self.envelope
+    }
+
     /// Name of the event
     pub fn event_name(&self) -> &str {
         &self.event_name
@@ -143,4 +218,60 @@ impl PersistedEvent {
     pub fn tag_description_ids(&self) -> &[TagDescriptionId] {
         &self.tags
     }
+
+    /// Where the event was read from (the database itself or recovered data).
+    pub fn provenance(&self) -> &EventProvenance {
+        &self.provenance
+    }
+
+    /// Key identifying this event across copies of the database:
+    /// device ID, timestamp, event name and payload digest.
+    pub fn key(&self) -> EventKey {
+        EventKey {
+            device_id: self.device_id.clone(),
+            filetime: self.filetime,
+            event_name: self.event_name.clone(),
+            payload_sha256: self.payload_sha256,
+        }
+    }
+}
+
+#[cfg(test)]
+impl PersistedEvent {
+    /// An event of the main database with the given payload and no other metadata.
+    pub fn for_tests(
+        device_id: &str,
+        event_name: &str,
+        timestamp: DateTime<Utc>,
+        payload: serde_json::Value,
+    ) -> Self {
+        let payload_sha256 = Sha256::digest(payload.to_string()).into();
+
+        Self::new(
+            device_id.to_string(),
+            timestamp,
+            FileTime::from_utc(&timestamp).unwrap(),
+            PersistedEventPayload::Parsed { payload },
+            payload_sha256,
+            event_name.to_string(),
+            0,
+            false,
+            ProviderGroup::new(0, String::new()),
+            LoggingBinary {
+                name: String::new(),
+                friendly_name: String::new(),
+            },
+            ProducerId::new(0),
+            Vec::new(),
+            Vec::new(),
+            EventProvenance::MainDatabase,
+        )
+    }
+
+    /// The event with the given tags and categories.
+    pub fn with_groups(mut self, tags: Vec<TagDescriptionId>, categories: Vec<CategoryId>) -> Self {
+        self.tags = tags;
+        self.categories = categories;
+        self
+    }
 }
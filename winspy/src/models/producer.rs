@@ -1,9 +1,7 @@
-use std::borrow::BorrowMut;
-
 use miette::{Context, IntoDiagnostic, Result};
 use sqlx::SqliteConnection;
 
-use crate::require_some;
+use crate::{reader::schema::SchemaVersion, require_some};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct ProducerId(i64);
@@ -13,6 +11,11 @@ impl ProducerId {
     pub fn new(id: i64) -> Self {
         Self(id)
     }
+
+    #[inline]
+    pub fn value(self) -> i64 {
+        self.0
+    }
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq)]
@@ -47,32 +50,23 @@ impl Producer {
         Self { id, name }
     }
 
-    pub async fn load_all_from_database(connection: &mut SqliteConnection) -> Result<Vec<Self>> {
-        let mut query_results: Result<Vec<ProducersTableRecord>> =
-            sqlx::query_as("SELECT producer_id, producer_id_name FROM producers")
-                .fetch_all(connection.borrow_mut())
+    pub async fn load_all_from_database(
+        connection: &mut SqliteConnection,
+        schema_version: SchemaVersion,
+    ) -> Result<Vec<Self>> {
+        let query_results: Vec<ProducersTableRecord> =
+            sqlx::query_as(&schema_version.producers_query())
+                .fetch_all(connection)
                 .await
                 .into_diagnostic()
-                .wrap_err("Failed to load all producers from database.");
-
-        if query_results.is_err() {
-            query_results = sqlx::query_as(
-                "SELECT producer_id, producer_id_text as producer_id_name FROM producers",
-            )
-            .fetch_all(connection)
-            .await
-            .into_diagnostic()
-            .wrap_err("Failed to load all producers from database.")
-        }
-
-        let query_results = query_results?;
+                .wrap_err("Failed to load all producers from database.")?;
 
         let mut parsed_producers = Vec::with_capacity(query_results.len());
 
         for query_result in query_results {
             let parsed_producer = Self {
                 id: ProducerId::new(query_result.producer_id),
-                name: require_some!(query_result.producer_id_name, "producer_id_text")?,
+                name: require_some!(query_result.producer_id_name, "producer_id_name")?,
             };
 
             parsed_producers.push(parsed_producer);
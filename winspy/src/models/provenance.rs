@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use crate::sqlite::wal::WalFrameState;
+
+/// Where a persisted event was read from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum EventProvenance {
+    /// A row of the `events_persisted` table, as returned by SQLite.
+    MainDatabase,
+
+    /// A record recovered from a frame of the write-ahead log (`-wal` file)
+    /// of a transcript or an event store.
+    WalFrame {
+        frame_number: u32,
+        page_number: u32,
+        frame_state: WalFrameState,
+    },
+
+    /// A deleted record carved from unused space of the database file.
+    Carved {
+        page_number: u32,
+
+        /// Offset of the record (or cell) within the page.
+        offset: usize,
+        region: CarvedRegion,
+    },
+
+    /// A record of an `EventStore.db` (events waiting to be uploaded).
+    EventStore {
+        record_id: Option<String>,
+        tenant_token: Option<String>,
+    },
+}
+
+/// Kind of unused space a record was carved from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CarvedRegion {
+    /// A page on the freelist.
+    FreePage,
+
+    /// A freeblock (space of a deleted cell) on a table leaf page that is in use.
+    Freeblock,
+
+    /// Space between the cell pointer array and the cell content area of a page in use.
+    UnallocatedSpace,
+}
+
+impl EventProvenance {
+    /// Whether the event was recovered from data SQLite itself does not return.
+    pub fn is_recovered(&self) -> bool {
+        matches!(self, Self::WalFrame { .. } | Self::Carved { .. })
+    }
+}
@@ -56,6 +56,36 @@ impl ProviderGroup {
         })
     }
 
+    pub async fn load_all_from_database(connection: &mut SqliteConnection) -> Result<Vec<Self>> {
+        let query_results = query!(
+            "SELECT group_id, group_guid \
+            FROM provider_groups"
+        )
+        .fetch_all(connection)
+        .await
+        .into_diagnostic()
+        .wrap_err("Failed to fetch all provider groups from database.")?;
+
+        let mut provider_groups = Vec::with_capacity(query_results.len());
+
+        for query_result in query_results {
+            provider_groups.push(Self {
+                id: query_result.group_id,
+                guid: require_some!(query_result.group_guid, "group_guid")?,
+            });
+        }
+
+        Ok(provider_groups)
+    }
+
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn guid(&self) -> &str {
+        &self.guid
+    }
+
     #[deprecated]
     pub fn try_from_sqlite_row(row: &SqliteRow) -> Result<Self> {
         let id: i64 = try_get_row!(row, "group_id")?;
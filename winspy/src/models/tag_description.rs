@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
 use miette::{Context, IntoDiagnostic, Result};
 use serde::{Deserialize, Serialize};
 use sqlx::{query, SqliteConnection};
 
 use crate::require_some;
 
+/// Locale of tag descriptions used if the preferred one is not available.
+pub const DEFAULT_TAG_LOCALE: &str = "en-US";
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub struct TagDescriptionId(i64);
 
@@ -13,30 +18,40 @@ impl TagDescriptionId {
         Self(id)
     }
 
-    pub async fn load_all_from_database_for_event(
+    #[inline]
+    pub fn value(self) -> i64 {
+        self.0
+    }
+
+    /// Loads the whole `event_tags` table, grouped by `full_event_name_hash`.
+    pub async fn load_all_event_mappings_from_database(
         connection: &mut SqliteConnection,
-        full_event_name_hash: i64,
-    ) -> Result<Vec<Self>> {
+    ) -> Result<HashMap<i64, Vec<Self>>> {
         let query_results = sqlx::query!(
-            "SELECT tag_id \
-            FROM event_tags et \
-            WHERE et.full_event_name_hash = $1",
-            full_event_name_hash
+            "SELECT full_event_name_hash, tag_id \
+            FROM event_tags"
         )
         .fetch_all(connection)
         .await
         .into_diagnostic()
-        .wrap_err("Failed to fetch all tag IDs for event from database.")?;
+        .wrap_err("Failed to fetch all event tags from database.")?;
 
-        let mut matching_tag_ids = Vec::with_capacity(query_results.len());
+        let mut tag_ids_by_event: HashMap<i64, Vec<Self>> = HashMap::new();
 
         for query_result in query_results {
+            let full_event_name_hash: i64 = require_some!(
+                query_result.full_event_name_hash,
+                "full_event_name_hash"
+            )?;
             let tag_id: i64 = require_some!(query_result.tag_id, "tag_id")?;
 
-            matching_tag_ids.push(Self(tag_id));
+            tag_ids_by_event
+                .entry(full_event_name_hash)
+                .or_default()
+                .push(Self(tag_id));
         }
 
-        Ok(matching_tag_ids)
+        Ok(tag_ids_by_event)
     }
 }
 
@@ -119,4 +134,45 @@ impl TagDescription {
     pub fn locale(&self) -> &str {
         &self.locale
     }
+
+    /// Identifies the description among those of all tags and locales.
+    /// Locale names are compared case-insensitively.
+    pub fn key(&self) -> TagDescriptionKey {
+        TagDescriptionKey::new(self.id, &self.locale)
+    }
+}
+
+/// A tag in a particular locale.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct TagDescriptionKey {
+    id: TagDescriptionId,
+    locale: String,
+}
+
+impl TagDescriptionKey {
+    pub fn new(id: TagDescriptionId, locale: &str) -> Self {
+        Self {
+            id,
+            locale: locale.to_ascii_lowercase(),
+        }
+    }
+
+    /// Locale name, in lowercase.
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+}
+
+/// Locales of the tag descriptions, and which one was used.
+#[derive(Debug, Clone, Serialize)]
+pub struct TagLocales {
+    /// Locale that was asked for.
+    pub preferred_locale: String,
+
+    /// All locales the transcripts describe tags in.
+    pub available_locales: Vec<String>,
+
+    /// Tags described in another locale than the preferred one,
+    /// as the preferred one was not available for them.
+    pub number_of_tags_in_fallback_locale: usize,
 }
@@ -0,0 +1,363 @@
+//! Windows version from `osver.txt`, cross-checked against the versions events report.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::models::persisted_event::PersistedEvent;
+
+/// Known Windows releases, by build number.
+const WINDOWS_RELEASES: [(u32, &str); 18] = [
+    (10240, "Windows 10 1507"),
+    (10586, "Windows 10 1511"),
+    (14393, "Windows 10 1607"),
+    (15063, "Windows 10 1703"),
+    (16299, "Windows 10 1709"),
+    (17134, "Windows 10 1803"),
+    (17763, "Windows 10 1809"),
+    (18362, "Windows 10 1903"),
+    (18363, "Windows 10 1909"),
+    (19041, "Windows 10 2004"),
+    (19042, "Windows 10 20H2"),
+    (19043, "Windows 10 21H1"),
+    (19044, "Windows 10 21H2"),
+    (19045, "Windows 10 22H2"),
+    (22000, "Windows 11 21H2"),
+    (22621, "Windows 11 22H2"),
+    (22631, "Windows 11 23H2"),
+    (26100, "Windows 11 24H2"),
+];
+
+/// A Windows version in the form `major.minor.build[.revision]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct OsVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+    pub revision: Option<u32>,
+}
+
+impl OsVersion {
+    /// Parses the leading numeric components of a version string,
+    /// ignoring anything after them (e.g. `10.0.19045.2006.amd64fre.vb_release.191206-1406`).
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut components = version
+            .trim()
+            .split('.')
+            .map_while(|component| component.parse::<u32>().ok());
+
+        Some(Self {
+            major: components.next()?,
+            minor: components.next()?,
+            build: components.next()?,
+            revision: components.next(),
+        })
+    }
+
+    /// Name of the Windows release with this build number, if it is a known one.
+    pub fn release_name(&self) -> Option<&'static str> {
+        if self.major != 10 || self.minor != 0 {
+            return None;
+        }
+
+        WINDOWS_RELEASES
+            .iter()
+            .find(|(build, _)| *build == self.build)
+            .map(|(_, name)| *name)
+    }
+
+    /// Whether both versions are the same build (revisions are not compared,
+    /// as `osver.txt` does not always contain one).
+    pub fn is_same_build(&self, other: &Self) -> bool {
+        (self.major, self.minor, self.build) == (other.major, other.minor, other.build)
+    }
+}
+
+impl fmt::Display for OsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)?;
+        if let Some(revision) = self.revision {
+            write!(f, ".{revision}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Content of an `osver.txt` file.
+#[derive(Debug, Clone, Serialize)]
+pub struct OsVersionFile {
+    pub path: PathBuf,
+    pub raw_version: String,
+    pub version: OsVersion,
+    pub release_name: Option<&'static str>,
+}
+
+impl OsVersionFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        // The file has been seen both with and without a trailing newline or NUL bytes.
+        let raw_version = fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err("Failed to read osver.txt.")?
+            .trim_matches(|character: char| character.is_whitespace() || character == '\0')
+            .to_string();
+
+        let version = OsVersion::parse(&raw_version)
+            .ok_or_else(|| miette!("Unknown format of osver.txt: {:?}.", raw_version))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            release_name: version.release_name(),
+            raw_version,
+            version,
+        })
+    }
+}
+
+/// How many events reported a particular `ext.os.ver`, and when.
+#[derive(Debug, Clone, Serialize)]
+pub struct ObservedOsVersion {
+    pub raw_version: String,
+    pub version: Option<OsVersion>,
+    pub number_of_events: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Collects the `ext.os.ver` field of event payloads.
+#[derive(Debug, Default)]
+pub struct OsVersionObserver {
+    versions: BTreeMap<String, ObservedOsVersion>,
+}
+
+impl OsVersionObserver {
+    pub fn observe(&mut self, event: &PersistedEvent) {
+        let Some(raw_version) = event.envelope().ext.os.ver.as_deref() else {
+            return;
+        };
+
+        let timestamp = *event.timestamp();
+
+        match self.versions.get_mut(raw_version) {
+            Some(observed) => {
+                observed.number_of_events += 1;
+                observed.first_seen = observed.first_seen.min(timestamp);
+                observed.last_seen = observed.last_seen.max(timestamp);
+            }
+            None => {
+                self.versions.insert(
+                    raw_version.to_string(),
+                    ObservedOsVersion {
+                        raw_version: raw_version.to_string(),
+                        version: OsVersion::parse(raw_version),
+                        number_of_events: 1,
+                        first_seen: timestamp,
+                        last_seen: timestamp,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn into_observed_versions(self) -> Vec<ObservedOsVersion> {
+        self.versions.into_values().collect()
+    }
+}
+
+/// Why events may report a different version than `osver.txt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchCause {
+    /// All events with the other version precede the ones with the version of `osver.txt`,
+    /// as is expected after Windows was upgraded.
+    LikelyUpgrade,
+
+    /// The other version cannot be explained by an upgrade:
+    /// the evidence may have been copied from another machine.
+    Unexplained,
+}
+
+/// An inconsistency between `osver.txt` and the rest of the evidence.
+///
+/// The schema of the transcripts is deliberately not compared: it is classified by its
+/// columns, which is what the reader relies on.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OsVersionMismatch {
+    /// Events report a different build than `osver.txt`.
+    EventVersion {
+        raw_version: String,
+        number_of_events: u64,
+        first_seen: DateTime<Utc>,
+        last_seen: DateTime<Utc>,
+        likely_cause: MismatchCause,
+    },
+}
+
+/// The OS version section of the output.
+#[derive(Debug, Clone, Serialize)]
+pub struct OsVersionReport {
+    pub os_version_file: Option<OsVersionFile>,
+    pub observed_versions: Vec<ObservedOsVersion>,
+    pub mismatches: Vec<OsVersionMismatch>,
+}
+
+impl OsVersionReport {
+    /// Compares `osver.txt` (if any) against the versions reported by events.
+    pub fn new(
+        os_version_file: Option<OsVersionFile>,
+        observed_versions: Vec<ObservedOsVersion>,
+    ) -> Self {
+        let mut mismatches = Vec::new();
+
+        if let Some(os_version_file) = &os_version_file {
+            let expected_version = os_version_file.version;
+
+            // Events of the current version mark when the system was running it.
+            let current_version_first_seen = observed_versions
+                .iter()
+                .filter(|observed| {
+                    observed
+                        .version
+                        .is_some_and(|version| version.is_same_build(&expected_version))
+                })
+                .map(|observed| observed.first_seen)
+                .min();
+
+            for observed in &observed_versions {
+                let Some(version) = observed.version else {
+                    continue;
+                };
+                if version.is_same_build(&expected_version) {
+                    continue;
+                }
+
+                let is_upgrade = version < expected_version
+                    && current_version_first_seen
+                        .is_some_and(|first_seen| observed.last_seen <= first_seen);
+
+                mismatches.push(OsVersionMismatch::EventVersion {
+                    raw_version: observed.raw_version.clone(),
+                    number_of_events: observed.number_of_events,
+                    first_seen: observed.first_seen,
+                    last_seen: observed.last_seen,
+                    likely_cause: if is_upgrade {
+                        MismatchCause::LikelyUpgrade
+                    } else {
+                        MismatchCause::Unexplained
+                    },
+                });
+            }
+        }
+
+        Self {
+            os_version_file,
+            observed_versions,
+            mismatches,
+        }
+    }
+
+    pub fn log_summary(&self) {
+        if let Some(os_version_file) = &self.os_version_file {
+            info!(
+                "osver.txt reports version {} ({}).",
+                os_version_file.version,
+                os_version_file.release_name.unwrap_or("unknown release")
+            );
+        }
+
+        for mismatch in &self.mismatches {
+            let OsVersionMismatch::EventVersion {
+                raw_version,
+                number_of_events,
+                likely_cause,
+                ..
+            } = mismatch;
+
+            warn!(
+                "{} events report OS version {} which differs from osver.txt ({:?}).",
+                number_of_events, raw_version, likely_cause
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn observed(raw_version: &str, first_day: u32, last_day: u32) -> ObservedOsVersion {
+        ObservedOsVersion {
+            raw_version: raw_version.to_string(),
+            version: OsVersion::parse(raw_version),
+            number_of_events: 1,
+            first_seen: Utc.with_ymd_and_hms(2024, 1, first_day, 0, 0, 0).unwrap(),
+            last_seen: Utc.with_ymd_and_hms(2024, 1, last_day, 0, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_versions() {
+        let version = OsVersion::parse("10.0.19045.2006.amd64fre.vb_release.191206-1406").unwrap();
+
+        assert_eq!(version.build, 19045);
+        assert_eq!(version.revision, Some(2006));
+        assert_eq!(version.release_name(), Some("Windows 10 22H2"));
+        assert_eq!(
+            OsVersion::parse("10.0.22631").unwrap().revision,
+            None
+        );
+        assert_eq!(OsVersion::parse("10.0"), None);
+        assert_eq!(OsVersion::parse("garbage"), None);
+    }
+
+    #[test]
+    fn classifies_mismatches() {
+        let version = OsVersion::parse("10.0.22631.1").unwrap();
+        let os_version_file = OsVersionFile {
+            path: PathBuf::from("osver.txt"),
+            raw_version: "10.0.22631.1".to_string(),
+            version,
+            release_name: version.release_name(),
+        };
+
+        let report = OsVersionReport::new(
+            Some(os_version_file),
+            vec![
+                observed("10.0.19045.1", 1, 5),
+                observed("10.0.22631.1", 6, 9),
+                observed("10.0.26100.1", 7, 8),
+            ],
+        );
+
+        let causes = report
+            .mismatches
+            .iter()
+            .map(|mismatch| {
+                let OsVersionMismatch::EventVersion {
+                    raw_version,
+                    likely_cause,
+                    ..
+                } = mismatch;
+                (raw_version.as_str(), *likely_cause)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            causes,
+            vec![
+                ("10.0.19045.1", MismatchCause::LikelyUpgrade),
+                ("10.0.26100.1", MismatchCause::Unexplained),
+            ]
+        );
+    }
+}
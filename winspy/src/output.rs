@@ -0,0 +1,172 @@
+//! Structure of the output JSON file.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    archive::ArchiveSourceMetadata,
+    clock_skew::ClockSkewReport,
+    detectors::{DetectorMetadata, ProcessedEvent},
+    diagnosis::{ArtifactKind, DiagnosisFolder},
+    downloaded_settings::CollectionCoverage,
+    evidence::EvidenceSnapshot,
+    evidence_summary::EvidenceSummary,
+    image::{DiskImageMetadata, ImageSourceMetadata},
+    models::{envelope::MalformedEnvelope, error::EventReaderError, tag_description::TagLocales},
+    os_version::OsVersionReport,
+    reader::schema::SchemaVersion,
+    user::UserReport,
+};
+
+/// Everything that is written into the output JSON file.
+#[derive(Debug, Serialize)]
+pub struct AnalysisOutput {
+    pub metadata: OutputMetadata,
+
+    /// Whether the events the detectors look for could have been collected,
+    /// if any `DownloadedSettings` were given.
+    pub collection_coverage: Option<CollectionCoverage>,
+
+    /// Version of Windows from `osver.txt`, compared with what the events report.
+    pub os_version: OsVersionReport,
+
+    /// Events whose Common Schema envelope could not be fully decoded,
+    /// grouped by event name and reason.
+    pub malformed_envelopes: Vec<MalformedEnvelope>,
+
+    /// Users the events were attributed to, with what was detected in their events.
+    pub users: UserReport,
+
+    /// How payload times and database timestamps of the events relate.
+    pub clock_skew: ClockSkewReport,
+
+    /// Events per tag and per category, showing which kinds of data the events hold.
+    pub evidence_summary: EvidenceSummary,
+    pub events: Vec<ProcessedEvent>,
+}
+
+/// Information about the analysis itself, for chain-of-custody documentation.
+#[derive(Debug, Serialize)]
+pub struct OutputMetadata {
+    pub tool_name: &'static str,
+    pub tool_version: &'static str,
+    pub analysis_started_at: DateTime<Utc>,
+    pub analysis_finished_at: DateTime<Utc>,
+
+    /// Every database that was read, in the order they were processed.
+    pub databases: Vec<DatabaseMetadata>,
+
+    /// Every disk image that was read, with the hashes of EWF images.
+    pub images: Vec<DiskImageMetadata>,
+
+    /// Artifacts of the Diagnosis folder, if one was given.
+    pub diagnosis_folder: Option<DiagnosisFolder>,
+
+    /// Locales the tags are described in.
+    pub tag_locales: TagLocales,
+
+    /// Detectors that were run, with their versions.
+    pub detectors: Vec<DetectorMetadata>,
+    pub evidence: EvidenceMetadata,
+}
+
+/// A single input database.
+#[derive(Debug, Serialize)]
+pub struct DatabaseMetadata {
+    pub path: PathBuf,
+
+    /// Either [`ArtifactKind::EventTranscript`] or [`ArtifactKind::EventStore`].
+    pub kind: ArtifactKind,
+
+    /// Schema version of a transcript, as detected by the reader.
+    pub schema: Option<SchemaMetadata>,
+
+    /// Where in a disk image the database was read from, if it was.
+    pub image: Option<ImageSourceMetadata>,
+
+    /// Which member of a triage archive the database was extracted from, if it was.
+    pub archive: Option<ArchiveSourceMetadata>,
+
+    /// Records of an event store that were skipped because their payload is not JSON.
+    pub number_of_records_without_json_payload: Option<u64>,
+}
+
+/// Schema version of a transcript, with the Windows builds that use it.
+#[derive(Debug, Serialize)]
+pub struct SchemaMetadata {
+    pub version: SchemaVersion,
+    pub windows_builds: &'static str,
+
+    /// Column of the `producers` table the producer names were read from.
+    pub producer_name_column: &'static str,
+}
+
+impl From<SchemaVersion> for SchemaMetadata {
+    fn from(version: SchemaVersion) -> Self {
+        Self {
+            version,
+            windows_builds: version.windows_builds(),
+            producer_name_column: version.producer_name_column(),
+        }
+    }
+}
+
+/// Hashes of the input files, taken before and after they were processed.
+#[derive(Debug, Serialize)]
+pub struct EvidenceMetadata {
+    pub before_processing: EvidenceSnapshot,
+    pub after_processing: EvidenceSnapshot,
+}
+
+impl OutputMetadata {
+    pub fn new(
+        analysis_started_at: DateTime<Utc>,
+        databases: Vec<DatabaseMetadata>,
+        images: Vec<DiskImageMetadata>,
+        diagnosis_folder: Option<DiagnosisFolder>,
+        tag_locales: TagLocales,
+        detectors: Vec<DetectorMetadata>,
+        evidence: EvidenceMetadata,
+    ) -> Self {
+        Self {
+            tool_name: env!("CARGO_PKG_NAME"),
+            tool_version: env!("CARGO_PKG_VERSION"),
+            analysis_started_at,
+            analysis_finished_at: Utc::now(),
+            databases,
+            images,
+            diagnosis_folder,
+            tag_locales,
+            detectors,
+            evidence,
+        }
+    }
+}
+
+/// Rows rejected in lenient mode, written into a separate JSON file next to the output.
+#[derive(Debug, Serialize)]
+pub struct RejectedRowsReport {
+    pub number_of_rejected_rows: usize,
+
+    /// Number of rejected rows per rejection reason.
+    pub rejection_reasons: BTreeMap<String, usize>,
+    pub rejected_rows: Vec<EventReaderError>,
+}
+
+impl RejectedRowsReport {
+    pub fn new(rejected_rows: Vec<EventReaderError>) -> Self {
+        let mut rejection_reasons = BTreeMap::new();
+        for rejected_row in &rejected_rows {
+            let EventReaderError::RecordParsingError { reason, .. } = rejected_row;
+            *rejection_reasons.entry(reason.clone()).or_default() += 1;
+        }
+
+        Self {
+            number_of_rejected_rows: rejected_rows.len(),
+            rejection_reasons,
+            rejected_rows,
+        }
+    }
+}
@@ -0,0 +1,698 @@
+//! Reader of `EventStore.db`, the queue of telemetry events waiting to be uploaded.
+//!
+//! Events in the store never went through the transcript, so they lack most of
+//! the metadata `events_persisted` has (provider group, producer, logging binary, ...).
+//! Only the fields that can be recovered from the record and its payload are filled in.
+//!
+//! The store is usually in WAL mode, so recently queued events may only be in
+//! `EventStore.db-wal`. As with transcripts, they are recovered from the log directly.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use chrono::DateTime;
+use futures::{stream, Stream, StreamExt};
+use miette::{miette, Context, IntoDiagnostic, Result};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    Row, SqlitePool,
+};
+use tracing::{info, warn};
+
+use super::{
+    filter::{EventFilter, ResolvedEventFilter},
+    recovery::{RecoveredEvents, WalRecords},
+};
+use crate::{
+    models::{
+        filetime::FileTime,
+        persisted_event::{LoggingBinary, PersistedEvent, PersistedEventPayload},
+        producer::ProducerId,
+        provenance::EventProvenance,
+        provider_group::ProviderGroup,
+    },
+    sqlite::{database::DatabaseFile, record::SqliteValue, wal::WriteAheadLog},
+};
+
+/// Table of the store that holds the queued events.
+const EVENTS_TABLE_NAME: &str = "events";
+
+/// Columns of the events table.
+///
+/// `timestamp` and `payload` are required, the others are used when present.
+#[derive(Debug, Clone)]
+struct EventStoreLayout {
+    /// All columns, in the order they are stored in records.
+    column_names: Vec<String>,
+    has_record_id: bool,
+    has_tenant_token: bool,
+}
+
+impl EventStoreLayout {
+    /// Reads the columns of the events table and checks that events can be read from it.
+    async fn load_from_database(pool: &SqlitePool) -> Result<Self> {
+        let column_names: Vec<(String,)> =
+            sqlx::query_as("SELECT name FROM pragma_table_info(?1) ORDER BY cid")
+                .bind(EVENTS_TABLE_NAME)
+                .fetch_all(pool)
+                .await
+                .into_diagnostic()
+                .wrap_err("Failed to load the columns of the event store.")?;
+        let column_names = column_names
+            .into_iter()
+            .map(|(name,)| name)
+            .collect::<Vec<_>>();
+
+        if column_names.is_empty() {
+            return Err(miette!(
+                "Unknown EventStore.db schema: there is no {EVENTS_TABLE_NAME} table."
+            ));
+        }
+
+        let has_column = |column_name: &str| column_names.iter().any(|name| name == column_name);
+
+        let missing_columns = ["timestamp", "payload"]
+            .into_iter()
+            .filter(|column_name| !has_column(column_name))
+            .map(|column_name| format!("{EVENTS_TABLE_NAME}.{column_name}"))
+            .collect::<Vec<_>>();
+
+        if !missing_columns.is_empty() {
+            return Err(miette!(
+                "Unknown EventStore.db schema, missing columns: {} (the {EVENTS_TABLE_NAME} table has: {}).",
+                missing_columns.join(", "),
+                column_names.join(", ")
+            ));
+        }
+
+        Ok(Self {
+            has_record_id: has_column("record_id"),
+            has_tenant_token: has_column("tenant_token"),
+            column_names,
+        })
+    }
+
+    fn events_query(&self) -> String {
+        format!(
+            "SELECT {}, {}, timestamp, payload FROM {EVENTS_TABLE_NAME} ORDER BY timestamp",
+            if self.has_record_id {
+                "CAST(record_id AS TEXT) as record_id"
+            } else {
+                "NULL as record_id"
+            },
+            if self.has_tenant_token {
+                "CAST(tenant_token AS TEXT) as tenant_token"
+            } else {
+                "NULL as tenant_token"
+            },
+        )
+    }
+
+    fn value<'a>(&self, values: &'a [SqliteValue], column_name: &str) -> Option<&'a SqliteValue> {
+        let index = self
+            .column_names
+            .iter()
+            .position(|name| name == column_name)?;
+        values.get(index)
+    }
+
+    /// Checks whether a raw record has the shape of an events table row.
+    fn matches(&self, values: &[SqliteValue]) -> bool {
+        !values.is_empty()
+            && values.len() <= self.column_names.len()
+            && self
+                .value(values, "timestamp")
+                .is_some_and(|value| value.is_integer() || value.is_null())
+            && self
+                .value(values, "payload")
+                .is_some_and(|value| matches!(value, SqliteValue::Text(_) | SqliteValue::Blob(_)))
+    }
+
+    /// Extracts the columns of a raw record that the events query selects.
+    ///
+    /// A `NULL` record ID is taken from the row ID (it is an alias of it
+    /// if the column is declared as `INTEGER PRIMARY KEY`).
+    fn to_event_store_record(&self, rowid: i64, values: &[SqliteValue]) -> EventStoreRecord {
+        let text = |column_name: &str| match self.value(values, column_name)? {
+            SqliteValue::Integer(value) => Some(value.to_string()),
+            SqliteValue::Text(value) => Some(value.clone()),
+            _ => None,
+        };
+
+        EventStoreRecord {
+            record_id: self
+                .has_record_id
+                .then(|| text("record_id").unwrap_or_else(|| rowid.to_string())),
+            tenant_token: text("tenant_token"),
+            timestamp_in_milliseconds: self
+                .value(values, "timestamp")
+                .and_then(SqliteValue::as_i64),
+            raw_payload: match self.value(values, "payload") {
+                Some(SqliteValue::Text(payload)) => payload.clone().into_bytes(),
+                Some(SqliteValue::Blob(payload)) => payload.clone(),
+                _ => Vec::new(),
+            },
+        }
+    }
+}
+
+/// The columns of an events table row that events are decoded from.
+struct EventStoreRecord {
+    record_id: Option<String>,
+    tenant_token: Option<String>,
+    timestamp_in_milliseconds: Option<i64>,
+    raw_payload: Vec<u8>,
+}
+
+impl EventStoreRecord {
+    fn from_row(row: &SqliteRow) -> Result<Self> {
+        Ok(Self {
+            record_id: row.try_get("record_id").into_diagnostic()?,
+            tenant_token: row.try_get("tenant_token").into_diagnostic()?,
+            timestamp_in_milliseconds: row.try_get("timestamp").into_diagnostic()?,
+            // The payload is stored as a BLOB or as TEXT, depending on the writer.
+            raw_payload: row
+                .try_get::<Option<Vec<u8>>, _>("payload")
+                .or_else(|_| {
+                    row.try_get::<Option<String>, _>("payload")
+                        .map(|payload| payload.map(String::into_bytes))
+                })
+                .into_diagnostic()?
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Provenance of an event read from the store as it is.
+    fn provenance(&self) -> EventProvenance {
+        EventProvenance::EventStore {
+            record_id: self.record_id.clone(),
+            tenant_token: self.tenant_token.clone(),
+        }
+    }
+}
+
+/// State of the stream returned by [`EventStoreReader::stream_events`].
+struct EventStoreStreamState<'a, S> {
+    /// `None` once all rows of the database have been streamed.
+    rows: Option<S>,
+
+    /// Events recovered from the write-ahead log (if there are any),
+    /// emitted once all rows have been read.
+    recovered_events: Option<RecoveredEvents<'a>>,
+    started_at: Instant,
+    number_of_events: u64,
+    number_of_recovered_events: u64,
+}
+
+pub struct EventStoreReader {
+    database_path: PathBuf,
+    pool: SqlitePool,
+    layout: EventStoreLayout,
+    events_query: String,
+    filter: ResolvedEventFilter,
+
+    /// Records skipped because their payload is not JSON (or has no event name).
+    number_of_records_without_json_payload: AtomicU64,
+}
+
+impl EventStoreReader {
+    pub async fn new(database_path: &Path, filter: &EventFilter) -> Result<Self> {
+        if !database_path.is_file() {
+            return Err(miette!(
+                "Provided file does not exist or is not a file."
+            ));
+        }
+
+        // Opened the same way as the transcript: read-only and without touching
+        // the `-wal` and `-shm` files next to it. Events in the write-ahead log
+        // are recovered separately (see [`Self::recovered_events`]).
+        let connect_options = SqliteConnectOptions::new()
+            .filename(database_path)
+            .read_only(true)
+            .immutable(true);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(connect_options)
+            .await
+            .into_diagnostic()?;
+
+        let layout = EventStoreLayout::load_from_database(&pool).await?;
+
+        Ok(Self {
+            database_path: database_path.to_path_buf(),
+            pool,
+            events_query: layout.events_query(),
+            layout,
+            // The store has no producers table, so a producer filter matches none of its events.
+            filter: filter.resolve(&[])?,
+            number_of_records_without_json_payload: AtomicU64::new(0),
+        })
+    }
+
+    /// Path of the database this reader reads from.
+    pub fn database_path(&self) -> &Path {
+        &self.database_path
+    }
+
+    /// Closes all connections to the database.
+    pub async fn close(self) {
+        self.pool.close().await;
+    }
+
+    /// Number of records read so far (including recovered ones) that were skipped
+    /// because their payload is not JSON.
+    pub fn number_of_records_without_json_payload(&self) -> u64 {
+        self.number_of_records_without_json_payload
+            .load(Ordering::Relaxed)
+    }
+
+    /// Path of the write-ahead log that belongs to the store.
+    fn wal_path(&self) -> PathBuf {
+        let mut wal_path = OsString::from(self.database_path.as_os_str());
+        wal_path.push("-wal");
+        PathBuf::from(wal_path)
+    }
+
+    /// Prepares to recover events from the write-ahead log, if there is one.
+    /// Records are only recovered and decoded as the returned events are taken.
+    ///
+    /// Failures are logged instead of returned: the store itself can still be processed.
+    async fn recovered_events(&self) -> Option<RecoveredEvents<'_>> {
+        let wal_path = self.wal_path();
+        if !wal_path.is_file() {
+            return None;
+        }
+
+        let records = DatabaseFile::open(&self.database_path).and_then(|database| {
+            let wal = WriteAheadLog::read(&wal_path)?;
+            WalRecords::new(
+                Arc::new(database),
+                wal,
+                |values: &[SqliteValue]| self.layout.matches(values),
+            )
+        });
+
+        let records = match records {
+            Ok(records) => records,
+            Err(error) => {
+                warn!(
+                    "Failed to recover events from write-ahead log {}: {:?}",
+                    wal_path.display(),
+                    error
+                );
+                return None;
+            }
+        };
+
+        let recovered_events = records.filter_map(|record| {
+            let store_record = self
+                .layout
+                .to_event_store_record(record.rowid, &record.values);
+
+            match self.parse_event_record(store_record, record.provenance.clone()) {
+                Ok(Some(event)) => self.filter.matches(&event).then_some(event),
+                Ok(None) => {
+                    self.number_of_records_without_json_payload
+                        .fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+                Err(error) => {
+                    warn!(
+                        "Skipping unreadable record recovered from {:?}: {}",
+                        record.provenance, error
+                    );
+                    None
+                }
+            }
+        });
+
+        RecoveredEvents::new(recovered_events)
+            .await
+            .map_err(|error| warn!("Failed to prepare event recovery: {:?}", error))
+            .ok()
+            .flatten()
+    }
+
+    /// Streams all queued events that match the filter, followed by the events recovered
+    /// from the write-ahead log that the store itself does not contain.
+    ///
+    /// Records whose payload is not JSON (e.g. binary-serialized ones) carry no event name
+    /// and are skipped; their number is logged and reported in the output metadata.
+    pub async fn stream_events(&self) -> impl Stream<Item = Result<PersistedEvent>> + '_ {
+        let state = EventStoreStreamState {
+            rows: Some(sqlx::query(&self.events_query).fetch(&self.pool)),
+            recovered_events: self.recovered_events().await,
+            started_at: Instant::now(),
+            number_of_events: 0,
+            number_of_recovered_events: 0,
+        };
+
+        stream::unfold(state, move |mut state| async move {
+            if let Some(rows) = state.rows.as_mut() {
+                while let Some(row) = rows.next().await {
+                    let event = match row.into_diagnostic().and_then(|row| {
+                        let record = EventStoreRecord::from_row(&row)?;
+                        let provenance = record.provenance();
+                        self.parse_event_record(record, provenance)
+                    }) {
+                        Ok(Some(event)) => event,
+                        Ok(None) => {
+                            self.number_of_records_without_json_payload
+                                .fetch_add(1, Ordering::Relaxed);
+                            continue;
+                        }
+                        Err(error) => return Some((Err(error), state)),
+                    };
+
+                    if !self.filter.matches(&event) {
+                        continue;
+                    }
+
+                    if let Some(recovered_events) = state.recovered_events.as_mut() {
+                        if let Err(error) = recovered_events.observe_main_event(&event).await {
+                            return Some((Err(error), state));
+                        }
+                    }
+                    state.number_of_events += 1;
+                    return Some((Ok(event), state));
+                }
+
+                state.rows = None;
+            }
+
+            let recovered_event = match state.recovered_events.as_mut() {
+                Some(recovered_events) => recovered_events.next().await,
+                None => Ok(None),
+            };
+
+            match recovered_event {
+                Ok(Some(event)) => {
+                    state.number_of_recovered_events += 1;
+                    return Some((Ok(event), state));
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    state.recovered_events = None;
+                    return Some((Err(error), state));
+                }
+            }
+
+            if let Some(recovered_events) = state.recovered_events.take() {
+                recovered_events.close().await;
+            }
+
+            info!(
+                "Loaded {} events (and {} recovered ones) from event store {} in {:.2?}.",
+                state.number_of_events,
+                state.number_of_recovered_events,
+                self.database_path.display(),
+                state.started_at.elapsed()
+            );
+
+            let number_of_records_without_json_payload =
+                self.number_of_records_without_json_payload();
+            if number_of_records_without_json_payload > 0 {
+                warn!(
+                    "Skipped {} records of event store {} without a JSON payload.",
+                    number_of_records_without_json_payload,
+                    self.database_path.display()
+                );
+            }
+
+            None
+        })
+    }
+
+    /// Decodes a record of the events table, or returns `None` if its payload is not JSON.
+    fn parse_event_record(
+        &self,
+        record: EventStoreRecord,
+        provenance: EventProvenance,
+    ) -> Result<Option<PersistedEvent>> {
+        let EventStoreRecord {
+            timestamp_in_milliseconds,
+            raw_payload,
+            ..
+        } = record;
+
+        let Ok(payload) = serde_json::from_slice::<serde_json::Value>(&raw_payload) else {
+            return Ok(None);
+        };
+
+        let Some(event_name) = payload.get("name").and_then(|name| name.as_str()) else {
+            return Ok(None);
+        };
+        let event_name = event_name.to_string();
+
+        // The store keeps milliseconds since the Unix epoch. If that is missing,
+        // fall back to the `time` field of the payload.
+        let event_timestamp = timestamp_in_milliseconds
+            .and_then(DateTime::from_timestamp_millis)
+            .or_else(|| {
+                payload
+                    .get("time")
+                    .and_then(|time| time.as_str())
+                    .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                    .map(|time| time.to_utc())
+            })
+            .ok_or_else(|| miette!("Event store record has no valid timestamp."))?;
+
+        let event_filetime = FileTime::from_utc(&event_timestamp)
+            .ok_or_else(|| miette!("Failed to construct FILETIME of event store record."))?;
+
+        let device_id = payload
+            .pointer("/ext/device/localId")
+            .and_then(|local_id| local_id.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        let payload_sha256: [u8; 32] = Sha256::digest(&raw_payload).into();
+
+        Ok(Some(PersistedEvent::new(
+            device_id,
+            event_timestamp,
+            event_filetime,
+            PersistedEventPayload::Parsed { payload },
+            payload_sha256,
+            event_name,
+            // Neither the name hash nor any of the following is stored in the event store.
+            0,
+            false,
+            ProviderGroup::new(0, String::new()),
+            LoggingBinary {
+                name: String::new(),
+                friendly_name: String::new(),
+            },
+            ProducerId::new(0),
+            Vec::new(),
+            Vec::new(),
+            provenance,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use sqlx::{ConnectOptions, Connection};
+
+    use super::*;
+
+    const EVENTS_TABLE: &str = "CREATE TABLE events (record_id TEXT, tenant_token TEXT NOT NULL, \
+        latency INTEGER, persistence INTEGER, timestamp INTEGER, retry_count INTEGER, \
+        reserved_until INTEGER, payload BLOB)";
+
+    fn payload(event_name: &str, sequence: u64) -> Vec<u8> {
+        format!(r#"{{"name":"{event_name}","ext":{{"device":{{"localId":"d"}}}},"data":{{"n":{sequence}}}}}"#)
+            .into_bytes()
+    }
+
+    async fn insert_event(
+        connection: &mut sqlx::SqliteConnection,
+        record_id: &str,
+        timestamp_in_milliseconds: i64,
+        payload: &[u8],
+    ) {
+        sqlx::query(
+            "INSERT INTO events (record_id, tenant_token, timestamp, payload) VALUES (?, 't', ?, ?)",
+        )
+        .bind(record_id)
+        .bind(timestamp_in_milliseconds)
+        .bind(payload)
+        .execute(connection)
+        .await
+        .unwrap();
+    }
+
+    async fn read_all(database_path: &Path) -> (Vec<PersistedEvent>, u64) {
+        let reader = EventStoreReader::new(database_path, &EventFilter::default())
+            .await
+            .unwrap();
+        let events = reader
+            .stream_events()
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let number_of_records_without_json_payload = reader.number_of_records_without_json_payload();
+        reader.close().await;
+
+        (events, number_of_records_without_json_payload)
+    }
+
+    async fn create_database(database_path: &Path, schema: &str) -> sqlx::SqliteConnection {
+        let mut connection = SqliteConnectOptions::new()
+            .filename(database_path)
+            .create_if_missing(true)
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query(schema).execute(&mut connection).await.unwrap();
+        connection
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_schemas() {
+        let directory = tempfile::tempdir().unwrap();
+
+        let without_table = directory.path().join("without_table.db");
+        create_database(&without_table, "CREATE TABLE other (a INTEGER)")
+            .await
+            .close()
+            .await
+            .unwrap();
+        let error = EventStoreReader::new(&without_table, &EventFilter::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("there is no events table"));
+
+        let without_payload = directory.path().join("without_payload.db");
+        create_database(
+            &without_payload,
+            "CREATE TABLE events (record_id TEXT, timestamp INTEGER)",
+        )
+        .await
+        .close()
+        .await
+        .unwrap();
+        let error = EventStoreReader::new(&without_payload, &EventFilter::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .contains("missing columns: events.payload"));
+        assert!(error.to_string().contains("record_id, timestamp"));
+    }
+
+    #[tokio::test]
+    async fn counts_records_without_json_payload() {
+        let directory = tempfile::tempdir().unwrap();
+        let database_path = directory.path().join("EventStore.db");
+
+        let mut connection = create_database(&database_path, EVENTS_TABLE).await;
+        insert_event(
+            &mut connection,
+            "r1",
+            1_705_526_400_000,
+            &payload("A", 1),
+        )
+        .await;
+        insert_event(
+            &mut connection,
+            "r2",
+            1_705_526_401_000,
+            &[1, 2, 3],
+        )
+        .await;
+        insert_event(
+            &mut connection,
+            "r3",
+            1_705_526_402_000,
+            br#"{"time":"x"}"#,
+        )
+        .await;
+        connection.close().await.unwrap();
+
+        let (events, number_of_records_without_json_payload) = read_all(&database_path).await;
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_name(), "A");
+        assert!(matches!(
+            events[0].provenance(),
+            EventProvenance::EventStore { record_id: Some(record_id), .. } if record_id == "r1"
+        ));
+        assert_eq!(number_of_records_without_json_payload, 2);
+    }
+
+    #[tokio::test]
+    async fn recovers_events_from_write_ahead_log() {
+        let directory = tempfile::tempdir().unwrap();
+        let live_path = directory.path().join("live.db");
+        let copy_path = directory.path().join("EventStore.db");
+
+        let mut connection = create_database(&live_path, EVENTS_TABLE).await;
+        insert_event(
+            &mut connection,
+            "r1",
+            1_705_526_400_000,
+            &payload("A", 1),
+        )
+        .await;
+
+        // Later events only go to the write-ahead log, which is copied while it is still open.
+        sqlx::query("PRAGMA wal_autocheckpoint = 0")
+            .execute(&mut connection)
+            .await
+            .unwrap();
+        sqlx::query("PRAGMA journal_mode = WAL")
+            .execute(&mut connection)
+            .await
+            .unwrap();
+        insert_event(
+            &mut connection,
+            "r2",
+            1_705_526_401_000,
+            &payload("B", 2),
+        )
+        .await;
+
+        std::fs::copy(&live_path, &copy_path).unwrap();
+        std::fs::copy(
+            directory.path().join("live.db-wal"),
+            directory.path().join("EventStore.db-wal"),
+        )
+        .unwrap();
+        connection.close().await.unwrap();
+
+        let (events, _) = read_all(&copy_path).await;
+
+        // `A` is also on the page in the log, but is only reported once.
+        let names_and_provenances = events
+            .iter()
+            .map(|event| {
+                (
+                    event.event_name(),
+                    matches!(
+                        event.provenance(),
+                        EventProvenance::WalFrame { .. }
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names_and_provenances,
+            vec![("A", false), ("B", true)]
+        );
+    }
+}
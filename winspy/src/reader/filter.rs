@@ -0,0 +1,495 @@
+//! Filtering of persisted events, pushed down into the events query where possible.
+
+use chrono::{DateTime, Utc};
+use miette::{miette, Result};
+
+use crate::{
+    models::{filetime::FileTime, persisted_event::PersistedEvent, producer::Producer},
+    user::{is_same_user, user_of},
+};
+
+/// Restricts which persisted events are read. Every criterion that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// Only events at or after this time.
+    pub start_time: Option<DateTime<Utc>>,
+
+    /// Only events before this time.
+    pub end_time: Option<DateTime<Utc>>,
+
+    /// Only events whose `full_event_name` starts with this prefix.
+    pub event_name_prefix: Option<String>,
+
+    /// Only events whose `full_event_name` matches this pattern
+    /// (SQLite `GLOB` syntax: `*`, `?` and `[...]`, case-sensitive).
+    pub event_name_glob: Option<String>,
+
+    /// Only events of the producer with this name (e.g. `Windows`), compared case-insensitively.
+    pub producer_name: Option<String>,
+
+    /// Only events of the provider group with this GUID, compared case-insensitively.
+    pub provider_group_guid: Option<String>,
+
+    /// Only core (or only non-core) events.
+    pub is_core: Option<bool>,
+
+    /// Only events of this user (see [`user_of`]), compared case-insensitively.
+    /// Matched after decoding, as it is part of the payload.
+    pub user: Option<String>,
+}
+
+impl EventFilter {
+    /// Whether any criterion is set at all.
+    pub fn is_empty(&self) -> bool {
+        self.start_time.is_none()
+            && self.end_time.is_none()
+            && self.event_name_prefix.is_none()
+            && self.event_name_glob.is_none()
+            && self.producer_name.is_none()
+            && self.provider_group_guid.is_none()
+            && self.is_core.is_none()
+            && self.user.is_none()
+    }
+
+    /// Converts times into `FILETIME` ticks and the producer name
+    /// into the IDs it has in a particular database.
+    pub(super) fn resolve(&self, producers: &[Producer]) -> Result<ResolvedEventFilter> {
+        let to_filetime = |timestamp: &DateTime<Utc>| {
+            FileTime::from_utc(timestamp).ok_or_else(|| {
+                miette!(
+                    "Time {} cannot be represented as a FILETIME.",
+                    timestamp
+                )
+            })
+        };
+
+        let producer_ids = self.producer_name.as_ref().map(|producer_name| {
+            producers
+                .iter()
+                .filter(|producer| producer.name().eq_ignore_ascii_case(producer_name))
+                .map(|producer| producer.id().value())
+                .collect()
+        });
+
+        Ok(ResolvedEventFilter {
+            start_filetime: self.start_time.as_ref().map(to_filetime).transpose()?,
+            end_filetime: self.end_time.as_ref().map(to_filetime).transpose()?,
+            event_name_prefix: self.event_name_prefix.clone(),
+            event_name_glob: self.event_name_glob.clone(),
+            producer_ids,
+            provider_group_guid: self.provider_group_guid.clone(),
+            is_core: self.is_core,
+            user: self.user.clone(),
+        })
+    }
+}
+
+/// A value bound to a placeholder of the events query.
+#[derive(Debug, Clone)]
+pub(super) enum QueryArgument {
+    Integer(i64),
+    Text(String),
+}
+
+/// An [`EventFilter`] prepared for a particular database.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ResolvedEventFilter {
+    start_filetime: Option<FileTime>,
+    end_filetime: Option<FileTime>,
+    event_name_prefix: Option<String>,
+    event_name_glob: Option<String>,
+    producer_ids: Option<Vec<i64>>,
+    provider_group_guid: Option<String>,
+    is_core: Option<bool>,
+    user: Option<String>,
+}
+
+impl ResolvedEventFilter {
+    /// Builds the `WHERE` clause of the events query (empty if nothing is filtered),
+    /// together with the values of its placeholders, in order.
+    ///
+    /// Expects `events_persisted` to be aliased as `e` and `provider_groups` as `g`.
+    pub(super) fn where_clause(&self) -> (String, Vec<QueryArgument>) {
+        let mut conditions = Vec::new();
+        let mut arguments = Vec::new();
+
+        if let Some(start_filetime) = self.start_filetime {
+            conditions.push("e.timestamp >= ?".to_string());
+            arguments.push(QueryArgument::Integer(start_filetime.ticks()));
+        }
+
+        if let Some(end_filetime) = self.end_filetime {
+            conditions.push("e.timestamp < ?".to_string());
+            arguments.push(QueryArgument::Integer(end_filetime.ticks()));
+        }
+
+        // A range rather than a function of the column, so an index on it can be used.
+        if let Some(prefix) = &self.event_name_prefix {
+            conditions.push("e.full_event_name >= ?".to_string());
+            arguments.push(QueryArgument::Text(prefix.clone()));
+
+            if let Some(upper_bound) = prefix_upper_bound(prefix) {
+                conditions.push("e.full_event_name < ?".to_string());
+                arguments.push(QueryArgument::Text(upper_bound));
+            }
+        }
+
+        if let Some(pattern) = &self.event_name_glob {
+            conditions.push("e.full_event_name GLOB ?".to_string());
+            arguments.push(QueryArgument::Text(pattern.clone()));
+        }
+
+        if let Some(producer_ids) = &self.producer_ids {
+            let placeholders = vec!["?"; producer_ids.len()].join(", ");
+            conditions.push(format!("e.producer_id IN ({placeholders})"));
+            arguments.extend(producer_ids.iter().copied().map(QueryArgument::Integer));
+        }
+
+        if let Some(guid) = &self.provider_group_guid {
+            conditions.push("g.group_guid = ? COLLATE NOCASE".to_string());
+            arguments.push(QueryArgument::Text(guid.clone()));
+        }
+
+        if let Some(is_core) = self.is_core {
+            conditions.push(if is_core {
+                "e.is_core != 0".to_string()
+            } else {
+                "e.is_core = 0".to_string()
+            });
+        }
+
+        if conditions.is_empty() {
+            return (String::new(), arguments);
+        }
+
+        (
+            format!(" WHERE {}", conditions.join(" AND ")),
+            arguments,
+        )
+    }
+
+    /// Applies the same criteria as [`Self::where_clause`] to an already decoded event,
+    /// for events that do not come from the events query (e.g. recovered ones).
+    pub(super) fn matches(&self, event: &PersistedEvent) -> bool {
+        let filetime = event.filetime();
+
+        self.start_filetime.is_none_or(|start| filetime >= start)
+            && self.end_filetime.is_none_or(|end| filetime < end)
+            && self
+                .event_name_prefix
+                .as_ref()
+                .is_none_or(|prefix| event.event_name().starts_with(prefix.as_str()))
+            && self
+                .event_name_glob
+                .as_ref()
+                .is_none_or(|pattern| glob_matches(pattern, event.event_name()))
+            && self
+                .producer_ids
+                .as_ref()
+                .is_none_or(|ids| ids.contains(&event.producer_id().value()))
+            && self
+                .provider_group_guid
+                .as_ref()
+                .is_none_or(|guid| event.provider_group().guid().eq_ignore_ascii_case(guid))
+            && self
+                .is_core
+                .is_none_or(|is_core| event.is_core() == is_core)
+            && self.matches_after_query(event)
+    }
+
+    /// Applies the criteria that [`Self::where_clause`] cannot express,
+    /// to events that come from the events query.
+    pub(super) fn matches_after_query(&self, event: &PersistedEvent) -> bool {
+        self.user.as_ref().is_none_or(|wanted_user| {
+            user_of(event).is_some_and(|user| is_same_user(&user, wanted_user))
+        })
+    }
+}
+
+/// Matches `text` against a pattern the same way SQLite's `GLOB` operator does.
+///
+/// Uses the iterative wildcard match: on a mismatch, only the most recent `*` is retried
+/// with one more character, so matching takes at most `pattern.len() * text.len()` steps.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let mut pattern_index = 0;
+    let mut text_index = 0;
+
+    // Pattern index after the most recent `*` and the text index it is retried from.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while text_index < text.len() {
+        if pattern.get(pattern_index) == Some(&'*') {
+            pattern_index += 1;
+            backtrack = Some((pattern_index, text_index));
+            continue;
+        }
+
+        if let Some(next_pattern_index) =
+            match_single_character(&pattern[pattern_index..], text[text_index])
+        {
+            pattern_index += next_pattern_index;
+            text_index += 1;
+            continue;
+        }
+
+        let Some((star_pattern_index, star_text_index)) = backtrack else {
+            return false;
+        };
+        pattern_index = star_pattern_index;
+        text_index = star_text_index + 1;
+        backtrack = Some((star_pattern_index, text_index));
+    }
+
+    pattern[pattern_index..]
+        .iter()
+        .all(|&character| character == '*')
+}
+
+/// Matches one `character` against the start of `pattern`, which must not be a `*`.
+///
+/// Returns the number of pattern characters consumed if it matched.
+fn match_single_character(pattern: &[char], character: char) -> Option<usize> {
+    match pattern.split_first()? {
+        ('?', _) => Some(1),
+        ('[', rest) => {
+            // An unterminated set never matches (as in SQLite).
+            let (matched, remaining_pattern) = match_character_set(rest, character)?;
+            matched.then_some(pattern.len() - remaining_pattern.len())
+        }
+        (&literal, _) => (literal == character).then_some(1),
+    }
+}
+
+/// Matches `character` against the set that starts right after a `[`.
+///
+/// Returns whether it matched and the pattern after the closing `]`.
+fn match_character_set(pattern: &[char], character: char) -> Option<(bool, &[char])> {
+    let (negated, mut pattern) = match pattern.split_first() {
+        Some(('^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    let mut is_first = true;
+
+    loop {
+        let (&current, rest) = pattern.split_first()?;
+
+        // A `]` right at the start of the set is an ordinary character.
+        if current == ']' && !is_first {
+            return Some((matched != negated, rest));
+        }
+
+        match rest {
+            ['-', end, after @ ..] if *end != ']' => {
+                matched |= (current..=*end).contains(&character);
+                pattern = after;
+            }
+            _ => {
+                matched |= current == character;
+                pattern = rest;
+            }
+        }
+
+        is_first = false;
+    }
+}
+
+/// The smallest string greater than all strings that start with `prefix` (in `BINARY`
+/// collation, which compares UTF-8 bytes and so code points), if there is one.
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut upper_bound = prefix.to_string();
+
+    // The last character that has a successor is replaced by it, dropping the ones after it.
+    while let Some(last_character) = upper_bound.pop() {
+        let successor =
+            (u32::from(last_character) + 1..=u32::from(char::MAX)).find_map(char::from_u32);
+
+        if let Some(successor) = successor {
+            upper_bound.push(successor);
+            return Some(upper_bound);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqliteRow},
+        ConnectOptions, Row, SqliteConnection,
+    };
+
+    use super::*;
+
+    #[test]
+    fn bounds_prefixes() {
+        assert_eq!(
+            prefix_upper_bound("Microsoft."),
+            Some("Microsoft/".to_string())
+        );
+        assert_eq!(
+            prefix_upper_bound("a\u{d7ff}"),
+            Some("a\u{e000}".to_string())
+        );
+        assert_eq!(
+            prefix_upper_bound("a\u{10ffff}"),
+            Some("b".to_string())
+        );
+        assert_eq!(prefix_upper_bound("\u{10ffff}"), None);
+        assert_eq!(prefix_upper_bound(""), None);
+    }
+
+    async fn fetch_rows(
+        connection: &mut SqliteConnection,
+        query: &str,
+        arguments: &[QueryArgument],
+    ) -> Vec<SqliteRow> {
+        let mut query = sqlx::query(query);
+        for argument in arguments {
+            query = match argument {
+                QueryArgument::Integer(value) => query.bind(*value),
+                QueryArgument::Text(value) => query.bind(value.as_str()),
+            };
+        }
+
+        query.fetch_all(connection).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn filters_prefixes_in_sqlite_with_an_index() {
+        let mut connection = "sqlite::memory:"
+            .parse::<SqliteConnectOptions>()
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE events_persisted (full_event_name TEXT); \
+            CREATE INDEX events_by_name ON events_persisted (full_event_name);",
+        )
+        .execute(&mut connection)
+        .await
+        .unwrap();
+
+        let event_names = [
+            "Microsoft",
+            "Microsoft.",
+            "Microsoft.Windows.Battery",
+            "Microsoft/Windows",
+            "Microsoft-Windows",
+            "microsoft.windows",
+            "Microsoft.\u{10ffff}",
+            "Aria.f4a7d46e472049dfba756e11bdbbc08f.Microsoft.WebBrowser",
+        ];
+        for event_name in event_names {
+            sqlx::query("INSERT INTO events_persisted VALUES (?)")
+                .bind(event_name)
+                .execute(&mut connection)
+                .await
+                .unwrap();
+        }
+
+        for prefix in [
+            "Microsoft.",
+            "Microsoft",
+            "M",
+            "",
+            "Microsoft.\u{10ffff}",
+            "Z",
+        ] {
+            let filter = ResolvedEventFilter {
+                event_name_prefix: Some(prefix.to_string()),
+                ..Default::default()
+            };
+            let (where_clause, arguments) = filter.where_clause();
+            let query = format!(
+                "SELECT full_event_name FROM events_persisted e{where_clause} \
+                ORDER BY full_event_name"
+            );
+
+            let names = fetch_rows(&mut connection, &query, &arguments)
+                .await
+                .iter()
+                .map(|row| row.get::<String, _>(0))
+                .collect::<Vec<_>>();
+
+            let mut expected = event_names
+                .iter()
+                .filter(|event_name| event_name.starts_with(prefix))
+                .map(|event_name| event_name.to_string())
+                .collect::<Vec<_>>();
+            expected.sort();
+            assert_eq!(names, expected, "{prefix:?}");
+
+            if !prefix.is_empty() {
+                let plan = fetch_rows(
+                    &mut connection,
+                    &format!("EXPLAIN QUERY PLAN {query}"),
+                    &arguments,
+                )
+                .await
+                .iter()
+                .map(|row| row.get::<String, _>("detail"))
+                .collect::<Vec<_>>();
+                assert!(
+                    plan.iter()
+                        .any(|detail| detail.starts_with("SEARCH e USING COVERING INDEX")),
+                    "{prefix:?}: {plan:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn matches_like_sqlite_glob() {
+        let cases = [
+            ("*", "", true),
+            ("", "", true),
+            ("", "a", false),
+            ("Microsoft.*", "Microsoft.Windows.Battery", true),
+            (
+                "*.Battery*",
+                "Microsoft.Windows.Battery.Info",
+                true,
+            ),
+            (
+                "*Battery",
+                "Microsoft.Windows.Battery.Info",
+                false,
+            ),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYbZ", false),
+            ("[abc]x", "bx", true),
+            ("[^abc]x", "bx", false),
+            ("[a-c]*", "cat", true),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("[abc", "a", false),
+            ("A*", "a", false),
+        ];
+
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                glob_matches(pattern, text),
+                expected,
+                "{pattern:?} against {text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_pathological_patterns_quickly() {
+        let pattern = format!("{}b", "*a".repeat(30));
+        let text = "a".repeat(5000);
+
+        assert!(!glob_matches(&pattern, &text));
+        assert!(glob_matches(&pattern, &format!("{text}b")));
+    }
+}
@@ -0,0 +1,98 @@
+//! Opening databases that were read into memory, e.g. extracted from a disk image.
+
+use std::{ffi::c_int, path::PathBuf, ptr::NonNull, sync::Arc};
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_deserialize, sqlite3_malloc64, SQLITE_DESERIALIZE_FREEONCLOSE,
+    SQLITE_DESERIALIZE_READONLY, SQLITE_OK,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+
+/// Offsets of the file format write and read versions in the database header.
+const FILE_FORMAT_VERSION_OFFSETS: [usize; 2] = [18, 19];
+
+/// File format version of databases in rollback journal (non-WAL) mode.
+const LEGACY_FILE_FORMAT_VERSION: u8 = 1;
+
+/// A database (and its write-ahead log) held in memory.
+#[derive(Debug, Clone)]
+pub struct InMemoryDatabase {
+    /// Path the database is reported under (it does not need to exist).
+    pub path: PathBuf,
+    pub database: Arc<[u8]>,
+    pub wal: Option<Arc<[u8]>>,
+}
+
+/// Opens a pool whose connections read a private, read-only copy of `database`.
+///
+/// The write-ahead log is not applied, the same as for databases opened as `immutable`.
+pub(super) async fn connect_in_memory(database: Arc<[u8]>) -> Result<SqlitePool> {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .after_connect(move |connection, _| {
+            let database = database.clone();
+
+            Box::pin(async move {
+                let mut handle = connection.lock_handle().await?;
+
+                // SAFETY: the handle is locked for the duration of the call, and
+                // `deserialize_into` hands SQLite a buffer it owns from then on.
+                unsafe { deserialize_into(handle.as_raw_handle(), &database) }
+                    .map_err(|error| sqlx::Error::Configuration(error.to_string().into()))
+            })
+        })
+        .connect_with(SqliteConnectOptions::new())
+        .await
+        .into_diagnostic()
+}
+
+/// Replaces the `main` database of the connection with a copy of `database`.
+///
+/// # Safety
+///
+/// `handle` must be a valid connection that is not used concurrently.
+unsafe fn deserialize_into(handle: NonNull<sqlite3>, database: &[u8]) -> Result<()> {
+    let size = database.len();
+
+    // SQLite frees the buffer when the connection closes (or if deserializing fails).
+    let buffer = sqlite3_malloc64(size as u64).cast::<u8>();
+    if buffer.is_null() {
+        return Err(miette!(
+            "Failed to allocate {} bytes for the database.",
+            size
+        ));
+    }
+
+    let copy = std::slice::from_raw_parts_mut(buffer, size);
+    copy.copy_from_slice(database);
+
+    // An in-memory database cannot use a write-ahead log, and SQLite refuses to open one
+    // whose header says it is in WAL mode. Only the private copy is changed.
+    for offset in FILE_FORMAT_VERSION_OFFSETS {
+        if let Some(version) = copy.get_mut(offset) {
+            *version = LEGACY_FILE_FORMAT_VERSION;
+        }
+    }
+
+    let result = sqlite3_deserialize(
+        handle.as_ptr(),
+        c"main".as_ptr(),
+        buffer,
+        size as i64,
+        size as i64,
+        (SQLITE_DESERIALIZE_FREEONCLOSE | SQLITE_DESERIALIZE_READONLY) as _,
+    );
+
+    if result != SQLITE_OK as c_int {
+        return Err(miette!(
+            "SQLite failed to load the database from memory (error {}).",
+            result
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,735 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures::{stream, stream::BoxStream, Stream, StreamExt};
+use miette::{miette, Context, IntoDiagnostic, Result};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow},
+    FromRow, SqlitePool,
+};
+use tracing::{info, warn};
+
+use self::{
+    event_store::EventStoreReader,
+    filter::{EventFilter, QueryArgument, ResolvedEventFilter},
+    in_memory::{connect_in_memory, InMemoryDatabase},
+    recovery::{CarvedRecords, EventsPersistedLayout, RecoveredEvents},
+    schema::{DatabaseSchema, SchemaVersion},
+};
+
+use crate::{
+    models::{
+        category::{Category, CategoryId},
+        error::{EventReaderError, SavedSqliteRow},
+        filetime::FileTime,
+        persisted_event::{LoggingBinary, PersistedEvent, PersistedEventPayload},
+        producer::{Producer, ProducerId},
+        provenance::EventProvenance,
+        provider_group::ProviderGroup,
+        tag_description::{TagDescription, TagDescriptionId},
+    },
+    require_some,
+    sqlite::{database::DatabaseFile, wal::WriteAheadLog},
+};
+
+pub mod event_store;
+pub mod filter;
+pub mod in_memory;
+mod recovery;
+pub mod schema;
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq)]
+struct EventTranscriptTableRecord {
+    sid: Option<String>,
+    timestamp: Option<i64>,
+    payload: Option<String>,
+    full_event_name: Option<String>,
+    full_event_name_hash: Option<i64>,
+    is_core: Option<i64>,
+    provider_group_id: Option<i64>,
+    provider_group_guid: Option<String>,
+    logging_binary_name: Option<String>,
+    friendly_logging_binary_name: Option<String>,
+    producer_id: i64,
+    producer_id_name: Option<String>,
+}
+
+/// Timing of a single pass over the `events_persisted` table.
+struct EventLoadingStatistics {
+    lookup_preload_duration: Duration,
+    started_at: Instant,
+    decoding_duration: Duration,
+    number_of_loaded_events: u64,
+    number_of_recovered_events: u64,
+    number_of_rejected_rows: u64,
+}
+
+impl EventLoadingStatistics {
+    fn new(lookup_preload_duration: Duration) -> Self {
+        Self {
+            lookup_preload_duration,
+            started_at: Instant::now(),
+            decoding_duration: Duration::ZERO,
+            number_of_loaded_events: 0,
+            number_of_recovered_events: 0,
+            number_of_rejected_rows: 0,
+        }
+    }
+
+    fn record_event(&mut self, decoding_duration: Duration) {
+        self.decoding_duration += decoding_duration;
+        self.number_of_loaded_events += 1;
+    }
+
+    fn record_rejected_row(&mut self, decoding_duration: Duration) {
+        self.decoding_duration += decoding_duration;
+        self.number_of_rejected_rows += 1;
+    }
+
+    fn record_recovered_event(&mut self) {
+        self.number_of_recovered_events += 1;
+    }
+
+    fn log_summary(&self) {
+        let streaming_duration = self.started_at.elapsed();
+        let events_per_second =
+            self.number_of_loaded_events as f64 / streaming_duration.as_secs_f64().max(f64::EPSILON);
+
+        info!(
+            "Loaded {} events (and {} recovered ones, {} rows rejected) in {:.2?} ({:.0} events/s): \
+            lookup table preload {:.2?}, row decoding {:.2?}, database reads {:.2?}.",
+            self.number_of_loaded_events,
+            self.number_of_recovered_events,
+            self.number_of_rejected_rows,
+            streaming_duration,
+            events_per_second,
+            self.lookup_preload_duration,
+            self.decoding_duration,
+            streaming_duration.saturating_sub(self.decoding_duration)
+        );
+    }
+}
+
+/// State of the stream returned by [`EventTranscriptReader::stream_events`].
+struct EventStreamState<'a, S> {
+    /// `None` once all rows of the database have been streamed.
+    rows: Option<S>,
+
+    /// Events recovered from outside of the database (if there are any),
+    /// emitted once all rows have been streamed.
+    recovered_events: Option<RecoveredEvents<'a>>,
+
+    statistics: EventLoadingStatistics,
+}
+
+/// A database persisted events can be read from.
+pub enum EventSourceReader {
+    Transcript(Box<EventTranscriptReader>),
+    Store(Box<EventStoreReader>),
+}
+
+impl EventSourceReader {
+    /// Path of the database this reader reads from.
+    pub fn database_path(&self) -> &Path {
+        match self {
+            Self::Transcript(reader) => reader.database_path(),
+            Self::Store(reader) => reader.database_path(),
+        }
+    }
+
+    /// Streams all events of the database (see the `stream_events` method of each reader).
+    pub async fn stream_events(&self) -> BoxStream<'_, Result<PersistedEvent>> {
+        match self {
+            Self::Transcript(reader) => reader.stream_events().await.boxed(),
+            Self::Store(reader) => reader.stream_events().await.boxed(),
+        }
+    }
+
+    /// Takes the rows rejected so far (only transcripts in lenient mode reject any).
+    pub fn take_rejected_rows(&self) -> Vec<EventReaderError> {
+        match self {
+            Self::Transcript(reader) => reader.take_rejected_rows(),
+            Self::Store(_) => Vec::new(),
+        }
+    }
+
+    /// Number of records skipped because their payload is not JSON
+    /// (only event stores skip any; `None` for transcripts).
+    pub fn number_of_records_without_json_payload(&self) -> Option<u64> {
+        match self {
+            Self::Transcript(_) => None,
+            Self::Store(reader) => Some(reader.number_of_records_without_json_payload()),
+        }
+    }
+
+    /// Closes all connections to the database.
+    pub async fn close(self) {
+        match self {
+            Self::Transcript(reader) => reader.close().await,
+            Self::Store(reader) => reader.close().await,
+        }
+    }
+}
+
+/// Optional behaviour of [`EventTranscriptReader`].
+#[derive(Debug, Clone, Default)]
+pub struct EventTranscriptReaderOptions {
+    /// Carve deleted `events_persisted` records from free pages, freeblocks
+    /// and unallocated space of the database file.
+    pub carve_deleted_records: bool,
+
+    /// Skip `events_persisted` rows that cannot be decoded instead of failing,
+    /// and collect them (see [`EventTranscriptReader::take_rejected_rows`]).
+    pub lenient: bool,
+
+    /// Only read events matching this filter.
+    pub filter: EventFilter,
+}
+
+/// Where the bytes of a transcript are read from, besides through SQLite.
+enum DatabaseSource {
+    /// The file at the database path, with the write-ahead log next to it.
+    File,
+
+    /// A database held in memory.
+    Memory {
+        database: Arc<[u8]>,
+        wal: Option<Arc<[u8]>>,
+    },
+}
+
+pub struct EventTranscriptReader {
+    database_path: PathBuf,
+    source: DatabaseSource,
+    options: EventTranscriptReaderOptions,
+
+    pool: SqlitePool,
+    schema: DatabaseSchema,
+
+    /// Query selecting all `events_persisted` rows that match the filter,
+    /// built for the detected schema.
+    events_query: String,
+
+    /// Values of the placeholders in `events_query`.
+    events_query_arguments: Vec<QueryArgument>,
+
+    /// The filter of the options, applied to events that are not read through `events_query`.
+    filter: ResolvedEventFilter,
+
+    /// Rows skipped in lenient mode, with the reason they were rejected.
+    rejected_rows: Mutex<Vec<EventReaderError>>,
+
+    /// Category IDs of each event, keyed by `full_event_name_hash`.
+    event_category_ids: HashMap<i64, Vec<CategoryId>>,
+
+    /// Tag IDs of each event, keyed by `full_event_name_hash`.
+    event_tag_ids: HashMap<i64, Vec<TagDescriptionId>>,
+
+    lookup_preload_duration: Duration,
+}
+
+impl EventTranscriptReader {
+    pub async fn new(database_path: &Path, options: EventTranscriptReaderOptions) -> Result<Self> {
+        if !database_path.exists() || !database_path.is_file() {
+            return Err(miette!(
+                "Provided file does not exist or is not a file."
+            ));
+        }
+
+        let Some(path_str) = database_path.to_str() else {
+            return Err(miette!(
+                "While file does exist, its name is not valid UTF-8."
+            ));
+        };
+
+        // The database is evidence and must not be modified in any way. Besides opening it
+        // read-only, `immutable` stops SQLite from taking locks and from creating `-wal`
+        // and `-shm` files next to it. As a consequence, frames in an existing
+        // write-ahead log are not visible through this connection.
+        let connect_options = SqliteConnectOptions::new()
+            .filename(path_str)
+            .read_only(true)
+            .immutable(true);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(connect_options)
+            .await
+            .into_diagnostic()?;
+
+        Self::from_pool(database_path, DatabaseSource::File, pool, options).await
+    }
+
+    /// Reads a transcript that is held in memory (e.g. extracted from a disk image),
+    /// without writing it anywhere.
+    pub async fn new_in_memory(
+        database: InMemoryDatabase,
+        options: EventTranscriptReaderOptions,
+    ) -> Result<Self> {
+        let pool = connect_in_memory(database.database.clone()).await?;
+
+        Self::from_pool(
+            &database.path,
+            DatabaseSource::Memory {
+                database: database.database,
+                wal: database.wal,
+            },
+            pool,
+            options,
+        )
+        .await
+    }
+
+    async fn from_pool(
+        database_path: &Path,
+        source: DatabaseSource,
+        pool: SqlitePool,
+        options: EventTranscriptReaderOptions,
+    ) -> Result<Self> {
+        let schema = DatabaseSchema::detect(&pool)
+            .await
+            .wrap_err("Failed to detect the schema of the database.")?;
+
+        info!("Detected database schema: {}.", schema.version());
+
+        let filter = if options.filter.producer_name.is_some() {
+            let mut connection = pool.acquire().await.into_diagnostic()?;
+            let producers = Producer::load_all_from_database(&mut connection, schema.version())
+                .await
+                .wrap_err("Failed to load producers for the event filter.")?;
+
+            options.filter.resolve(&producers)?
+        } else {
+            options.filter.resolve(&[])?
+        };
+
+        let (where_clause, events_query_arguments) = filter.where_clause();
+        if !options.filter.is_empty() {
+            info!("Filtering events with:{}", where_clause);
+        }
+
+        // Preload the event-to-category and event-to-tag mappings,
+        // so decoding a row does not need any additional queries.
+        let preload_started_at = Instant::now();
+
+        let mut connection = pool.acquire().await.into_diagnostic()?;
+        let event_category_ids =
+            CategoryId::load_all_event_mappings_from_database(&mut connection).await?;
+        let event_tag_ids =
+            TagDescriptionId::load_all_event_mappings_from_database(&mut connection).await?;
+        drop(connection);
+
+        let lookup_preload_duration = preload_started_at.elapsed();
+
+        info!(
+            "Preloaded categories of {} and tags of {} event names in {:.2?}.",
+            event_category_ids.len(),
+            event_tag_ids.len(),
+            lookup_preload_duration
+        );
+
+        Ok(Self {
+            database_path: database_path.to_path_buf(),
+            source,
+            options,
+            pool,
+            events_query: schema.version().events_query() + &where_clause,
+            events_query_arguments,
+            filter,
+            rejected_rows: Mutex::new(Vec::new()),
+            schema,
+            event_category_ids,
+            event_tag_ids,
+            lookup_preload_duration,
+        })
+    }
+
+    /// Path of the database this reader reads from.
+    pub fn database_path(&self) -> &Path {
+        &self.database_path
+    }
+
+    /// Version of the database schema, as detected when the database was opened.
+    pub fn schema_version(&self) -> SchemaVersion {
+        self.schema.version()
+    }
+
+    /// Takes the rows rejected so far (only collected in lenient mode).
+    pub fn take_rejected_rows(&self) -> Vec<EventReaderError> {
+        std::mem::take(&mut *self.rejected_rows.lock().unwrap())
+    }
+
+    /// Closes all connections to the database.
+    pub async fn close(self) {
+        self.pool.close().await;
+    }
+
+    pub async fn load_all_tags(&self) -> Result<Vec<TagDescription>> {
+        let mut connection = self.pool.acquire().await.into_diagnostic()?;
+        TagDescription::load_all_from_database(&mut connection).await
+    }
+
+    pub async fn load_all_producers(&self) -> Result<Vec<Producer>> {
+        let mut connection = self.pool.acquire().await.into_diagnostic()?;
+        Producer::load_all_from_database(&mut connection, self.schema.version()).await
+    }
+
+    pub async fn load_all_categories(&self) -> Result<Vec<Category>> {
+        let mut connection = self.pool.acquire().await.into_diagnostic()?;
+        Category::load_all_from_database(&mut connection).await
+    }
+
+    /// Path of the write-ahead log that belongs to the database.
+    fn wal_path(&self) -> PathBuf {
+        let mut wal_path = OsString::from(self.database_path.as_os_str());
+        wal_path.push("-wal");
+        PathBuf::from(wal_path)
+    }
+
+    /// Prepares to recover events from the write-ahead log (if there is one) and,
+    /// if enabled, to carve deleted events from the database file. Records are only
+    /// recovered and decoded as the returned events are taken.
+    ///
+    /// Failures are logged instead of returned: the main database can still be processed.
+    async fn recovered_events(&self) -> Option<RecoveredEvents<'_>> {
+        let wal_path = self.wal_path();
+        let wal = match &self.source {
+            DatabaseSource::File => wal_path.is_file().then(|| WriteAheadLog::read(&wal_path)),
+            DatabaseSource::Memory { wal, .. } => wal.as_ref().map(|wal| WriteAheadLog::parse(wal)),
+        };
+
+        if wal.is_none() && !self.options.carve_deleted_records {
+            return None;
+        }
+
+        let database = match &self.source {
+            DatabaseSource::File => DatabaseFile::open(&self.database_path),
+            DatabaseSource::Memory { database, .. } => DatabaseFile::from_bytes(database.clone()),
+        };
+        let database = match database {
+            Ok(database) => Arc::new(database),
+            Err(error) => {
+                warn!("Failed to prepare event recovery: {:?}", error);
+                return None;
+            }
+        };
+
+        let layout = Arc::new(EventsPersistedLayout::new(
+            self.schema.events_persisted_columns(),
+        ));
+
+        let provider_group_guids = async {
+            let mut connection = self.pool.acquire().await.into_diagnostic()?;
+            let provider_group_guids = ProviderGroup::load_all_from_database(&mut connection)
+                .await?
+                .into_iter()
+                .map(|group| (group.id(), group.guid().to_string()))
+                .collect::<HashMap<_, _>>();
+
+            Result::<_>::Ok(Arc::new(provider_group_guids))
+        }
+        .await;
+
+        let provider_group_guids = match provider_group_guids {
+            Ok(provider_group_guids) => provider_group_guids,
+            Err(error) => {
+                warn!("Failed to prepare event recovery: {:?}", error);
+                return None;
+            }
+        };
+
+        let wal_records = match wal.map(|wal| {
+            recovery::recover_records_from_wal(
+                database.clone(),
+                wal?,
+                layout.clone(),
+                provider_group_guids.clone(),
+            )
+        }) {
+            Some(Ok(wal_records)) => Some(wal_records),
+            Some(Err(error)) => {
+                warn!(
+                    "Failed to recover events from write-ahead log {}: {:?}",
+                    wal_path.display(),
+                    error
+                );
+                None
+            }
+            None => None,
+        };
+
+        let carved_records = if self.options.carve_deleted_records {
+            match CarvedRecords::new(database, layout, provider_group_guids) {
+                Ok(carved_records) => Some(carved_records),
+                Err(error) => {
+                    self.log_carving_error(error);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Carving stops at the first page that cannot be read.
+        let carved_records = carved_records
+            .into_iter()
+            .flatten()
+            .map_while(|carved_record| {
+                carved_record
+                    .map_err(|error| self.log_carving_error(error))
+                    .ok()
+            });
+
+        let recovered_events = wal_records
+            .into_iter()
+            .flatten()
+            .chain(carved_records)
+            .filter_map(|recovered_record| {
+                let provenance = recovered_record.provenance.clone();
+
+                match self.parse_event_record(
+                    recovered_record.record,
+                    recovered_record.provenance,
+                ) {
+                    Ok(event) => self.filter.matches(&event).then_some(event),
+                    Err(error) => {
+                        warn!(
+                            "Skipping unreadable record recovered from {:?}: {}",
+                            provenance, error
+                        );
+                        None
+                    }
+                }
+            });
+
+        RecoveredEvents::new(recovered_events)
+            .await
+            .map_err(|error| warn!("Failed to prepare event recovery: {:?}", error))
+            .ok()
+            .flatten()
+    }
+
+    fn log_carving_error(&self, error: miette::Report) {
+        warn!(
+            "Failed to carve events from {}: {:?}",
+            self.database_path.display(),
+            error
+        );
+    }
+
+    /// Streams all persisted events from the database.
+    ///
+    /// Rows are decoded one at a time as the stream is polled, so memory usage
+    /// does not depend on the size of the database. Afterwards, events recovered from
+    /// the write-ahead log (and carved from unused space, if enabled)
+    /// that are not present in the database are emitted,
+    /// marked with their [`EventProvenance`]. A timing summary is logged
+    /// once the stream is exhausted.
+    pub async fn stream_events(&self) -> impl Stream<Item = Result<PersistedEvent>> + '_ {
+        let recovered_events = self.recovered_events().await;
+
+        let mut events_query = sqlx::query(&self.events_query);
+        for argument in &self.events_query_arguments {
+            events_query = match argument {
+                QueryArgument::Integer(value) => events_query.bind(*value),
+                QueryArgument::Text(value) => events_query.bind(value.as_str()),
+            };
+        }
+
+        let rows = events_query.fetch(&self.pool);
+        let statistics = EventLoadingStatistics::new(self.lookup_preload_duration);
+
+        let state = EventStreamState {
+            rows: Some(rows),
+            recovered_events,
+            statistics,
+        };
+
+        stream::unfold(state, move |mut state| async move {
+            // First, stream all rows of the database itself.
+            if let Some(rows) = state.rows.as_mut() {
+                while let Some(row) = rows.next().await {
+                    let row = match row.into_diagnostic() {
+                        Ok(row) => row,
+                        Err(error) => return Some((Err(error), state)),
+                    };
+
+                    let decoding_started_at = Instant::now();
+                    let event = self.parse_event_row(&row);
+
+                    match event {
+                        Ok(event) => {
+                            state.statistics.record_event(decoding_started_at.elapsed());
+                            if let Some(recovered_events) = state.recovered_events.as_mut() {
+                                if let Err(error) = recovered_events.observe_main_event(&event).await
+                                {
+                                    return Some((Err(error), state));
+                                }
+                            }
+                            if self.filter.matches_after_query(&event) {
+                                return Some((Ok(event), state));
+                            }
+                        }
+                        Err(error) if self.options.lenient => {
+                            if let Err(error) = self.reject_row(&row, error) {
+                                return Some((Err(error), state));
+                            }
+                            state
+                                .statistics
+                                .record_rejected_row(decoding_started_at.elapsed());
+                        }
+                        Err(error) => return Some((Err(error), state)),
+                    }
+                }
+
+                state.rows = None;
+            }
+
+            // Then, the recovered events that the database does not contain.
+            let recovered_event = match state.recovered_events.as_mut() {
+                Some(recovered_events) => recovered_events.next().await,
+                None => Ok(None),
+            };
+
+            match recovered_event {
+                Ok(Some(event)) => {
+                    state.statistics.record_recovered_event();
+                    Some((Ok(event), state))
+                }
+                Ok(None) => {
+                    if let Some(recovered_events) = state.recovered_events.take() {
+                        recovered_events.close().await;
+                    }
+                    state.statistics.log_summary();
+                    None
+                }
+                Err(error) => {
+                    state.recovered_events = None;
+                    Some((Err(error), state))
+                }
+            }
+        })
+    }
+
+    fn parse_event_row(&self, row: &SqliteRow) -> Result<PersistedEvent> {
+        let record = EventTranscriptTableRecord::from_row(row).into_diagnostic()?;
+        self.parse_event_record(record, EventProvenance::MainDatabase)
+    }
+
+    /// Saves a row that could not be decoded, so it can be reported later.
+    fn reject_row(&self, row: &SqliteRow, error: miette::Report) -> Result<()> {
+        let saved_row = SavedSqliteRow::from_sqlite_row(row)
+            .wrap_err("Failed to save a row that could not be decoded.")?;
+
+        warn!("Rejected an events_persisted row: {}", error);
+
+        self.rejected_rows
+            .lock()
+            .unwrap()
+            .push(EventReaderError::RecordParsingError {
+                database_path: self.database_path.clone(),
+                table_name: "events_persisted".to_string(),
+                reason: error.to_string(),
+                saved_row,
+            });
+
+        Ok(())
+    }
+
+    fn parse_event_record(
+        &self,
+        row: EventTranscriptTableRecord,
+        provenance: EventProvenance,
+    ) -> Result<PersistedEvent> {
+        // Unpack columns and ensure that most of them are `Some`.
+        let device_id: String = require_some!(row.sid, "sid")?;
+        let raw_ldap_event_timestamp: i64 = require_some!(row.timestamp, "timestamp")?;
+        let raw_payload: Option<String> = row.payload;
+        let event_name: String = require_some!(row.full_event_name, "full_event_name")?;
+        let event_name_hash: i64 = require_some!(row.full_event_name_hash, "full_event_name_hash")?;
+        let is_core: bool = {
+            let is_core_integer: i64 = require_some!(row.is_core, "is_core")?;
+            is_core_integer != 0
+        };
+        let provider_group_id: i64 = require_some!(row.provider_group_id, "provider_group_id")?;
+        let provider_group_guid: String =
+            require_some!(row.provider_group_guid, "provider_group_guid")?;
+        let logging_binary_name: String =
+            require_some!(row.logging_binary_name, "logging_binary_name")?;
+        let logging_binary_friendly_name: String = require_some!(
+            row.friendly_logging_binary_name,
+            "friendly_logging_binary_name"
+        )?;
+        let producer_id: i64 = row.producer_id;
+
+        // Parse a LDAP timestamp into a UTC one (without losing sub-second precision).
+        let event_filetime = FileTime::from_ticks(raw_ldap_event_timestamp);
+        let event_timestamp = event_filetime
+            .to_utc()
+            .ok_or_else(|| miette!("Failed to construct UTC event timestamp."))?;
+
+        // Look up all related categories and tag descriptions (but only their IDs).
+        let category_ids = self
+            .event_category_ids
+            .get(&event_name_hash)
+            .cloned()
+            .unwrap_or_default();
+
+        let tag_ids = self
+            .event_tag_ids
+            .get(&event_name_hash)
+            .cloned()
+            .unwrap_or_default();
+
+        let payload_sha256: [u8; 32] =
+            Sha256::digest(raw_payload.as_deref().unwrap_or_default()).into();
+
+        // Parse the event payload, if any, as JSON.
+        let event_payload = if let Some(payload) = raw_payload {
+            match serde_json::from_str(&payload) {
+                Ok(parsed_payload) => PersistedEventPayload::Parsed {
+                    payload: parsed_payload,
+                },
+                Err(_) => PersistedEventPayload::Invalid {
+                    raw_payload: payload,
+                },
+            }
+        } else {
+            PersistedEventPayload::None
+        };
+
+        // Parse event provider, logging binary and producer ID.
+        let event_provider_group = ProviderGroup::new(provider_group_id, provider_group_guid);
+
+        let logging_binary = LoggingBinary {
+            name: logging_binary_name,
+            friendly_name: logging_binary_friendly_name,
+        };
+
+        let producer_id = ProducerId::new(producer_id);
+
+        // Structure the entire event into a [`PersistedEvent`] for future use.
+        Ok(PersistedEvent::new(
+            device_id,
+            event_timestamp,
+            event_filetime,
+            event_payload,
+            payload_sha256,
+            event_name,
+            event_name_hash,
+            is_core,
+            event_provider_group,
+            logging_binary,
+            producer_id,
+            category_ids,
+            tag_ids,
+            provenance,
+        ))
+    }
+}
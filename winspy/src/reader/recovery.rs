@@ -0,0 +1,698 @@
+//! Recovery of `events_persisted` records that SQLite itself does not return.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    iter::Peekable,
+    sync::Arc,
+};
+
+use miette::{miette, IntoDiagnostic, Result};
+use sha2::{Digest, Sha256};
+use sqlx::QueryBuilder;
+
+use super::EventTranscriptTableRecord;
+use crate::{
+    models::{
+        persisted_event::PersistedEvent,
+        provenance::{CarvedRegion, EventProvenance},
+    },
+    sqlite::{
+        carving::carve_records,
+        database::DatabaseFile,
+        freelist::freelist_pages,
+        page::{BTreePage, BTreePageType},
+        record::{decode_record, SqliteValue},
+        wal::WriteAheadLog,
+    },
+    temporary_database::TemporaryDatabase,
+};
+
+/// Column layout of the `events_persisted` table, needed to interpret raw records.
+pub(super) struct EventsPersistedLayout {
+    column_indices: HashMap<String, usize>,
+    number_of_columns: usize,
+}
+
+impl EventsPersistedLayout {
+    pub(super) fn new(column_names: &[String]) -> Self {
+        let column_indices = column_names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.clone(), index))
+            .collect::<HashMap<_, _>>();
+
+        Self {
+            number_of_columns: column_indices.len(),
+            column_indices,
+        }
+    }
+
+    fn value<'a>(&self, values: &'a [SqliteValue], column_name: &str) -> Option<&'a SqliteValue> {
+        values.get(*self.column_indices.get(column_name)?)
+    }
+
+    /// Checks whether a raw record has the shape of an `events_persisted` row.
+    ///
+    /// Records of other tables on the same pages are rejected this way.
+    fn matches(&self, values: &[SqliteValue]) -> bool {
+        // Records written before an `ALTER TABLE ... ADD COLUMN` can have fewer columns.
+        if values.is_empty() || values.len() > self.number_of_columns {
+            return false;
+        }
+
+        let has = |column_name: &str, predicate: fn(&SqliteValue) -> bool| {
+            self.value(values, column_name).is_some_and(predicate)
+        };
+
+        has("sid", SqliteValue::is_text)
+            && has("timestamp", SqliteValue::is_integer)
+            && has("full_event_name", SqliteValue::is_text)
+            && has("full_event_name_hash", SqliteValue::is_integer)
+            && has("payload", |value| {
+                value.is_text() || value.is_null()
+            })
+    }
+
+    /// Converts a raw record into the same shape as a row returned by the events query.
+    fn to_table_record(
+        &self,
+        values: &[SqliteValue],
+        provider_group_guids: &HashMap<i64, String>,
+    ) -> EventTranscriptTableRecord {
+        let text = |column_name: &str| {
+            self.value(values, column_name)
+                .cloned()
+                .and_then(SqliteValue::into_text)
+        };
+        let integer = |column_name: &str| {
+            self.value(values, column_name)
+                .and_then(SqliteValue::as_i64)
+        };
+
+        let provider_group_id = integer("provider_group_id").unwrap_or_default();
+
+        // Only the columns checked by [`Self::matches`] are required. Any other column that
+        // could not be recovered (or a provider group that no longer exists)
+        // is left empty instead of discarding the whole record.
+        EventTranscriptTableRecord {
+            sid: text("sid"),
+            timestamp: integer("timestamp"),
+            payload: text("payload"),
+            full_event_name: text("full_event_name"),
+            full_event_name_hash: integer("full_event_name_hash"),
+            is_core: Some(integer("is_core").unwrap_or_default()),
+            provider_group_id: Some(provider_group_id),
+            provider_group_guid: Some(
+                provider_group_guids
+                    .get(&provider_group_id)
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            logging_binary_name: Some(text("logging_binary_name").unwrap_or_default()),
+            friendly_logging_binary_name: Some(
+                text("friendly_logging_binary_name").unwrap_or_default(),
+            ),
+            producer_id: integer("producer_id").unwrap_or_default(),
+            producer_id_name: None,
+        }
+    }
+}
+
+/// A record recovered from outside of the live database, before it is decoded into an event.
+pub(super) struct RecoveredRecord {
+    pub(super) record: EventTranscriptTableRecord,
+    pub(super) provenance: EventProvenance,
+}
+
+/// A raw record of some table, found in a frame of the write-ahead log.
+pub(super) struct WalRecord {
+    pub(super) rowid: i64,
+    pub(super) values: Vec<SqliteValue>,
+    pub(super) provenance: EventProvenance,
+}
+
+/// Extracts every version of every record found in the write-ahead log that `matches`
+/// accepts, including frames SQLite ignores and pages that were later overwritten.
+///
+/// Frames are only read as the iterator is advanced.
+pub(super) struct WalRecords<M> {
+    database: Arc<DatabaseFile>,
+    wal: WriteAheadLog,
+    matches: M,
+    next_frame_index: usize,
+
+    /// The same record usually appears in many frames, as every change to a page
+    /// writes the entire page again. Only its first appearance is kept.
+    seen_cells: HashSet<(i64, [u8; 32])>,
+
+    /// Records of the last frame read that have not been returned yet.
+    pending_records: VecDeque<WalRecord>,
+}
+
+impl<M> WalRecords<M>
+where
+    M: Fn(&[SqliteValue]) -> bool,
+{
+    pub(super) fn new(database: Arc<DatabaseFile>, wal: WriteAheadLog, matches: M) -> Result<Self> {
+        if wal.page_size != database.header().page_size {
+            return Err(miette!(
+                "Page size of the write-ahead log ({}) does not match the database ({}).",
+                wal.page_size,
+                database.header().page_size
+            ));
+        }
+
+        Ok(Self {
+            database,
+            wal,
+            matches,
+            next_frame_index: 0,
+            seen_cells: HashSet::new(),
+            pending_records: VecDeque::new(),
+        })
+    }
+}
+
+impl<M> Iterator for WalRecords<M>
+where
+    M: Fn(&[SqliteValue]) -> bool,
+{
+    type Item = WalRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let text_encoding = self.database.header().text_encoding;
+        let usable_page_size = self.database.header().usable_page_size();
+
+        loop {
+            if let Some(record) = self.pending_records.pop_front() {
+                return Some(record);
+            }
+
+            let frame = self.wal.frames.get(self.next_frame_index)?;
+            self.next_frame_index += 1;
+
+            let Some(page) = BTreePage::parse(&frame.page, frame.page_number) else {
+                continue;
+            };
+
+            if page.page_type() != BTreePageType::LeafTable {
+                continue;
+            }
+
+            let page_lookup = self
+                .wal
+                .page_lookup_at(frame.frame_number, self.database.as_ref());
+
+            for cell in page.table_leaf_cells(usable_page_size, &page_lookup) {
+                let Some(values) = decode_record(&cell.payload, text_encoding) else {
+                    continue;
+                };
+
+                if !(self.matches)(&values) {
+                    continue;
+                }
+
+                let payload_digest: [u8; 32] = Sha256::digest(&cell.payload).into();
+                if !self.seen_cells.insert((cell.rowid, payload_digest)) {
+                    continue;
+                }
+
+                self.pending_records.push_back(WalRecord {
+                    rowid: cell.rowid,
+                    values,
+                    provenance: EventProvenance::WalFrame {
+                        frame_number: frame.frame_number,
+                        page_number: frame.page_number,
+                        frame_state: frame.state,
+                    },
+                });
+            }
+        }
+    }
+}
+
+/// Extracts every version of every `events_persisted` record found in the write-ahead log.
+pub(super) fn recover_records_from_wal(
+    database: Arc<DatabaseFile>,
+    wal: WriteAheadLog,
+    layout: Arc<EventsPersistedLayout>,
+    provider_group_guids: Arc<HashMap<i64, String>>,
+) -> Result<impl Iterator<Item = RecoveredRecord>> {
+    let records = WalRecords::new(database, wal, {
+        let layout = layout.clone();
+        move |values: &[SqliteValue]| layout.matches(values)
+    })?;
+
+    Ok(records.map(move |record| RecoveredRecord {
+        record: layout.to_table_record(&record.values, &provider_group_guids),
+        provenance: record.provenance,
+    }))
+}
+
+/// Carves `events_persisted` records from the unused space of the database file:
+/// pages on the freelist, freeblocks and the unallocated space of table leaf pages.
+///
+/// Pages are only read as the iterator is advanced.
+pub(super) struct CarvedRecords {
+    database: Arc<DatabaseFile>,
+    layout: Arc<EventsPersistedLayout>,
+    provider_group_guids: Arc<HashMap<i64, String>>,
+    free_pages: BTreeSet<u32>,
+    next_page_number: u32,
+
+    /// A deleted cell is often found both as a cell of a freed page and by scanning,
+    /// so records are deduplicated by their raw bytes.
+    seen_records: HashSet<[u8; 32]>,
+
+    /// Records of the last page read that have not been returned yet.
+    pending_records: VecDeque<RecoveredRecord>,
+}
+
+impl CarvedRecords {
+    pub(super) fn new(
+        database: Arc<DatabaseFile>,
+        layout: Arc<EventsPersistedLayout>,
+        provider_group_guids: Arc<HashMap<i64, String>>,
+    ) -> Result<Self> {
+        Ok(Self {
+            free_pages: freelist_pages(&database)?,
+            database,
+            layout,
+            provider_group_guids,
+            next_page_number: 1,
+            seen_records: HashSet::new(),
+            pending_records: VecDeque::new(),
+        })
+    }
+
+    fn carve_page(&mut self, page_number: u32) -> Result<()> {
+        let page = self.database.read_page(page_number)?;
+        let text_encoding = self.database.header().text_encoding;
+        let usable_page_size = self.database.header().usable_page_size();
+        let number_of_columns = 1..=self.layout.number_of_columns;
+
+        let leaf_page = BTreePage::parse(&page, page_number)
+            .filter(|btree_page| btree_page.page_type() == BTreePageType::LeafTable);
+
+        // Candidates as (offset in page, raw record, values, region).
+        let mut candidates = Vec::new();
+
+        if self.free_pages.contains(&page_number) {
+            // A freed table leaf page usually still has its header and cells intact.
+            if let Some(leaf_page) = &leaf_page {
+                for cell in leaf_page.table_leaf_cells(usable_page_size, self.database.as_ref()) {
+                    if let Some(values) = decode_record(&cell.payload, text_encoding) {
+                        candidates.push((
+                            cell.cell_offset,
+                            cell.payload,
+                            values,
+                            CarvedRegion::FreePage,
+                        ));
+                    }
+                }
+            }
+
+            for record in carve_records(
+                &page,
+                text_encoding,
+                number_of_columns.clone(),
+                |values| self.layout.matches(values),
+            ) {
+                candidates.push((
+                    record.offset,
+                    record.bytes,
+                    record.values,
+                    CarvedRegion::FreePage,
+                ));
+            }
+        } else if let Some(leaf_page) = &leaf_page {
+            let unused_regions = leaf_page
+                .freeblocks()
+                .into_iter()
+                .map(|freeblock| (freeblock, CarvedRegion::Freeblock))
+                .chain([(
+                    leaf_page.unallocated_space(),
+                    CarvedRegion::UnallocatedSpace,
+                )]);
+
+            for (range, region) in unused_regions {
+                for record in carve_records(
+                    &page[range.clone()],
+                    text_encoding,
+                    number_of_columns.clone(),
+                    |values| self.layout.matches(values),
+                ) {
+                    candidates.push((
+                        range.start + record.offset,
+                        record.bytes,
+                        record.values,
+                        region,
+                    ));
+                }
+            }
+        }
+
+        for (offset, raw_record, values, region) in candidates {
+            if !self.layout.matches(&values) {
+                continue;
+            }
+
+            if !self.seen_records.insert(Sha256::digest(&raw_record).into()) {
+                continue;
+            }
+
+            self.pending_records.push_back(RecoveredRecord {
+                record: self
+                    .layout
+                    .to_table_record(&values, &self.provider_group_guids),
+                provenance: EventProvenance::Carved {
+                    page_number,
+                    offset,
+                    region,
+                },
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for CarvedRecords {
+    type Item = Result<RecoveredRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.pending_records.pop_front() {
+                return Some(Ok(record));
+            }
+
+            let page_number = self.next_page_number;
+            if page_number > self.database.number_of_pages() {
+                return None;
+            }
+            self.next_page_number += 1;
+
+            if let Err(error) = self.carve_page(page_number) {
+                // Pages after one that cannot be read are not carved either.
+                self.next_page_number = u32::MAX;
+                return Some(Err(error));
+            }
+        }
+    }
+}
+
+/// Number of event keys that are written to or looked up in [`MainEventKeys`] at once.
+const KEY_BATCH_SIZE: usize = 4096;
+
+/// Keys of the events of the main database, kept in a temporary database on disk.
+struct MainEventKeys {
+    database: TemporaryDatabase,
+
+    /// Keys not yet written to `database`.
+    unsaved_key_digests: Vec<[u8; 32]>,
+}
+
+impl MainEventKeys {
+    async fn new() -> Result<Self> {
+        let database = TemporaryDatabase::new(
+            "main_events.db",
+            "CREATE TABLE main_events (key_digest BLOB PRIMARY KEY) WITHOUT ROWID",
+        )
+        .await?;
+
+        Ok(Self {
+            database,
+            unsaved_key_digests: Vec::with_capacity(KEY_BATCH_SIZE),
+        })
+    }
+
+    /// Remembers the key of an event, writing keys out in batches.
+    async fn insert(&mut self, key_digest: [u8; 32]) -> Result<()> {
+        self.unsaved_key_digests.push(key_digest);
+
+        if self.unsaved_key_digests.len() == KEY_BATCH_SIZE {
+            self.save().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn save(&mut self) -> Result<()> {
+        if self.unsaved_key_digests.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = QueryBuilder::new("INSERT OR IGNORE INTO main_events (key_digest) ");
+        query.push_values(
+            self.unsaved_key_digests.drain(..),
+            |mut row, key_digest| {
+                row.push_bind(key_digest.to_vec());
+            },
+        );
+
+        query
+            .build()
+            .execute(self.database.pool())
+            .await
+            .into_diagnostic()?;
+
+        Ok(())
+    }
+
+    /// Returns which of the given keys were inserted, with a single query.
+    ///
+    /// Keys that have not been written out yet are not found.
+    async fn find_all(&self, key_digests: &[[u8; 32]]) -> Result<HashSet<[u8; 32]>> {
+        // A batch of keys stays well below SQLite's limit of 32766 parameters.
+        let mut query =
+            QueryBuilder::new("SELECT key_digest FROM main_events WHERE key_digest IN (");
+        let mut separated = query.separated(", ");
+        for key_digest in key_digests {
+            separated.push_bind(key_digest.as_slice());
+        }
+        separated.push_unseparated(")");
+
+        let rows: Vec<(Vec<u8>,)> = query
+            .build_query_as()
+            .fetch_all(self.database.pool())
+            .await
+            .into_diagnostic()?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(key_digest,)| key_digest.try_into().ok())
+            .collect())
+    }
+}
+
+/// Recovered events waiting to be emitted after the main database has been read.
+///
+/// Records are only recovered and decoded as events are taken, one batch at a time.
+/// Recovered records that are identical to a row of the main database
+/// (e.g. WAL frames that have already been checkpointed) are dropped: the keys of
+/// the events of the main database are kept on disk instead of in memory,
+/// and looked up once per batch.
+pub(super) struct RecoveredEvents<'a> {
+    events: Peekable<Box<dyn Iterator<Item = PersistedEvent> + Send + 'a>>,
+    main_event_keys: MainEventKeys,
+
+    /// Events of the current batch that the main database does not contain.
+    next_events: std::vec::IntoIter<PersistedEvent>,
+}
+
+impl<'a> RecoveredEvents<'a> {
+    /// Prepares to emit the given recovered events, or returns `None` if there are none.
+    pub(super) async fn new(
+        events: impl Iterator<Item = PersistedEvent> + Send + 'a,
+    ) -> Result<Option<Self>> {
+        let mut events = (Box::new(events) as Box<dyn Iterator<Item = _> + Send + 'a>).peekable();
+        if events.peek().is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            events,
+            main_event_keys: MainEventKeys::new().await?,
+            next_events: Vec::new().into_iter(),
+        }))
+    }
+
+    /// Remembers an event of the main database, so that recovered copies of it are dropped.
+    pub(super) async fn observe_main_event(&mut self, event: &PersistedEvent) -> Result<()> {
+        self.main_event_keys.insert(event.key().digest()).await
+    }
+
+    /// Takes the next recovered event that the main database does not contain.
+    ///
+    /// Must only be called once every event of the main database has been observed.
+    pub(super) async fn next(&mut self) -> Result<Option<PersistedEvent>> {
+        loop {
+            if let Some(event) = self.next_events.next() {
+                return Ok(Some(event));
+            }
+
+            self.main_event_keys.save().await?;
+
+            let events = self
+                .events
+                .by_ref()
+                .take(KEY_BATCH_SIZE)
+                .collect::<Vec<_>>();
+            if events.is_empty() {
+                return Ok(None);
+            }
+
+            let key_digests = events
+                .iter()
+                .map(|event| event.key().digest())
+                .collect::<Vec<_>>();
+            let main_event_keys = self.main_event_keys.find_all(&key_digests).await?;
+
+            self.next_events = events
+                .into_iter()
+                .zip(key_digests)
+                .filter(|(_, key_digest)| !main_event_keys.contains(key_digest))
+                .map(|(event, _)| event)
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+    }
+
+    pub(super) async fn close(self) {
+        self.main_event_keys.database.close().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use serde_json::json;
+
+    use super::*;
+    use crate::sqlite::test_util::random_bytes;
+
+    const PAGE_SIZE: usize = 512;
+
+    /// A database of random pages behind a valid header, whose freelist starts at
+    /// `first_freelist_trunk_page`. Every other page looks like a table leaf page.
+    fn random_database(
+        seed: u64,
+        number_of_pages: usize,
+        first_freelist_trunk_page: u32,
+    ) -> Vec<u8> {
+        let mut bytes = random_bytes(seed, PAGE_SIZE * number_of_pages);
+
+        bytes[..16].copy_from_slice(b"SQLite format 3\0");
+        bytes[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+        bytes[20] = 0;
+        bytes[32..36].copy_from_slice(&first_freelist_trunk_page.to_be_bytes());
+        bytes[56..60].copy_from_slice(&1u32.to_be_bytes());
+
+        for page_index in (1..number_of_pages).step_by(2) {
+            bytes[page_index * PAGE_SIZE] = 0x0d;
+        }
+
+        bytes
+    }
+
+    fn events_persisted_layout() -> EventsPersistedLayout {
+        let column_names = [
+            "sid",
+            "timestamp",
+            "payload",
+            "full_event_name",
+            "full_event_name_hash",
+            "event_keyword",
+            "is_core",
+            "provider_group_id",
+            "logging_binary_name",
+            "friendly_logging_binary_name",
+            "compressed_payload_size",
+            "producer_id",
+        ]
+        .map(String::from);
+
+        EventsPersistedLayout::new(&column_names)
+    }
+
+    #[test]
+    fn carves_random_pages_without_panicking() {
+        let layout = Arc::new(events_persisted_layout());
+
+        for seed in 1..=32 {
+            // Trunk pointers: none, in range, out of range and pointing at the header page.
+            for first_freelist_trunk_page in [0, 2, 3, 1000, 1] {
+                let database = DatabaseFile::from_bytes(Arc::from(random_database(
+                    seed,
+                    16,
+                    first_freelist_trunk_page,
+                )))
+                .unwrap();
+
+                for record in
+                    CarvedRecords::new(Arc::new(database), layout.clone(), Arc::default()).unwrap()
+                {
+                    record.unwrap();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn recovers_events_lazily_and_drops_copies_of_main_events() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+        let event = |index: usize| {
+            PersistedEvent::for_tests(
+                "S-1-5-21-device",
+                "Microsoft.Windows.Test",
+                start + TimeDelta::seconds(index as i64),
+                json!({ "index": index }),
+            )
+        };
+
+        assert!(RecoveredEvents::new(std::iter::empty())
+            .await
+            .unwrap()
+            .is_none());
+
+        let number_of_events = 2 * KEY_BATCH_SIZE + 10;
+        let number_of_taken_events = Arc::new(AtomicUsize::new(0));
+        let mut recovered_events = RecoveredEvents::new((0..number_of_events).map(|index| {
+            number_of_taken_events.fetch_add(1, Ordering::Relaxed);
+            event(index)
+        }))
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(number_of_taken_events.load(Ordering::Relaxed), 1);
+
+        // Every third recovered event is also in the main database.
+        for index in (0..number_of_events).step_by(3) {
+            recovered_events
+                .observe_main_event(&event(index))
+                .await
+                .unwrap();
+        }
+
+        let mut indices = Vec::new();
+        while let Some(event) = recovered_events.next().await.unwrap() {
+            if indices.is_empty() {
+                assert_eq!(
+                    number_of_taken_events.load(Ordering::Relaxed),
+                    KEY_BATCH_SIZE
+                );
+            }
+            indices.push((*event.timestamp() - start).num_seconds() as usize);
+        }
+        recovered_events.close().await;
+
+        assert_eq!(
+            indices,
+            (0..number_of_events)
+                .filter(|index| index % 3 != 0)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,276 @@
+//! Detection of the `EventTranscript.db` schema version.
+//!
+//! Windows releases differ slightly in how the database is laid out. Instead of trying
+//! queries until one succeeds, the columns of every table are read via `PRAGMA table_info`
+//! and the schema is classified up front.
+//!
+//! | Version       | Windows builds     | Column with producer names   |
+//! |---------------|--------------------|------------------------------|
+//! | [`Windows10`] | 10240 to 19045     | `producers.producer_id_text` |
+//! | [`Windows11`] | 22000 and later    | `producers.producer_id_name` |
+//!
+//! The version is classified by the columns alone, not by the build in `osver.txt`.
+//!
+//! [`Windows10`]: SchemaVersion::Windows10
+//! [`Windows11`]: SchemaVersion::Windows11
+
+use std::{collections::HashMap, fmt};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+/// Columns every known schema version has, by table.
+const REQUIRED_COLUMNS: [(&str, &[&str]); 7] = [
+    (
+        "events_persisted",
+        &[
+            "sid",
+            "timestamp",
+            "payload",
+            "full_event_name",
+            "full_event_name_hash",
+            "is_core",
+            "provider_group_id",
+            "logging_binary_name",
+            "friendly_logging_binary_name",
+            "producer_id",
+        ],
+    ),
+    ("provider_groups", &["group_id", "group_guid"]),
+    ("producers", &["producer_id"]),
+    (
+        "event_categories",
+        &["full_event_name_hash", "category_id"],
+    ),
+    (
+        "categories",
+        &["category_id", "category_id_text", "producer_id"],
+    ),
+    ("event_tags", &["full_event_name_hash", "tag_id"]),
+    (
+        "tag_descriptions",
+        &["tag_id", "locale_name", "tag_name", "description"],
+    ),
+];
+
+/// Known layouts of `EventTranscript.db`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaVersion {
+    /// Windows 10 (builds 10240 to 19045): producer names are stored in
+    /// `producers.producer_id_text`.
+    Windows10,
+
+    /// Windows 11 (build 22000 and later): producer names are stored in
+    /// `producers.producer_id_name`.
+    Windows11,
+}
+
+impl SchemaVersion {
+    /// Name of the column of the `producers` table that holds the producer name.
+    pub fn producer_name_column(self) -> &'static str {
+        match self {
+            Self::Windows10 => "producer_id_text",
+            Self::Windows11 => "producer_id_name",
+        }
+    }
+
+    /// Windows builds that create databases with this layout.
+    pub fn windows_builds(self) -> &'static str {
+        match self {
+            Self::Windows10 => "Windows 10 (builds 10240 to 19045)",
+            Self::Windows11 => "Windows 11 (build 22000 and later)",
+        }
+    }
+
+    /// Query that selects all `events_persisted` rows,
+    /// joined with their provider group and producer.
+    pub fn events_query(self) -> String {
+        format!(
+            "SELECT sid, timestamp, payload, full_event_name, full_event_name_hash, is_core, \
+                provider_group_id, group_guid as provider_group_guid, logging_binary_name, \
+                friendly_logging_binary_name, p.producer_id as producer_id, \
+                {} as producer_id_name \
+            FROM events_persisted as e \
+            LEFT JOIN provider_groups as g \
+                ON e.provider_group_id = g.group_id \
+            LEFT JOIN producers as p \
+                ON e.producer_id = p.producer_id",
+            self.producer_name_column()
+        )
+    }
+
+    /// Query that selects all producers, with the name column aliased to `producer_id_name`.
+    pub fn producers_query(self) -> String {
+        format!(
+            "SELECT producer_id, {} as producer_id_name FROM producers",
+            self.producer_name_column()
+        )
+    }
+}
+
+impl fmt::Display for SchemaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Windows10 => write!(f, "Windows 10 (producers.producer_id_text)"),
+            Self::Windows11 => write!(f, "Windows 11 (producers.producer_id_name)"),
+        }
+    }
+}
+
+/// Detected schema of an `EventTranscript.db` database.
+#[derive(Debug, Clone)]
+pub struct DatabaseSchema {
+    version: SchemaVersion,
+
+    /// Columns of `events_persisted`, in the order they are stored in records.
+    events_persisted_columns: Vec<String>,
+}
+
+impl DatabaseSchema {
+    /// Reads the columns of all tables winspy uses and classifies the schema.
+    ///
+    /// Fails with a list of all missing columns if the schema is not a known one.
+    pub async fn detect(pool: &SqlitePool) -> Result<Self> {
+        let mut columns_by_table: HashMap<&str, Vec<String>> = HashMap::new();
+
+        for (table_name, _) in REQUIRED_COLUMNS {
+            columns_by_table.insert(
+                table_name,
+                load_table_columns(pool, table_name).await?,
+            );
+        }
+
+        let has_column = |table_name: &str, column_name: &str| {
+            columns_by_table
+                .get(table_name)
+                .is_some_and(|columns| columns.iter().any(|column| column == column_name))
+        };
+
+        let mut missing_columns = Vec::new();
+        for (table_name, column_names) in REQUIRED_COLUMNS {
+            for column_name in column_names {
+                if !has_column(table_name, column_name) {
+                    missing_columns.push(format!("{table_name}.{column_name}"));
+                }
+            }
+        }
+
+        let version = if has_column("producers", "producer_id_name") {
+            Some(SchemaVersion::Windows11)
+        } else if has_column("producers", "producer_id_text") {
+            Some(SchemaVersion::Windows10)
+        } else {
+            missing_columns
+                .push("producers.producer_id_name (or producers.producer_id_text)".to_string());
+            None
+        };
+
+        match version {
+            Some(version) if missing_columns.is_empty() => Ok(Self {
+                version,
+                events_persisted_columns: columns_by_table
+                    .remove("events_persisted")
+                    .unwrap_or_default(),
+            }),
+            _ => Err(miette!(
+                "Unknown EventTranscript.db schema, missing columns: {}.",
+                missing_columns.join(", ")
+            )),
+        }
+    }
+
+    pub fn version(&self) -> SchemaVersion {
+        self.version
+    }
+
+    pub fn events_persisted_columns(&self) -> &[String] {
+        &self.events_persisted_columns
+    }
+}
+
+/// Returns the names of all columns of `table_name`, in order (empty if there is no such table).
+async fn load_table_columns(pool: &SqlitePool, table_name: &str) -> Result<Vec<String>> {
+    let column_names: Vec<(String,)> =
+        sqlx::query_as("SELECT name FROM pragma_table_info(?1) ORDER BY cid")
+            .bind(table_name)
+            .fetch_all(pool)
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to load the columns of {table_name}."))?;
+
+    Ok(column_names.into_iter().map(|(name,)| name).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    /// An in-memory database with every required table, and `producers` with the given columns.
+    async fn database_with_producer_columns(producer_columns: &str) -> SqlitePool {
+        // In-memory databases are private to their connection.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        for (table_name, column_names) in REQUIRED_COLUMNS {
+            let column_names = if table_name == "producers" {
+                producer_columns.to_string()
+            } else {
+                column_names.join(", ")
+            };
+
+            sqlx::query(&format!(
+                "CREATE TABLE {table_name} ({column_names})"
+            ))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        pool
+    }
+
+    #[tokio::test]
+    async fn detects_schema_versions() {
+        for (producer_columns, expected_version) in [
+            (
+                "producer_id, producer_id_text",
+                SchemaVersion::Windows10,
+            ),
+            (
+                "producer_id, producer_id_name",
+                SchemaVersion::Windows11,
+            ),
+        ] {
+            let pool = database_with_producer_columns(producer_columns).await;
+            let schema = DatabaseSchema::detect(&pool).await.unwrap();
+
+            assert_eq!(schema.version(), expected_version);
+            assert_eq!(schema.events_persisted_columns()[0], "sid");
+            assert!(producer_columns.ends_with(expected_version.producer_name_column()));
+        }
+
+        assert_eq!(
+            SchemaVersion::Windows10.windows_builds(),
+            "Windows 10 (builds 10240 to 19045)"
+        );
+        assert_eq!(
+            SchemaVersion::Windows11.windows_builds(),
+            "Windows 11 (build 22000 and later)"
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_missing_columns() {
+        let pool = database_with_producer_columns("producer_id").await;
+        let error = DatabaseSchema::detect(&pool).await.unwrap_err();
+
+        assert!(error.to_string().contains("producers.producer_id_name"));
+    }
+}
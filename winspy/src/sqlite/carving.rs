@@ -0,0 +1,127 @@
+use std::ops::RangeInclusive;
+
+use super::{
+    database::TextEncoding,
+    record::{decode_record_body, RecordHeader, SqliteValue},
+};
+
+/// A record found by scanning raw bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CarvedRecord {
+    /// Offset of the record header within the scanned bytes.
+    pub offset: usize,
+
+    /// The raw record (header and body).
+    pub bytes: Vec<u8>,
+    pub values: Vec<SqliteValue>,
+}
+
+/// Scans `bytes` for complete records with the given number of columns
+/// that are accepted by `is_candidate`.
+///
+/// Cells whose header was overwritten (e.g. by a freeblock header) are found as long as
+/// the record header itself survived. Records that spilled onto overflow pages are not
+/// found, since only their beginning is stored locally.
+pub fn carve_records<F>(
+    bytes: &[u8],
+    encoding: TextEncoding,
+    number_of_columns: RangeInclusive<usize>,
+    is_candidate: F,
+) -> Vec<CarvedRecord>
+where
+    F: Fn(&[SqliteValue]) -> bool,
+{
+    let mut carved_records = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let carved_record = try_decode_record_at(&bytes[offset..], encoding, &number_of_columns)
+            .filter(|(values, _)| is_candidate(values));
+
+        match carved_record {
+            Some((values, length)) => {
+                carved_records.push(CarvedRecord {
+                    offset,
+                    bytes: bytes[offset..offset + length].to_vec(),
+                    values,
+                });
+                offset += length;
+            }
+            None => offset += 1,
+        }
+    }
+
+    carved_records
+}
+
+/// Attempts to decode a record starting at the first byte of `bytes`.
+fn try_decode_record_at(
+    bytes: &[u8],
+    encoding: TextEncoding,
+    number_of_columns: &RangeInclusive<usize>,
+) -> Option<(Vec<SqliteValue>, usize)> {
+    // Headers of the records we look for fit into a one-byte header size,
+    // which rules out most offsets cheaply.
+    let header_size = usize::from(*bytes.first()?);
+    if !(2..=0x7f).contains(&header_size) {
+        return None;
+    }
+
+    let header = RecordHeader::parse(bytes)?;
+    if !number_of_columns.contains(&header.serial_types.len()) {
+        return None;
+    }
+
+    let record_size = header.header_size.checked_add(header.body_size()?)?;
+    let body = bytes.get(header.header_size..record_size)?;
+
+    let values = decode_record_body(&header, body, encoding)?;
+
+    Some((values, record_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite::test_util::random_bytes;
+
+    #[test]
+    fn finds_record_between_garbage() {
+        // Header: size 3, a three-byte text and a one-byte integer.
+        let record = [3, 19, 1, b'a', b'b', b'c', 5];
+
+        let mut bytes = vec![0u8; 40];
+        bytes.extend(record);
+        bytes.extend([0u8; 40]);
+
+        let carved_records = carve_records(&bytes, TextEncoding::Utf8, 2..=2, |_| true);
+
+        assert_eq!(carved_records.len(), 1);
+        assert_eq!(carved_records[0].offset, 40);
+        assert_eq!(carved_records[0].bytes, record);
+        assert_eq!(
+            carved_records[0].values,
+            vec![
+                SqliteValue::Text("abc".to_string()),
+                SqliteValue::Integer(5)
+            ]
+        );
+    }
+
+    #[test]
+    fn carves_random_bytes_without_panicking() {
+        for seed in 1..=64 {
+            let bytes = random_bytes(seed, 4096);
+
+            for encoding in [
+                TextEncoding::Utf8,
+                TextEncoding::Utf16Le,
+                TextEncoding::Utf16Be,
+            ] {
+                for carved_record in carve_records(&bytes, encoding, 1..=16, |_| true) {
+                    assert!(carved_record.offset + carved_record.bytes.len() <= bytes.len());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    sync::Arc,
+};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+
+use super::page::PageLookup;
+
+/// Size of the database header at the start of the first page.
+pub const DATABASE_HEADER_SIZE: usize = 100;
+
+const DATABASE_HEADER_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// Encoding of all text values in a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// The parts of the 100-byte database header needed to read pages directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct DatabaseHeader {
+    pub page_size: u32,
+    pub reserved_bytes_per_page: u8,
+    pub database_size_in_pages: u32,
+    pub first_freelist_trunk_page: u32,
+    pub number_of_freelist_pages: u32,
+    pub text_encoding: TextEncoding,
+}
+
+impl DatabaseHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < DATABASE_HEADER_SIZE || !bytes.starts_with(DATABASE_HEADER_MAGIC) {
+            return Err(miette!(
+                "Not an SQLite database: header magic is missing."
+            ));
+        }
+
+        let read_u32 = |offset: usize| {
+            u32::from_be_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+
+        // The value 1 stands for a page size of 65536, which does not fit into two bytes.
+        let page_size = match u16::from_be_bytes([bytes[16], bytes[17]]) {
+            1 => 65536,
+            page_size => u32::from(page_size),
+        };
+
+        if !page_size.is_power_of_two() || page_size < 512 {
+            return Err(miette!(
+                "Invalid database page size: {}.",
+                page_size
+            ));
+        }
+
+        let text_encoding = match read_u32(56) {
+            1 => TextEncoding::Utf8,
+            2 => TextEncoding::Utf16Le,
+            3 => TextEncoding::Utf16Be,
+            encoding => {
+                return Err(miette!(
+                    "Invalid database text encoding: {}.",
+                    encoding
+                ))
+            }
+        };
+
+        Ok(Self {
+            page_size,
+            reserved_bytes_per_page: bytes[20],
+            database_size_in_pages: read_u32(28),
+            first_freelist_trunk_page: read_u32(32),
+            number_of_freelist_pages: read_u32(36),
+            text_encoding,
+        })
+    }
+
+    /// Number of bytes of each page that SQLite uses for its own content.
+    pub fn usable_page_size(&self) -> usize {
+        self.page_size as usize - usize::from(self.reserved_bytes_per_page)
+    }
+}
+
+/// Where the bytes of a database come from.
+enum DatabaseStorage {
+    File(File),
+
+    /// A database that was read into memory (e.g. extracted from a disk image).
+    Memory(Arc<[u8]>),
+}
+
+/// Read-only access to the pages of a database file.
+pub struct DatabaseFile {
+    storage: DatabaseStorage,
+    header: DatabaseHeader,
+    number_of_pages: u32,
+}
+
+impl DatabaseFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open database file {}.", path.display()))?;
+
+        let mut header_bytes = [0u8; DATABASE_HEADER_SIZE];
+        file.read_exact(&mut header_bytes)
+            .into_diagnostic()
+            .wrap_err("Failed to read database header.")?;
+
+        let header = DatabaseHeader::parse(&header_bytes)?;
+
+        let file_size = file.metadata().into_diagnostic()?.len();
+        let number_of_pages = (file_size / u64::from(header.page_size)) as u32;
+
+        Ok(Self {
+            storage: DatabaseStorage::File(file),
+            header,
+            number_of_pages,
+        })
+    }
+
+    /// Reads pages from a database that is already in memory.
+    pub fn from_bytes(bytes: Arc<[u8]>) -> Result<Self> {
+        let header = DatabaseHeader::parse(&bytes)?;
+        let number_of_pages = (bytes.len() as u64 / u64::from(header.page_size)) as u32;
+
+        Ok(Self {
+            storage: DatabaseStorage::Memory(bytes),
+            header,
+            number_of_pages,
+        })
+    }
+
+    pub fn header(&self) -> &DatabaseHeader {
+        &self.header
+    }
+
+    /// Number of whole pages in the file (which may differ from the header in damaged files).
+    pub fn number_of_pages(&self) -> u32 {
+        self.number_of_pages
+    }
+
+    /// Reads the page with the given (1-based) number.
+    pub fn read_page(&self, page_number: u32) -> Result<Vec<u8>> {
+        if page_number == 0 || page_number > self.number_of_pages {
+            return Err(miette!("Page {} is out of range.", page_number));
+        }
+
+        let page_size = u64::from(self.header.page_size);
+        let page_offset = u64::from(page_number - 1) * page_size;
+
+        match &self.storage {
+            DatabaseStorage::File(file) => {
+                let mut page = vec![0u8; page_size as usize];
+
+                let mut file = file;
+                file.seek(SeekFrom::Start(page_offset)).into_diagnostic()?;
+                file.read_exact(&mut page).into_diagnostic()?;
+
+                Ok(page)
+            }
+            DatabaseStorage::Memory(bytes) => {
+                let page_offset = page_offset as usize;
+                Ok(bytes[page_offset..page_offset + page_size as usize].to_vec())
+            }
+        }
+    }
+}
+
+impl PageLookup for DatabaseFile {
+    fn page(&self, page_number: u32) -> Option<Cow<'_, [u8]>> {
+        self.read_page(page_number).ok().map(Cow::Owned)
+    }
+
+    fn number_of_pages(&self) -> u32 {
+        self.number_of_pages
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeSet;
+
+use miette::Result;
+use tracing::warn;
+
+use super::database::DatabaseFile;
+
+/// Collects the numbers of all pages on the freelist, trunk pages included.
+///
+/// Freed pages keep their old content until they are reused,
+/// unless the database was configured with `secure_delete`.
+///
+/// A damaged freelist (a trunk page out of range or referenced twice) is followed
+/// only up to the damage, so the pages found before it can still be carved.
+pub fn freelist_pages(database: &DatabaseFile) -> Result<BTreeSet<u32>> {
+    let mut free_pages = BTreeSet::new();
+    let mut next_trunk_page = database.header().first_freelist_trunk_page;
+
+    while next_trunk_page != 0 {
+        if next_trunk_page > database.number_of_pages() {
+            warn!(
+                "Freelist trunk page {} is out of range, ignoring the rest of the freelist.",
+                next_trunk_page
+            );
+            break;
+        }
+
+        if !free_pages.insert(next_trunk_page) {
+            warn!(
+                "Freelist trunk page {} is referenced twice, ignoring the rest of the freelist.",
+                next_trunk_page
+            );
+            break;
+        }
+
+        let trunk_page = database.read_page(next_trunk_page)?;
+        let read_u32 = |offset: usize| {
+            trunk_page
+                .get(offset..offset.checked_add(4)?)
+                .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        // Leaf pointers that do not fit into the page end the list.
+        let number_of_leaves = read_u32(4).unwrap_or_default() as usize;
+        for leaf_index in 0..number_of_leaves {
+            let Some(leaf_page) = leaf_index
+                .checked_mul(4)
+                .and_then(|offset| offset.checked_add(8))
+                .and_then(read_u32)
+            else {
+                break;
+            };
+
+            if leaf_page != 0 && leaf_page <= database.number_of_pages() {
+                free_pages.insert(leaf_page);
+            }
+        }
+
+        next_trunk_page = read_u32(0).unwrap_or_default();
+    }
+
+    Ok(free_pages)
+}
@@ -0,0 +1,14 @@
+//! Minimal reader of the SQLite file format.
+//!
+//! SQLite only exposes the current state of a database, while deleted or superseded
+//! records can still be found in write-ahead log frames and free pages.
+//! Those are parsed here directly, following <https://www.sqlite.org/fileformat2.html>.
+
+pub mod carving;
+pub mod database;
+pub mod freelist;
+pub mod page;
+pub mod record;
+#[cfg(test)]
+pub mod test_util;
+pub mod wal;
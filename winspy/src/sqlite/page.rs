@@ -0,0 +1,421 @@
+use std::{borrow::Cow, collections::HashSet};
+
+use super::{database::DATABASE_HEADER_SIZE, record::read_varint};
+
+/// Source of page contents, used to follow overflow chains.
+pub trait PageLookup {
+    /// Returns the content of the page with the given (1-based) number, if available.
+    fn page(&self, page_number: u32) -> Option<Cow<'_, [u8]>>;
+
+    /// Number of pages that can be looked up, which bounds the size of any payload.
+    fn number_of_pages(&self) -> u32;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BTreePageType {
+    InteriorIndex,
+    InteriorTable,
+    LeafIndex,
+    LeafTable,
+}
+
+impl BTreePageType {
+    fn from_flag(flag: u8) -> Option<Self> {
+        match flag {
+            0x02 => Some(Self::InteriorIndex),
+            0x05 => Some(Self::InteriorTable),
+            0x0a => Some(Self::LeafIndex),
+            0x0d => Some(Self::LeafTable),
+            _ => None,
+        }
+    }
+
+    fn header_size(self) -> usize {
+        match self {
+            Self::InteriorIndex | Self::InteriorTable => 12,
+            Self::LeafIndex | Self::LeafTable => 8,
+        }
+    }
+}
+
+/// A cell of a table b-tree leaf page, i.e. one row of a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableLeafCell {
+    /// Offset of the cell within its page.
+    pub cell_offset: usize,
+    pub rowid: i64,
+
+    /// The complete record, including the parts stored on overflow pages.
+    pub payload: Vec<u8>,
+}
+
+/// Number of payload bytes stored directly on a table leaf page
+/// (the rest is stored on overflow pages).
+pub fn table_leaf_local_payload_size(payload_size: usize, usable_page_size: usize) -> usize {
+    let max_local = usable_page_size - 35;
+
+    if payload_size <= max_local {
+        return payload_size;
+    }
+
+    let min_local = ((usable_page_size - 12) * 32 / 255) - 23;
+    let local = min_local + ((payload_size - min_local) % (usable_page_size - 4));
+
+    if local <= max_local {
+        local
+    } else {
+        min_local
+    }
+}
+
+/// Largest payload that could be stored in `pages`: anything larger comes from a damaged
+/// or unrelated cell (cells of WAL frames and freed pages are not trustworthy).
+fn max_payload_size(usable_page_size: usize, pages: &dyn PageLookup) -> Option<usize> {
+    usize::try_from(pages.number_of_pages())
+        .ok()?
+        .checked_mul(usable_page_size)
+}
+
+/// Reads `remaining_size` bytes of payload from the overflow chain starting at `first_page`.
+///
+/// Returns `None` if any page of the chain is unavailable, if the chain visits a page twice
+/// or if `remaining_size` is larger than all pages together.
+pub fn read_overflow_chain(
+    first_page: u32,
+    mut remaining_size: usize,
+    usable_page_size: usize,
+    pages: &dyn PageLookup,
+) -> Option<Vec<u8>> {
+    if remaining_size > max_payload_size(usable_page_size, pages)? {
+        return None;
+    }
+
+    // The buffer grows as pages are read, as the size comes from an untrusted cell.
+    let mut overflow_payload = Vec::new();
+    let mut visited_pages = HashSet::new();
+    let mut next_page = first_page;
+
+    while remaining_size > 0 {
+        if next_page == 0 || !visited_pages.insert(next_page) {
+            return None;
+        }
+
+        let page = pages.page(next_page)?;
+        let content = page.get(4..usable_page_size)?;
+        let chunk_size = remaining_size.min(content.len());
+
+        overflow_payload.extend_from_slice(&content[..chunk_size]);
+        remaining_size -= chunk_size;
+        next_page = u32::from_be_bytes([page[0], page[1], page[2], page[3]]);
+    }
+
+    Some(overflow_payload)
+}
+
+/// A b-tree page.
+pub struct BTreePage<'a> {
+    bytes: &'a [u8],
+    header_offset: usize,
+    page_type: BTreePageType,
+    number_of_cells: usize,
+    first_freeblock: usize,
+    cell_content_start: usize,
+}
+
+impl<'a> BTreePage<'a> {
+    /// Parses the b-tree page header of a page.
+    ///
+    /// Returns `None` if the page is not a b-tree page.
+    pub fn parse(bytes: &'a [u8], page_number: u32) -> Option<Self> {
+        // The first page starts with the database header.
+        let header_offset = if page_number == 1 {
+            DATABASE_HEADER_SIZE
+        } else {
+            0
+        };
+
+        let header = bytes.get(header_offset..header_offset + 8)?;
+        let page_type = BTreePageType::from_flag(header[0])?;
+
+        Some(Self {
+            bytes,
+            header_offset,
+            page_type,
+            first_freeblock: usize::from(u16::from_be_bytes([header[1], header[2]])),
+            number_of_cells: usize::from(u16::from_be_bytes([header[3], header[4]])),
+            // Zero stands for 65536, which does not fit into two bytes.
+            cell_content_start: match u16::from_be_bytes([header[5], header[6]]) {
+                0 => 65536,
+                start => usize::from(start),
+            },
+        })
+    }
+
+    pub fn page_type(&self) -> BTreePageType {
+        self.page_type
+    }
+
+    fn cell_offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        let pointer_array_start = self.header_offset + self.page_type.header_size();
+
+        (0..self.number_of_cells).filter_map(move |cell_index| {
+            let pointer_offset = pointer_array_start + cell_index * 2;
+            let pointer = self.bytes.get(pointer_offset..pointer_offset + 2)?;
+
+            Some(usize::from(u16::from_be_bytes([
+                pointer[0], pointer[1],
+            ])))
+        })
+    }
+
+    /// Parses all cells of a table leaf page, following overflow chains through `pages`.
+    ///
+    /// Cells that are damaged or whose overflow pages are not available are skipped.
+    pub fn table_leaf_cells(
+        &self,
+        usable_page_size: usize,
+        pages: &dyn PageLookup,
+    ) -> Vec<TableLeafCell> {
+        if self.page_type != BTreePageType::LeafTable {
+            return Vec::new();
+        }
+
+        self.cell_offsets()
+            .filter_map(|cell_offset| {
+                parse_table_leaf_cell(self.bytes, cell_offset, usable_page_size, pages)
+            })
+            .collect()
+    }
+
+    /// Byte ranges of all freeblocks (unallocated space between cells) on this page.
+    pub fn freeblocks(&self) -> Vec<std::ops::Range<usize>> {
+        let mut freeblocks = Vec::new();
+        let mut next_freeblock = self.first_freeblock;
+
+        // Freeblocks are at least four bytes long, which bounds their number.
+        while next_freeblock != 0 && freeblocks.len() < self.bytes.len() / 4 {
+            let Some(freeblock_header) = self.bytes.get(next_freeblock..next_freeblock + 4) else {
+                break;
+            };
+
+            let size = usize::from(u16::from_be_bytes([
+                freeblock_header[2],
+                freeblock_header[3],
+            ]));
+
+            if size < 4 || next_freeblock + size > self.bytes.len() {
+                break;
+            }
+
+            freeblocks.push(next_freeblock..next_freeblock + size);
+
+            let following_freeblock = usize::from(u16::from_be_bytes([
+                freeblock_header[0],
+                freeblock_header[1],
+            ]));
+
+            // Freeblocks are kept in increasing order, anything else means a damaged page.
+            if following_freeblock != 0 && following_freeblock <= next_freeblock {
+                break;
+            }
+
+            next_freeblock = following_freeblock;
+        }
+
+        freeblocks
+    }
+
+    /// Byte range between the cell pointer array and the cell content area,
+    /// which holds no live data but may still contain old cells.
+    pub fn unallocated_space(&self) -> std::ops::Range<usize> {
+        let pointer_array_end =
+            self.header_offset + self.page_type.header_size() + self.number_of_cells * 2;
+        let cell_content_start = self.cell_content_start.min(self.bytes.len());
+
+        pointer_array_end.min(cell_content_start)..cell_content_start
+    }
+}
+
+/// Parses the table leaf cell at `cell_offset`.
+pub fn parse_table_leaf_cell(
+    page: &[u8],
+    cell_offset: usize,
+    usable_page_size: usize,
+    pages: &dyn PageLookup,
+) -> Option<TableLeafCell> {
+    let (payload_size, payload_size_length) = read_varint(page.get(cell_offset..)?)?;
+    let payload_size = usize::try_from(payload_size).ok()?;
+    if payload_size > max_payload_size(usable_page_size, pages)? {
+        return None;
+    }
+
+    let rowid_offset = cell_offset.checked_add(payload_size_length)?;
+    let (rowid, rowid_length) = read_varint(page.get(rowid_offset..)?)?;
+
+    let payload_offset = rowid_offset.checked_add(rowid_length)?;
+    let local_payload_size = table_leaf_local_payload_size(payload_size, usable_page_size);
+    let local_payload = page.get(payload_offset..payload_offset.checked_add(local_payload_size)?)?;
+
+    let mut payload = local_payload.to_vec();
+
+    if local_payload_size < payload_size {
+        let pointer_offset = payload_offset + local_payload_size;
+        let pointer = page.get(pointer_offset..pointer_offset.checked_add(4)?)?;
+        let first_overflow_page =
+            u32::from_be_bytes([pointer[0], pointer[1], pointer[2], pointer[3]]);
+
+        payload.extend(read_overflow_chain(
+            first_overflow_page,
+            payload_size - local_payload_size,
+            usable_page_size,
+            pages,
+        )?);
+    }
+
+    Some(TableLeafCell {
+        cell_offset,
+        rowid: rowid as i64,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USABLE_PAGE_SIZE: usize = 512;
+
+    /// Pages in memory, numbered from 1.
+    struct TestPages(Vec<Vec<u8>>);
+
+    impl PageLookup for TestPages {
+        fn page(&self, page_number: u32) -> Option<Cow<'_, [u8]>> {
+            let page = self
+                .0
+                .get(usize::try_from(page_number).ok()?.checked_sub(1)?)?;
+            Some(Cow::Borrowed(page))
+        }
+
+        fn number_of_pages(&self) -> u32 {
+            self.0.len() as u32
+        }
+    }
+
+    fn overflow_page(next_page: u32, fill: u8) -> Vec<u8> {
+        let mut page = next_page.to_be_bytes().to_vec();
+        page.resize(USABLE_PAGE_SIZE, fill);
+        page
+    }
+
+    /// A cell with a two-byte payload size, rowid 1, `local_size` local bytes
+    /// and a pointer to `first_overflow_page`.
+    fn cell(payload_size: u16, local_size: usize, first_overflow_page: u32) -> Vec<u8> {
+        let mut cell = vec![
+            0x80 | (payload_size >> 7) as u8,
+            (payload_size & 0x7f) as u8,
+            1,
+        ];
+        cell.extend(std::iter::repeat_n(0xaa, local_size));
+        cell.extend(first_overflow_page.to_be_bytes());
+        cell
+    }
+
+    #[test]
+    fn local_payload_size_matches_sqlite() {
+        assert_eq!(
+            table_leaf_local_payload_size(100, USABLE_PAGE_SIZE),
+            100
+        );
+        assert_eq!(
+            table_leaf_local_payload_size(477, USABLE_PAGE_SIZE),
+            477
+        );
+        assert_eq!(
+            table_leaf_local_payload_size(1000, USABLE_PAGE_SIZE),
+            39
+        );
+    }
+
+    #[test]
+    fn follows_overflow_chain() {
+        let pages = TestPages(vec![
+            vec![0; USABLE_PAGE_SIZE],
+            overflow_page(3, 0xbb),
+            overflow_page(0, 0xcc),
+        ]);
+
+        let cell = parse_table_leaf_cell(&cell(1000, 39, 2), 0, USABLE_PAGE_SIZE, &pages).unwrap();
+
+        assert_eq!(cell.rowid, 1);
+        assert_eq!(cell.payload.len(), 1000);
+        assert!(cell.payload[..39].iter().all(|&byte| byte == 0xaa));
+        assert!(cell.payload[39..39 + 508].iter().all(|&byte| byte == 0xbb));
+        assert!(cell.payload[39 + 508..].iter().all(|&byte| byte == 0xcc));
+    }
+
+    #[test]
+    fn rejects_payload_larger_than_database() {
+        let pages = TestPages(vec![vec![0; USABLE_PAGE_SIZE]; 2]);
+
+        // A nine-byte varint of all ones claims a payload of 2^64 - 1 bytes.
+        let mut hostile_cell = vec![0xff; 9];
+        hostile_cell.extend([1, 0, 0, 0, 2]);
+        assert_eq!(
+            parse_table_leaf_cell(&hostile_cell, 0, USABLE_PAGE_SIZE, &pages),
+            None
+        );
+
+        assert_eq!(
+            read_overflow_chain(2, usize::MAX, USABLE_PAGE_SIZE, &pages),
+            None
+        );
+    }
+
+    #[test]
+    fn stops_at_cyclic_overflow_chain() {
+        let pages = TestPages(vec![
+            vec![0; USABLE_PAGE_SIZE],
+            overflow_page(3, 0xbb),
+            overflow_page(2, 0xcc),
+            vec![0; USABLE_PAGE_SIZE],
+        ]);
+
+        // 1505 bytes keep 39 locally and need three overflow pages, so the chain
+        // comes back to page 2 before the payload is complete.
+        assert_eq!(
+            table_leaf_local_payload_size(1505, USABLE_PAGE_SIZE),
+            39
+        );
+        assert_eq!(
+            parse_table_leaf_cell(&cell(1505, 39, 2), 0, USABLE_PAGE_SIZE, &pages),
+            None
+        );
+
+        let acyclic_pages = TestPages(vec![
+            vec![0; USABLE_PAGE_SIZE],
+            overflow_page(3, 0xbb),
+            overflow_page(4, 0xcc),
+            overflow_page(0, 0xdd),
+        ]);
+        assert!(parse_table_leaf_cell(
+            &cell(1505, 39, 2),
+            0,
+            USABLE_PAGE_SIZE,
+            &acyclic_pages
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn rejects_truncated_cells() {
+        let pages = TestPages(vec![vec![0; USABLE_PAGE_SIZE]]);
+        let cell = cell(1000, 39, 2);
+
+        for length in 0..cell.len() {
+            assert_eq!(
+                parse_table_leaf_cell(&cell[..length], 0, USABLE_PAGE_SIZE, &pages),
+                None
+            );
+        }
+    }
+}
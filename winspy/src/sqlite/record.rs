@@ -0,0 +1,261 @@
+use super::database::TextEncoding;
+
+/// A single value of a record, as stored in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqliteValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl SqliteValue {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn into_text(self) -> Option<String> {
+        match self {
+            Self::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Self::Null)
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, Self::Text(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Self::Integer(_))
+    }
+}
+
+/// Reads a big-endian variable-length integer.
+///
+/// Returns the value and the number of bytes it took up, or `None` if `bytes` is too short.
+pub fn read_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+
+    for (index, byte) in bytes.iter().take(9).enumerate() {
+        if index == 8 {
+            // The ninth byte contributes all eight of its bits.
+            return Some(((value << 8) | u64::from(*byte), 9));
+        }
+
+        value = (value << 7) | u64::from(byte & 0x7f);
+
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+
+    None
+}
+
+/// Size of the value of the given serial type in the record body.
+///
+/// Returns `None` for the reserved serial types 10 and 11.
+pub fn serial_type_size(serial_type: u64) -> Option<usize> {
+    match serial_type {
+        0 | 8 | 9 => Some(0),
+        1 => Some(1),
+        2 => Some(2),
+        3 => Some(3),
+        4 => Some(4),
+        5 => Some(6),
+        6 | 7 => Some(8),
+        10 | 11 => None,
+        _ => Some(((serial_type - 12) / 2) as usize),
+    }
+}
+
+/// Header of a record: the serial type of each column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordHeader {
+    pub header_size: usize,
+    pub serial_types: Vec<u64>,
+}
+
+impl RecordHeader {
+    /// Parses the record header at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (header_size, mut offset) = read_varint(bytes)?;
+        let header_size = usize::try_from(header_size).ok()?;
+
+        if header_size < offset || header_size > bytes.len() {
+            return None;
+        }
+
+        let mut serial_types = Vec::new();
+        while offset < header_size {
+            let (serial_type, varint_length) = read_varint(&bytes[offset..header_size])?;
+            serial_types.push(serial_type);
+            offset += varint_length;
+        }
+
+        if offset != header_size {
+            return None;
+        }
+
+        Some(Self {
+            header_size,
+            serial_types,
+        })
+    }
+
+    /// Total size of the record body described by this header.
+    pub fn body_size(&self) -> Option<usize> {
+        self.serial_types
+            .iter()
+            .try_fold(0usize, |total, serial_type| {
+                total.checked_add(serial_type_size(*serial_type)?)
+            })
+    }
+}
+
+fn decode_text(bytes: &[u8], encoding: TextEncoding) -> Option<String> {
+    match encoding {
+        TextEncoding::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+            if !bytes.len().is_multiple_of(2) {
+                return None;
+            }
+
+            let code_units = bytes.chunks_exact(2).map(|pair| match encoding {
+                TextEncoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+
+            char::decode_utf16(code_units)
+                .collect::<Result<String, _>>()
+                .ok()
+        }
+    }
+}
+
+fn decode_value(serial_type: u64, bytes: &[u8], encoding: TextEncoding) -> Option<SqliteValue> {
+    let read_signed = |bytes: &[u8]| {
+        // Sign-extend the big-endian two's complement integer to 64 bits.
+        let fill = if bytes.first().is_some_and(|byte| byte & 0x80 != 0) {
+            0xff
+        } else {
+            0x00
+        };
+
+        let mut buffer = [fill; 8];
+        buffer[8 - bytes.len()..].copy_from_slice(bytes);
+        i64::from_be_bytes(buffer)
+    };
+
+    let value = match serial_type {
+        0 => SqliteValue::Null,
+        1..=6 => SqliteValue::Integer(read_signed(bytes)),
+        7 => SqliteValue::Real(f64::from_be_bytes(bytes.try_into().ok()?)),
+        8 => SqliteValue::Integer(0),
+        9 => SqliteValue::Integer(1),
+        10 | 11 => return None,
+        serial_type if serial_type % 2 == 0 => SqliteValue::Blob(bytes.to_vec()),
+        _ => SqliteValue::Text(decode_text(bytes, encoding)?),
+    };
+
+    Some(value)
+}
+
+/// Decodes an entire record (header and body).
+///
+/// Returns `None` if the record is malformed or truncated.
+pub fn decode_record(bytes: &[u8], encoding: TextEncoding) -> Option<Vec<SqliteValue>> {
+    let header = RecordHeader::parse(bytes)?;
+    decode_record_body(&header, &bytes[header.header_size..], encoding)
+}
+
+/// Decodes the body of a record whose header has already been parsed.
+pub fn decode_record_body(
+    header: &RecordHeader,
+    body: &[u8],
+    encoding: TextEncoding,
+) -> Option<Vec<SqliteValue>> {
+    let mut values = Vec::with_capacity(header.serial_types.len());
+    let mut offset: usize = 0;
+
+    for serial_type in &header.serial_types {
+        let size = serial_type_size(*serial_type)?;
+        let value_bytes = body.get(offset..offset.checked_add(size)?)?;
+
+        values.push(decode_value(*serial_type, value_bytes, encoding)?);
+        offset += size;
+    }
+
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_varints() {
+        assert_eq!(read_varint(&[0x05]), Some((5, 1)));
+        assert_eq!(read_varint(&[0x81, 0x00]), Some((128, 2)));
+        assert_eq!(read_varint(&[0xff; 9]), Some((u64::MAX, 9)));
+        assert_eq!(read_varint(&[0x81]), None);
+        assert_eq!(read_varint(&[]), None);
+    }
+
+    #[test]
+    fn decodes_record() {
+        // Header: size 6, NULL, 2-byte integer, float, 0, 3-byte text.
+        let mut record = vec![6, 0, 2, 7, 8, 19];
+        record.extend((-2i16).to_be_bytes());
+        record.extend(1.5f64.to_be_bytes());
+        record.extend(b"abc");
+
+        assert_eq!(
+            decode_record(&record, TextEncoding::Utf8),
+            Some(vec![
+                SqliteValue::Null,
+                SqliteValue::Integer(-2),
+                SqliteValue::Real(1.5),
+                SqliteValue::Integer(0),
+                SqliteValue::Text("abc".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_utf16_text() {
+        let record = [2, 21, 0x00, b'h', 0x00, b'i'];
+
+        assert_eq!(
+            decode_record(&record, TextEncoding::Utf16Be),
+            Some(vec![SqliteValue::Text("hi".to_string())])
+        );
+        assert_eq!(
+            decode_record(&record[..5], TextEncoding::Utf16Be),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        // Header size larger than the record.
+        assert_eq!(RecordHeader::parse(&[10, 1]), None);
+
+        // Reserved serial type.
+        assert_eq!(decode_record(&[2, 10], TextEncoding::Utf8), None);
+
+        // Huge text that the body does not hold.
+        assert_eq!(
+            decode_record(&[3, 0xff, 0x7f, b'a'], TextEncoding::Utf8),
+            None
+        );
+    }
+}
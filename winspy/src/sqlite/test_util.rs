@@ -0,0 +1,14 @@
+//! Helpers shared by the tests of the parsers.
+
+/// Deterministic pseudo-random bytes (xorshift64).
+pub fn random_bytes(seed: u64, length: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
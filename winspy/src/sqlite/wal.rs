@@ -0,0 +1,365 @@
+use std::{borrow::Cow, fs, path::Path};
+
+use miette::{miette, Context, IntoDiagnostic, Result};
+
+use super::{database::DatabaseFile, page::PageLookup};
+
+const WAL_HEADER_SIZE: usize = 32;
+const WAL_FRAME_HEADER_SIZE: usize = 24;
+
+/// Magic number of a WAL with little-endian checksums (the last bit selects big-endian).
+const WAL_MAGIC: u32 = 0x377f0682;
+
+/// State of a frame in the write-ahead log.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize
+)]
+#[serde(rename_all = "snake_case")]
+pub enum WalFrameState {
+    /// Part of a committed transaction: SQLite would see this page (until it is checkpointed).
+    Committed,
+
+    /// Valid, but written after the last commit frame (e.g. a transaction in progress).
+    Uncommitted,
+
+    /// Salt or checksum mismatch, usually a leftover of an earlier WAL generation
+    /// that was overwritten only partially. SQLite ignores such frames.
+    Invalid,
+}
+
+/// A single frame (a page image) of the write-ahead log.
+#[derive(Debug, Clone)]
+pub struct WalFrame {
+    /// 1-based position of the frame in the WAL file.
+    pub frame_number: u32,
+    pub page_number: u32,
+    pub state: WalFrameState,
+    pub page: Vec<u8>,
+}
+
+/// A parsed SQLite write-ahead log (`-wal`) file.
+#[derive(Debug, Clone)]
+pub struct WriteAheadLog {
+    pub page_size: u32,
+    pub frames: Vec<WalFrame>,
+}
+
+/// Continues the WAL checksum over `bytes`, which are read as 32-bit words.
+fn wal_checksum(bytes: &[u8], big_endian: bool, mut checksum: (u32, u32)) -> (u32, u32) {
+    let read_word = |word: &[u8]| {
+        let word = [word[0], word[1], word[2], word[3]];
+        if big_endian {
+            u32::from_be_bytes(word)
+        } else {
+            u32::from_le_bytes(word)
+        }
+    };
+
+    for pair in bytes.chunks_exact(8) {
+        checksum.0 = checksum
+            .0
+            .wrapping_add(read_word(&pair[0..4]))
+            .wrapping_add(checksum.1);
+        checksum.1 = checksum
+            .1
+            .wrapping_add(read_word(&pair[4..8]))
+            .wrapping_add(checksum.0);
+    }
+
+    checksum
+}
+
+impl WriteAheadLog {
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).into_diagnostic().wrap_err_with(|| {
+            format!(
+                "Failed to read write-ahead log {}.",
+                path.display()
+            )
+        })?;
+
+        Self::parse(&bytes)
+    }
+
+    /// Parses a write-ahead log, including frames SQLite itself would ignore.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let header = bytes
+            .get(..WAL_HEADER_SIZE)
+            .ok_or_else(|| miette!("Write-ahead log is shorter than its header."))?;
+
+        let read_u32 = |bytes: &[u8], offset: usize| {
+            u32::from_be_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+
+        let magic = read_u32(header, 0);
+        if magic & !1 != WAL_MAGIC {
+            return Err(miette!(
+                "Not a write-ahead log: invalid magic {:#x}.",
+                magic
+            ));
+        }
+        let big_endian_checksums = magic & 1 == 1;
+
+        let page_size = read_u32(header, 8);
+        if !page_size.is_power_of_two() || page_size < 512 {
+            return Err(miette!(
+                "Invalid write-ahead log page size: {}.",
+                page_size
+            ));
+        }
+
+        let salt = (read_u32(header, 16), read_u32(header, 20));
+
+        let header_checksum = wal_checksum(&header[..24], big_endian_checksums, (0, 0));
+        let mut checksum_chain_intact =
+            header_checksum == (read_u32(header, 24), read_u32(header, 28));
+        let mut running_checksum = header_checksum;
+
+        let frame_size = WAL_FRAME_HEADER_SIZE + page_size as usize;
+        let mut frames = Vec::new();
+        let mut last_commit_index = None;
+
+        for (frame_index, frame) in bytes[WAL_HEADER_SIZE..]
+            .chunks_exact(frame_size)
+            .enumerate()
+        {
+            let frame_header = &frame[..WAL_FRAME_HEADER_SIZE];
+            let page = &frame[WAL_FRAME_HEADER_SIZE..];
+
+            let page_number = read_u32(frame_header, 0);
+            let commit_size = read_u32(frame_header, 4);
+            let frame_salt = (
+                read_u32(frame_header, 8),
+                read_u32(frame_header, 12),
+            );
+
+            // A frame is only valid if every frame before it is valid too.
+            if checksum_chain_intact {
+                let checksum = wal_checksum(
+                    &frame_header[..8],
+                    big_endian_checksums,
+                    running_checksum,
+                );
+                let checksum = wal_checksum(page, big_endian_checksums, checksum);
+
+                checksum_chain_intact = frame_salt == salt
+                    && checksum
+                        == (
+                            read_u32(frame_header, 16),
+                            read_u32(frame_header, 20),
+                        );
+                running_checksum = checksum;
+            }
+
+            let state = if checksum_chain_intact {
+                if commit_size != 0 {
+                    last_commit_index = Some(frame_index);
+                }
+
+                WalFrameState::Uncommitted
+            } else {
+                WalFrameState::Invalid
+            };
+
+            frames.push(WalFrame {
+                frame_number: frame_index as u32 + 1,
+                page_number,
+                state,
+                page: page.to_vec(),
+            });
+        }
+
+        if let Some(last_commit_index) = last_commit_index {
+            for frame in frames.iter_mut().take(last_commit_index + 1) {
+                frame.state = WalFrameState::Committed;
+            }
+        }
+
+        Ok(Self { page_size, frames })
+    }
+
+    /// Page lookup as of the given frame: the newest version of each page written
+    /// at or before `frame_number`, falling back to the database file.
+    ///
+    /// Invalid frames are skipped: they may be left over from an earlier generation of
+    /// the log, so their pages need not belong to the same version of the database.
+    pub fn page_lookup_at<'a>(
+        &'a self,
+        frame_number: u32,
+        database: &'a DatabaseFile,
+    ) -> WalPageLookup<'a> {
+        WalPageLookup {
+            wal: self,
+            frame_number,
+            database,
+        }
+    }
+}
+
+/// See [`WriteAheadLog::page_lookup_at`].
+pub struct WalPageLookup<'a> {
+    wal: &'a WriteAheadLog,
+    frame_number: u32,
+    database: &'a DatabaseFile,
+}
+
+impl PageLookup for WalPageLookup<'_> {
+    fn page(&self, page_number: u32) -> Option<Cow<'_, [u8]>> {
+        let newest_frame = self
+            .wal
+            .frames
+            .iter()
+            .take(self.frame_number as usize)
+            .rev()
+            .find(|frame| frame.page_number == page_number && frame.state != WalFrameState::Invalid);
+
+        match newest_frame {
+            Some(frame) => Some(Cow::Borrowed(&frame.page)),
+            None => self.database.page(page_number),
+        }
+    }
+
+    /// Pages of the database file, or beyond it if valid frames extended the database.
+    fn number_of_pages(&self) -> u32 {
+        self.wal
+            .frames
+            .iter()
+            .take(self.frame_number as usize)
+            .filter(|frame| frame.state != WalFrameState::Invalid)
+            .map(|frame| frame.page_number)
+            .fold(self.database.number_of_pages(), u32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: u32 = 512;
+    const SALT: (u32, u32) = (0x1234_5678, 0x9abc_def0);
+
+    /// Builds a WAL with little-endian checksums. Frames are given as
+    /// (page number, commit size, salt), with pages filled with the frame number.
+    fn build_wal(frames: &[(u32, u32, (u32, u32))]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for word in [WAL_MAGIC, 3_007_000, PAGE_SIZE, 0, SALT.0, SALT.1] {
+            bytes.extend(word.to_be_bytes());
+        }
+        let mut checksum = wal_checksum(&bytes, false, (0, 0));
+        bytes.extend(checksum.0.to_be_bytes());
+        bytes.extend(checksum.1.to_be_bytes());
+
+        for (frame_index, &(page_number, commit_size, salt)) in frames.iter().enumerate() {
+            let mut frame_header = Vec::new();
+            for word in [page_number, commit_size, salt.0, salt.1] {
+                frame_header.extend(word.to_be_bytes());
+            }
+            let page = vec![frame_index as u8 + 1; PAGE_SIZE as usize];
+
+            checksum = wal_checksum(&frame_header[..8], false, checksum);
+            checksum = wal_checksum(&page, false, checksum);
+            frame_header.extend(checksum.0.to_be_bytes());
+            frame_header.extend(checksum.1.to_be_bytes());
+
+            bytes.extend(frame_header);
+            bytes.extend(page);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn classifies_frames() {
+        let wal = WriteAheadLog::parse(&build_wal(&[
+            (2, 0, SALT),
+            (3, 3, SALT),
+            (2, 0, SALT),
+            (4, 4, (1, 1)),
+        ]))
+        .unwrap();
+
+        assert_eq!(wal.page_size, PAGE_SIZE);
+        assert_eq!(
+            wal.frames
+                .iter()
+                .map(|frame| (frame.frame_number, frame.page_number, frame.state))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, 2, WalFrameState::Committed),
+                (2, 3, WalFrameState::Committed),
+                (3, 2, WalFrameState::Uncommitted),
+                (4, 4, WalFrameState::Invalid),
+            ]
+        );
+        assert!(wal.frames[2].page.iter().all(|&byte| byte == 3));
+    }
+
+    #[test]
+    fn invalidates_frames_after_a_corrupted_one() {
+        let mut bytes = build_wal(&[(2, 1, SALT), (3, 2, SALT)]);
+
+        // Flip a byte in the page of the first frame.
+        bytes[WAL_HEADER_SIZE + WAL_FRAME_HEADER_SIZE] ^= 0xff;
+
+        let wal = WriteAheadLog::parse(&bytes).unwrap();
+        assert!(wal
+            .frames
+            .iter()
+            .all(|frame| frame.state == WalFrameState::Invalid));
+    }
+
+    #[test]
+    fn looks_up_pages_of_valid_frames_only() {
+        let mut database = vec![0xdd; 4 * PAGE_SIZE as usize];
+        database[..16].copy_from_slice(b"SQLite format 3\0");
+        database[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+        database[56..60].copy_from_slice(&1u32.to_be_bytes());
+        let database = DatabaseFile::from_bytes(database.into()).unwrap();
+
+        let wal = WriteAheadLog::parse(&build_wal(&[
+            (2, 0, SALT),
+            (3, 3, SALT),
+            // Left over from an earlier generation of the log.
+            (2, 0, (1, 1)),
+            (9, 9, (1, 1)),
+        ]))
+        .unwrap();
+
+        let page_lookup = wal.page_lookup_at(4, &database);
+        let first_byte_of_page = |page_number| page_lookup.page(page_number).map(|page| page[0]);
+
+        assert_eq!(first_byte_of_page(2), Some(1));
+        assert_eq!(first_byte_of_page(3), Some(2));
+        assert_eq!(first_byte_of_page(4), Some(0xdd));
+        assert_eq!(first_byte_of_page(9), None);
+        assert_eq!(page_lookup.number_of_pages(), 4);
+
+        // As of the first frame, the second one was not written yet.
+        let page_lookup = wal.page_lookup_at(1, &database);
+        assert_eq!(
+            page_lookup.page(3).map(|page| page[0]),
+            Some(0xdd)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert!(WriteAheadLog::parse(&[0; 16]).is_err());
+        assert!(WriteAheadLog::parse(&[0; 32]).is_err());
+
+        let mut bytes = build_wal(&[]);
+        bytes[8..12].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(WriteAheadLog::parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+//! SQLite databases in a temporary directory, for state that would otherwise
+//! have to be kept in memory and grow with the size of the evidence.
+
+use miette::{IntoDiagnostic, Result, WrapErr};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    SqlitePool,
+};
+use tempfile::TempDir;
+
+pub struct TemporaryDatabase {
+    pool: SqlitePool,
+
+    /// Holds the database file; removed when dropped.
+    _temporary_directory: TempDir,
+}
+
+impl TemporaryDatabase {
+    /// Creates the database `file_name` in a new temporary directory and runs `schema` in it.
+    pub async fn new(file_name: &str, schema: &str) -> Result<Self> {
+        let temporary_directory = tempfile::Builder::new()
+            .prefix("winspy-")
+            .tempdir()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create a temporary directory for {file_name}."))?;
+
+        // The database is thrown away at the end, so there is nothing to journal or sync.
+        let connect_options = SqliteConnectOptions::new()
+            .filename(temporary_directory.path().join(file_name))
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Off)
+            .synchronous(SqliteSynchronous::Off);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(connect_options)
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create the temporary database {file_name}."))?;
+
+        sqlx::query(schema).execute(&pool).await.into_diagnostic()?;
+
+        Ok(Self {
+            pool,
+            _temporary_directory: temporary_directory,
+        })
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    pub async fn close(self) {
+        self.pool.close().await;
+    }
+}
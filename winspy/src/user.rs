@@ -0,0 +1,258 @@
+//! Attribution of events to user accounts.
+//!
+//! The device SID only identifies the machine. Most payloads also carry the user the
+//! event was logged for in `ext.user.localId`, as `<prefix>:<id>` (e.g. `m:S-1-5-21-…`);
+//! a few events only name the user in their `data`.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{detectors::ProcessedEvent, models::persisted_event::PersistedEvent};
+
+/// Fields of `data` that hold the SID of the user, for events without `ext.user`.
+const USER_SID_DATA_FIELDS: [&str; 3] = ["UserSid", "UserSID", "userSid"];
+
+/// Prefix of Windows security identifiers.
+const SID_PREFIX: &str = "S-1-";
+
+/// Normalizes a user identifier: a SID is reported without the type prefix
+/// of `localId`, anything else as it is.
+fn normalize_user_id(raw_user_id: &str) -> String {
+    match raw_user_id.split_once(':') {
+        Some((_, sid))
+            if sid
+                .get(..SID_PREFIX.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(SID_PREFIX)) =>
+        {
+            sid.to_string()
+        }
+        _ => raw_user_id.to_string(),
+    }
+}
+
+/// The user the event was logged for, if it names one.
+///
+/// `ext.user.localId` is preferred, then `ext.user.id`, then a SID in `data`.
+pub fn user_of(event: &PersistedEvent) -> Option<String> {
+    let envelope = event.envelope();
+
+    // An empty field counts as missing, so the next one is tried.
+    let is_not_empty = |raw_user_id: &&str| !raw_user_id.is_empty();
+
+    let raw_user_id = envelope
+        .ext
+        .user
+        .local_id
+        .as_deref()
+        .filter(is_not_empty)
+        .or_else(|| envelope.ext.user.id.as_deref().filter(is_not_empty))
+        .or_else(|| {
+            USER_SID_DATA_FIELDS
+                .iter()
+                .find_map(|field| envelope.data.get(*field)?.as_str().filter(is_not_empty))
+        })?;
+
+    Some(normalize_user_id(raw_user_id))
+}
+
+/// Whether `user_id` (as returned by [`user_of`]) is the user given on the command line,
+/// which may be given with or without the `localId` prefix.
+pub fn is_same_user(user_id: &str, wanted_user_id: &str) -> bool {
+    user_id.eq_ignore_ascii_case(&normalize_user_id(wanted_user_id))
+}
+
+/// Activity of a single user.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserSummary {
+    pub user: String,
+    pub number_of_events: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+
+    /// Number of events each detector found among the events of the user.
+    pub detector_hits: BTreeMap<&'static str, u64>,
+}
+
+/// Activity of every user the events were attributed to.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UserReport {
+    pub users: Vec<UserSummary>,
+
+    /// Events that do not name a user.
+    pub number_of_unattributed_events: u64,
+}
+
+/// Collects the users of persisted events, and what was detected in their events.
+#[derive(Debug, Default)]
+pub struct UserObserver {
+    users: BTreeMap<String, UserSummary>,
+    number_of_unattributed_events: u64,
+}
+
+impl UserObserver {
+    pub fn observe(&mut self, event: &PersistedEvent, detected_events: &[ProcessedEvent]) {
+        let Some(user) = user_of(event) else {
+            self.number_of_unattributed_events += 1;
+            return;
+        };
+
+        let timestamp = *event.timestamp();
+        let summary = self
+            .users
+            .entry(user.clone())
+            .or_insert_with(|| UserSummary {
+                user,
+                number_of_events: 0,
+                first_seen: timestamp,
+                last_seen: timestamp,
+                detector_hits: BTreeMap::new(),
+            });
+
+        summary.number_of_events += 1;
+        summary.first_seen = summary.first_seen.min(timestamp);
+        summary.last_seen = summary.last_seen.max(timestamp);
+
+        for detected_event in detected_events {
+            *summary
+                .detector_hits
+                .entry(detected_event.detected_event.detector_name())
+                .or_default() += 1;
+        }
+    }
+
+    pub fn into_report(self) -> UserReport {
+        UserReport {
+            users: self.users.into_values().collect(),
+            number_of_unattributed_events: self.number_of_unattributed_events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::detectors::DetectedEvent;
+
+    fn event(seconds: i64, payload: Value) -> PersistedEvent {
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+        PersistedEvent::for_tests(
+            "S-1-5-21-device",
+            "Microsoft.Windows.Test",
+            start + TimeDelta::seconds(seconds),
+            payload,
+        )
+    }
+
+    #[test]
+    fn normalizes_user_ids() {
+        assert_eq!(normalize_user_id("m:S-1-5-21-1"), "S-1-5-21-1");
+        assert_eq!(normalize_user_id("m:s-1-5-18"), "s-1-5-18");
+        assert_eq!(normalize_user_id("S-1-5-18"), "S-1-5-18");
+        assert_eq!(normalize_user_id("c:1234"), "c:1234");
+        assert_eq!(normalize_user_id("a:b:S-1-5-18"), "a:b:S-1-5-18");
+        assert_eq!(normalize_user_id("m:S-"), "m:S-");
+    }
+
+    #[test]
+    fn prefers_local_id_then_id_then_data() {
+        let user_of_payload = |payload| user_of(&event(0, payload));
+
+        assert_eq!(
+            user_of_payload(json!({
+                "ext": { "user": { "localId": "m:S-1-5-21-1", "id": "c:2" } },
+                "data": { "UserSid": "S-1-5-21-3" }
+            })),
+            Some("S-1-5-21-1".to_string())
+        );
+        assert_eq!(
+            user_of_payload(json!({
+                "ext": { "user": { "localId": "", "id": "c:2" } },
+                "data": { "UserSid": "S-1-5-21-3" }
+            })),
+            Some("c:2".to_string())
+        );
+        assert_eq!(
+            user_of_payload(json!({
+                "ext": { "user": {} },
+                "data": { "userSid": "S-1-5-21-4", "UserSID": "S-1-5-21-3" }
+            })),
+            Some("S-1-5-21-3".to_string())
+        );
+        assert_eq!(
+            user_of_payload(json!({ "data": { "UserSid": "", "userSid": "S-1-5-21-4" } })),
+            Some("S-1-5-21-4".to_string())
+        );
+        assert_eq!(
+            user_of_payload(json!({ "data": { "UserSid": 5 } })),
+            None
+        );
+        assert_eq!(user_of_payload(json!({})), None);
+    }
+
+    #[test]
+    fn matches_users_given_on_the_command_line() {
+        assert!(is_same_user("S-1-5-21-1", "S-1-5-21-1"));
+        assert!(is_same_user("S-1-5-21-1", "m:S-1-5-21-1"));
+        assert!(is_same_user("S-1-5-21-1", "s-1-5-21-1"));
+        assert!(is_same_user("c:1234", "C:1234"));
+        assert!(!is_same_user("S-1-5-21-1", "S-1-5-21-10"));
+        assert!(!is_same_user("c:1234", "1234"));
+    }
+
+    #[test]
+    fn summarizes_users() {
+        let battery_event = serde_json::from_value::<DetectedEvent>(json!({
+            "type": "battery_event",
+            "content": { "battery_percentage_change": { "battery_percentage": 50 } }
+        }))
+        .unwrap();
+
+        let first_user = json!({ "ext": { "user": { "localId": "m:S-1-5-21-1" } } });
+        let second_user = json!({ "data": { "UserSid": "S-1-5-21-2" } });
+
+        let mut observer = UserObserver::default();
+        for (seconds, payload, number_of_detected_events) in [
+            (30, first_user.clone(), 2),
+            (10, first_user.clone(), 0),
+            (20, second_user, 1),
+            (40, json!({}), 1),
+            (0, first_user, 1),
+        ] {
+            let event = event(seconds, payload);
+            let detected_events = (0..number_of_detected_events)
+                .map(|_| ProcessedEvent::new_with_random_id(&event, battery_event.clone()))
+                .collect::<Vec<_>>();
+            observer.observe(&event, &detected_events);
+        }
+
+        let report = observer.into_report();
+        assert_eq!(report.number_of_unattributed_events, 1);
+
+        let start = Utc.with_ymd_and_hms(2024, 1, 17, 0, 0, 0).unwrap();
+        let summaries = report
+            .users
+            .iter()
+            .map(|summary| {
+                (
+                    summary.user.as_str(),
+                    summary.number_of_events,
+                    (summary.first_seen - start).num_seconds(),
+                    (summary.last_seen - start).num_seconds(),
+                    summary.detector_hits.get("battery").copied(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            [
+                ("S-1-5-21-1", 3, 0, 30, Some(3)),
+                ("S-1-5-21-2", 1, 20, 20, Some(1)),
+            ]
+        );
+    }
+}